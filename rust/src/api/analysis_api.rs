@@ -0,0 +1,265 @@
+use crate::api::hdr_image_api::ToneMapper;
+use crate::api::screen_shot_api::CaptureResult;
+
+#[derive(Clone, Debug)]
+pub struct LuminancePercentile {
+    pub percentile: f32,
+    pub nits: f32,
+}
+
+/// How much HDR content a capture holds; all light levels are in nits
+///
+/// EXR export writes MaxCLL / MaxFALL into its header. Ultra HDR JPEG
+/// export sizes its gain map from MaxCLL, but neither Ultra HDR XMP nor
+/// ISO 21496-1 has a field for content light levels, so the JPEG carries
+/// neither; callers that need them there take them from here.
+#[derive(Clone, Debug)]
+pub struct HdrContentStats {
+    /// Maximum content light level (brightest pixel component)
+    pub max_cll: f32,
+    /// Maximum frame-average light level (mean of per-pixel brightest component)
+    pub max_fall: f32,
+    pub min_luminance: f32,
+    pub max_luminance: f32,
+    pub average_luminance: f32,
+    /// Share of pixels brighter than SDR reference white (203 nits)
+    pub fraction_above_sdr_white: f32,
+    pub percentiles: Vec<LuminancePercentile>,
+    /// Lower edge in nits of each histogram bin (bins are uniform in PQ)
+    pub histogram_bin_nits: Vec<f32>,
+    pub luminance_histogram: Vec<u32>,
+    pub red_histogram: Vec<u32>,
+    pub green_histogram: Vec<u32>,
+    pub blue_histogram: Vec<u32>,
+}
+
+impl CaptureResult {
+    /// MaxCLL / MaxFALL, luminance histograms and percentiles for this capture
+    ///
+    /// See [`HdrContentStats`] for which exports also carry MaxCLL / MaxFALL.
+    pub fn analyze_hdr_content(&self) -> anyhow::Result<HdrContentStats> {
+        crate::colorist::analysis::analyze_hdr_content(self)
+    }
+
+    /// Read the colour at (x, y), averaged over a (2·radius+1)² window
+    pub fn sample_pixel(&self, x: u32, y: u32, radius: u32) -> anyhow::Result<PixelSample> {
+        crate::colorist::sample::sample_pixel(self, x, y, radius)
+    }
+
+    /// WCAG 2.x and APCA contrast of `foreground` (text) on `background`
+    pub fn measure_contrast(
+        &self,
+        foreground: SampleRegion,
+        background: SampleRegion,
+    ) -> anyhow::Result<ContrastReport> {
+        crate::colorist::contrast::measure_contrast(self, &foreground, &background)
+    }
+
+    /// Up to `count` (at most 32) dominant colours of `region`, most common first
+    pub fn extract_palette(
+        &self,
+        region: SampleRegion,
+        count: u32,
+    ) -> anyhow::Result<Vec<PaletteColor>> {
+        crate::colorist::palette::extract_palette(self, &region, count)
+    }
+
+    /// Render a diagnostic heatmap as RGBA8 at the capture's size
+    pub fn render_visualization(&self, kind: VisualizationKind) -> anyhow::Result<Vec<u8>> {
+        crate::colorist::visualize::render_visualization(self, kind)
+    }
+
+    /// Per-pixel ΔE ITP between this capture ("before") and `other` ("after")
+    pub fn diff(&self, other: &CaptureResult, options: DiffOptions) -> anyhow::Result<CaptureDiff> {
+        crate::colorist::diff::diff(self, other, &options)
+    }
+
+    /// Bounds of the content inside uniform-coloured margins; `tolerance`
+    /// is the largest per-channel difference in linear light (1.0 = SDR
+    /// white) still counted as margin, e.g. 0.01 to absorb dithering
+    pub fn find_trim_region(&self, tolerance: f32) -> anyhow::Result<SampleRegion> {
+        crate::colorist::trim::trim_region(self, tolerance)
+    }
+
+    /// Find UI element boundaries once, for repeated
+    /// [`ElementMap::element_rect_at`] queries while the selection moves
+    pub fn detect_elements(&self) -> anyhow::Result<ElementMap> {
+        crate::colorist::elements::detect(self)
+    }
+}
+
+/// Eyedropper reading at one point of a capture
+#[derive(Clone, Debug)]
+pub struct PixelSample {
+    pub x: u32,
+    pub y: u32,
+    pub radius: u32,
+    /// Pixels averaged (fewer than (2r+1)² at the frame edge)
+    pub sample_count: u32,
+    /// Averaged linear RGBA, sRGB primaries, 1.0 = SDR white
+    pub linear: Vec<f32>,
+    /// Luminance in nits
+    pub nits: f32,
+    /// Brighter than SDR white in at least one channel
+    pub is_hdr: bool,
+    /// `#rrggbb`, HDR colours scaled into SDR range keeping hue
+    pub hex: String,
+    pub css_rgb: String,
+    pub css_display_p3: String,
+    pub css_rec2100_pq: String,
+    pub css_oklch: String,
+}
+
+/// A rectangle of a capture, in pixels; use 1x1 for a single point
+#[derive(Clone, Copy, Debug)]
+pub struct SampleRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Contrast between a foreground (text) and background colour
+///
+/// Colours are measured on the SDR rendition, where WCAG and APCA are defined.
+#[derive(Clone, Debug)]
+pub struct ContrastReport {
+    pub foreground_hex: String,
+    pub background_hex: String,
+    /// WCAG 2.x contrast ratio, 1.0 to 21.0
+    pub wcag_ratio: f32,
+    pub wcag_aa_normal_text: bool,
+    pub wcag_aa_large_text: bool,
+    pub wcag_aaa_normal_text: bool,
+    pub wcag_aaa_large_text: bool,
+    /// WCAG 1.4.11 non-text contrast (UI components, graphics)
+    pub wcag_non_text: bool,
+    /// APCA lightness contrast; negative for light text on dark backgrounds
+    pub apca_lc: f32,
+    /// |Lc| >= 75, minimum for body text
+    pub apca_body_text: bool,
+    /// |Lc| >= 60, minimum for other content text
+    pub apca_content_text: bool,
+    /// |Lc| >= 45, minimum for large text and headlines
+    pub apca_large_text: bool,
+    /// |Lc| >= 30, minimum for non-text elements
+    pub apca_non_text: bool,
+}
+
+/// One dominant colour of a region
+#[derive(Clone, Debug)]
+pub struct PaletteColor {
+    /// Linear RGB, sRGB primaries, 1.0 = SDR white
+    pub linear: Vec<f32>,
+    /// Share of the region's pixels closest to this colour, in percent
+    /// (0.0 to 100.0)
+    pub coverage: f32,
+    pub hex: String,
+    pub css_display_p3: String,
+    pub css_oklch: String,
+}
+
+/// Diagnostic heatmap rendered by [`CaptureResult::render_visualization`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VisualizationKind {
+    /// Luminance in false-colour bands (blue < 100 nits, grey up to SDR
+    /// white, yellow/orange/red/magenta for increasingly bright highlights)
+    FalseColorNits,
+    /// Pixels the SDR base image clips (red) or crushes to black (blue)
+    SdrClipping,
+    /// Colours outside sRGB (yellow) and outside Display P3 (magenta)
+    Gamut,
+}
+
+/// Options for [`CaptureResult::diff`]
+#[derive(Clone, Copy, Debug)]
+pub struct DiffOptions {
+    /// ΔE ITP above which a pixel counts as changed; 1.0 is roughly one
+    /// just-noticeable difference
+    pub threshold: f32,
+    /// Changed pixels closer than this many pixels share one region
+    pub merge_distance: u32,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            merge_distance: 4,
+        }
+    }
+}
+
+/// Result of comparing two captures of equal size
+#[derive(Clone, Debug)]
+pub struct CaptureDiff {
+    pub width: u32,
+    pub height: u32,
+    /// ΔE ITP (ITU-R BT.2124) per pixel, row-major
+    pub delta_e: Vec<f32>,
+    /// 255 where ΔE exceeds the threshold, 0 elsewhere
+    pub mask: Vec<u8>,
+    /// Bounding boxes of changed areas, largest first
+    pub changed_regions: Vec<SampleRegion>,
+    pub changed_pixels: u64,
+    pub changed_fraction: f32,
+    pub max_delta_e: f32,
+    pub mean_delta_e: f32,
+    /// RGBA8: the "after" capture dimmed to grey, changes from yellow
+    /// (at the threshold) to red (ten times the threshold)
+    pub diff_image: Vec<u8>,
+}
+
+/// Regions of a capture that look like UI elements (panels, buttons, fields)
+#[flutter_rust_bridge::frb(opaque)]
+pub struct ElementMap {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    /// Region index per pixel, row-major
+    pub(crate) labels: Vec<u32>,
+    /// Per region: left, top, right, bottom (exclusive)
+    pub(crate) bounds: Vec<[u32; 4]>,
+}
+
+impl ElementMap {
+    /// Rectangle of the element under (x, y) to snap a selection to, or
+    /// `None` when there is no element-sized region around the point
+    #[flutter_rust_bridge::frb(sync)]
+    pub fn element_rect_at(&self, x: u32, y: u32) -> Option<SampleRegion> {
+        crate::colorist::elements::element_rect_at(self, x, y)
+    }
+}
+
+/// Zoomed view of a capture for pixel-accurate selection
+///
+/// Holds its own copy of the capture, handed over once, so each
+/// [`Magnifier::loupe`] call only transfers the loupe's pixels.
+#[flutter_rust_bridge::frb(opaque)]
+pub struct Magnifier {
+    pub(crate) capture: CaptureResult,
+    pub(crate) tone_mapper: ToneMapper,
+    /// Brightest linear channel value in the capture, at least 1.0
+    pub(crate) peak: f32,
+}
+
+impl Magnifier {
+    pub fn new(capture: CaptureResult, tone_mapper: ToneMapper) -> anyhow::Result<Magnifier> {
+        crate::colorist::loupe::magnifier(capture, tone_mapper)
+    }
+
+    /// The `size`x`size` block around (x, y) and the reading at (x, y)
+    #[flutter_rust_bridge::frb(sync)]
+    pub fn loupe(&self, x: u32, y: u32, size: u32) -> anyhow::Result<LoupeView> {
+        crate::colorist::loupe::loupe(self, x, y, size)
+    }
+}
+
+/// Pixels around the cursor for the magnifier
+#[derive(Clone, Debug)]
+pub struct LoupeView {
+    pub size: u32,
+    /// RGBA8, row-major, tone mapped to SDR; transparent outside the frame
+    pub rgba: Vec<u8>,
+    /// The pixel under the cursor, unaveraged
+    pub center: PixelSample,
+}
@@ -0,0 +1,402 @@
+use serde::{Deserialize, Serialize};
+
+use crate::api::processing_api::{Adjustments, ImageTransform, ResizeFilter};
+use crate::api::screen_shot_api::CaptureResult;
+
+/// A point in capture pixels; (0, 0) is the top-left corner of the frame
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EditPoint {
+    pub x: f32,
+    pub y: f32,
+}
+
+/// A rectangle in capture pixels
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EditRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Annotation colour: an sRGB colour plus how bright it should glow
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AnnotationColor {
+    /// sRGB code values, 0.0 to 1.0
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+    /// Brightness of a full-scale channel in nits; 203 is SDR white,
+    /// higher values make the annotation glow on HDR displays
+    pub nits: f32,
+    /// 0.0 (invisible) to 1.0 (opaque)
+    pub opacity: f32,
+}
+
+/// One shape drawn by [`CaptureResult::annotate`]; sizes are in pixels
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Annotation {
+    Rectangle {
+        rect: EditRect,
+        color: AnnotationColor,
+        stroke_width: f32,
+        corner_radius: f32,
+        /// Fill the interior instead of drawing the outline
+        filled: bool,
+    },
+    Arrow {
+        from: EditPoint,
+        to: EditPoint,
+        color: AnnotationColor,
+        stroke_width: f32,
+    },
+    Freehand {
+        points: Vec<EditPoint>,
+        color: AnnotationColor,
+        stroke_width: f32,
+    },
+    /// Translucent marker that tints what is underneath instead of covering it
+    Highlighter {
+        points: Vec<EditPoint>,
+        color: AnnotationColor,
+        width: f32,
+    },
+    /// Numbered circle for step-by-step instructions
+    StepBadge {
+        center: EditPoint,
+        number: u32,
+        radius: f32,
+        color: AnnotationColor,
+    },
+    Text {
+        /// Top-left corner of the first line
+        origin: EditPoint,
+        text: String,
+        /// In pixels, at most 2048
+        font_size: f32,
+        color: AnnotationColor,
+    },
+}
+
+/// How a redacted region is obscured
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RedactionStyle {
+    /// Blur over roughly `radius` pixels; detail finer than half the radius
+    /// (and never finer than 4 pixels) is averaged away before blurring, so
+    /// it cannot be deconvolved back
+    GaussianBlur { radius: f32 },
+    /// Replace each `block_size`² block by its average colour; smaller
+    /// sizes are raised to 4 pixels
+    Pixelate { block_size: u32 },
+    /// Cover with an opaque colour; the colour's opacity is ignored
+    SolidFill { color: AnnotationColor },
+}
+
+/// A region to redact and how
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Redaction {
+    /// Rounded outwards to whole pixels
+    pub rect: EditRect,
+    pub style: RedactionStyle,
+}
+
+/// Outline of a shaped crop, within the crop rectangle
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CropShape {
+    RoundedRect {
+        corner_radius: f32,
+    },
+    /// Ellipse inscribed in the crop rectangle
+    Ellipse,
+    /// Freeform outline in capture pixels (not relative to the crop)
+    Polygon {
+        points: Vec<EditPoint>,
+    },
+}
+
+/// Backdrop behind a beautified capture
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BeautifyBackground {
+    Solid {
+        color: AnnotationColor,
+    },
+    /// Linear-light blend from `start` to `end`; 0° runs left to right,
+    /// 90° top to bottom
+    LinearGradient {
+        start: AnnotationColor,
+        end: AnnotationColor,
+        angle_degrees: f32,
+    },
+}
+
+/// Soft shadow cast by a beautified capture
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DropShadow {
+    pub offset_x: f32,
+    pub offset_y: f32,
+    /// Blur radius in pixels
+    pub blur_radius: f32,
+    pub color: AnnotationColor,
+}
+
+/// Options for [`CaptureResult::beautify`]; sizes are in pixels
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BeautifyOptions {
+    pub padding: u32,
+    pub background: BeautifyBackground,
+    pub corner_radius: f32,
+    pub shadow: Option<DropShadow>,
+}
+
+/// How [`compose_collage`] arranges its items
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CollageLayout {
+    Row,
+    Column,
+    /// Left to right, then top to bottom, `columns` items per row
+    Grid {
+        columns: u32,
+    },
+}
+
+/// One image placed by [`compose_collage`]
+#[derive(Clone)]
+pub struct CollageItem {
+    pub capture: CaptureResult,
+    /// Only this part of the capture is used when set; rounded outwards to
+    /// whole pixels
+    pub region: Option<EditRect>,
+    /// Caption drawn centred below the image
+    pub label: Option<String>,
+    /// Linear value at which this source's SDR white sits. A capture does
+    /// not record it, so the caller must pass it: 1.0 for captures taken or
+    /// decoded by this app, e.g. 3.0 for a raw scRGB buffer (1.0 = 80 nits)
+    /// taken with SDR content at 240 nits. Zero or less is read as 1.0
+    pub sdr_white: f32,
+}
+
+/// Options for [`compose_collage`]; sizes are in pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CollageOptions {
+    pub layout: CollageLayout,
+    /// Gap between items and around the edge
+    pub spacing: u32,
+    pub background: AnnotationColor,
+    /// In pixels, at most 2048; zero or less draws no labels
+    pub label_font_size: f32,
+    pub label_color: AnnotationColor,
+}
+
+impl CaptureResult {
+    /// Place the capture on a padded background with rounded corners and a
+    /// shadow; the capture's HDR highlights are kept as they are
+    pub fn beautify(&self, options: BeautifyOptions) -> anyhow::Result<CaptureResult> {
+        crate::editor::beautify::beautify(self, &options)
+    }
+
+    /// Crop to a rectangle and make everything outside `shape` transparent,
+    /// with anti-aliased edges in the alpha channel
+    ///
+    /// PNG ([`CaptureResult::to_sdr_image`]) and EXR
+    /// ([`CaptureResult::to_exr`]) exports keep the alpha channel; JPEG
+    /// exports flatten it against a background colour.
+    pub fn crop_shape(
+        &self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        shape: CropShape,
+    ) -> anyhow::Result<CaptureResult> {
+        crate::editor::crop::crop_shape(self, (x, y, width, height), &shape)
+    }
+
+    /// Composite over an opaque `background`, removing all transparency
+    pub fn flatten(&self, background: AnnotationColor) -> anyhow::Result<CaptureResult> {
+        crate::editor::crop::flatten(self, background.linear())
+    }
+
+    /// Draw `annotations` in order onto a copy of the capture, in linear light
+    pub fn annotate(&self, annotations: Vec<Annotation>) -> anyhow::Result<CaptureResult> {
+        crate::editor::annotate::annotate(self, &annotations)
+    }
+
+    /// Return a copy with the regions destroyed; exports derive both the SDR
+    /// base and the gain map from the result, so nothing of the original
+    /// region survives in either
+    pub fn redact(&self, redactions: Vec<Redaction>) -> anyhow::Result<CaptureResult> {
+        crate::editor::redact::redact(self, &redactions)
+    }
+}
+
+/// Lay several captures out in one image, each centred in its cell, with
+/// every source scaled so SDR white matches; the result is FP16 unless all
+/// sources are SDR
+pub fn compose_collage(
+    items: Vec<CollageItem>,
+    options: CollageOptions,
+) -> anyhow::Result<CaptureResult> {
+    crate::editor::collage::compose(&items, &options)
+}
+
+/// Merge overlapping captures of the same region, taken while scrolling
+/// down, into one tall image; rows that stay put in every frame, like a
+/// sticky header or footer, appear once. Frames must share size and mode
+pub fn stitch_scrolling_capture(frames: Vec<CaptureResult>) -> anyhow::Result<CaptureResult> {
+    crate::editor::stitch::stitch(&frames)
+}
+
+/// One step of an [`EditDocument`], applied to the result of the steps before
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum EditOperation {
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    Transform {
+        transform: ImageTransform,
+    },
+    Resize {
+        width: u32,
+        height: u32,
+        filter: ResizeFilter,
+    },
+    Adjust {
+        adjustments: Adjustments,
+    },
+    Annotate {
+        annotations: Vec<Annotation>,
+    },
+    Redact {
+        redactions: Vec<Redaction>,
+    },
+    CropShape {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        shape: CropShape,
+    },
+    Beautify {
+        options: BeautifyOptions,
+    },
+}
+
+/// A capture plus the edits made to it, with undo/redo
+///
+/// The original pixels are never modified; [`EditDocument::render`] replays
+/// the operations on demand, so any step can be changed or removed later.
+#[flutter_rust_bridge::frb(opaque)]
+pub struct EditDocument {
+    pub(crate) original: CaptureResult,
+    /// Every state of the operation list, oldest first
+    pub(crate) history: Vec<Vec<EditOperation>>,
+    /// Index of the current state in `history`
+    pub(crate) cursor: usize,
+}
+
+impl EditDocument {
+    pub fn new(original: CaptureResult) -> EditDocument {
+        EditDocument {
+            original,
+            history: vec![Vec::new()],
+            cursor: 0,
+        }
+    }
+
+    pub fn original(&self) -> CaptureResult {
+        self.original.clone()
+    }
+
+    pub fn operations(&self) -> Vec<EditOperation> {
+        self.history[self.cursor].clone()
+    }
+
+    pub fn push_operation(&mut self, operation: EditOperation) {
+        let mut operations = self.operations();
+        operations.push(operation);
+        crate::editor::document::commit(self, operations);
+    }
+
+    /// Change the operation at `index`, e.g. to move an arrow
+    pub fn replace_operation(
+        &mut self,
+        index: u32,
+        operation: EditOperation,
+    ) -> anyhow::Result<()> {
+        let mut operations = self.operations();
+        let slot = operations
+            .get_mut(index as usize)
+            .ok_or_else(|| anyhow::anyhow!("No operation at index {index}"))?;
+        *slot = operation;
+        crate::editor::document::commit(self, operations);
+        Ok(())
+    }
+
+    pub fn remove_operation(&mut self, index: u32) -> anyhow::Result<()> {
+        let mut operations = self.operations();
+        if index as usize >= operations.len() {
+            anyhow::bail!("No operation at index {index}");
+        }
+        operations.remove(index as usize);
+        crate::editor::document::commit(self, operations);
+        Ok(())
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.cursor > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.cursor + 1 < self.history.len()
+    }
+
+    /// Step back one edit; returns false when there is nothing to undo
+    pub fn undo(&mut self) -> bool {
+        let possible = self.can_undo();
+        if possible {
+            self.cursor -= 1;
+        }
+        possible
+    }
+
+    /// Re-apply an undone edit; returns false when there is nothing to redo
+    pub fn redo(&mut self) -> bool {
+        let possible = self.can_redo();
+        if possible {
+            self.cursor += 1;
+        }
+        possible
+    }
+
+    /// Apply the current operations to the original capture
+    pub fn render(&self) -> anyhow::Result<CaptureResult> {
+        crate::editor::document::render(&self.original, &self.history[self.cursor])
+    }
+
+    /// Serialize the original capture and current operations as a project
+    /// file; undo history is not saved
+    ///
+    /// Redactions are baked in: the file stores the image as rendered up to
+    /// the last redaction, so the redacted pixels are never written out.
+    pub fn to_project_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        crate::editor::document::to_project_bytes(self)
+    }
+
+    pub fn from_project_bytes(bytes: Vec<u8>) -> anyhow::Result<EditDocument> {
+        crate::editor::document::from_project_bytes(&bytes)
+    }
+
+    pub fn save_project(&self, path: String) -> anyhow::Result<()> {
+        std::fs::write(&path, self.to_project_bytes()?)
+            .map_err(|e| anyhow::anyhow!("Failed to write project {path}: {e}"))
+    }
+
+    pub fn open_project(path: String) -> anyhow::Result<EditDocument> {
+        let bytes = std::fs::read(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to read project {path}: {e}"))?;
+        Self::from_project_bytes(bytes)
+    }
+}
@@ -0,0 +1,128 @@
+use crate::api::screen_shot_api::CaptureResult;
+
+/// Load an Ultra HDR JPEG (e.g. one of our own exports) back into a capture
+pub fn decode_ultra_hdr_jpeg(bytes: Vec<u8>) -> anyhow::Result<CaptureResult> {
+    crate::colorist::decode::ultra_hdr_jpeg_to_capture(&bytes)
+}
+
+/// Gain map parameters as stored in one metadata flavour (XMP or ISO 21496-1)
+///
+/// Boosts and capacities are linear factors; per-channel fields hold three
+/// values even for single-channel maps.
+#[derive(Clone, Debug)]
+pub struct GainMapInfo {
+    pub min_content_boost: Vec<f32>,
+    pub max_content_boost: Vec<f32>,
+    pub gamma: Vec<f32>,
+    pub offset_sdr: Vec<f32>,
+    pub offset_hdr: Vec<f32>,
+    pub hdr_capacity_min: f32,
+    pub hdr_capacity_max: f32,
+    pub base_rendition_is_hdr: bool,
+    pub use_base_color_space: bool,
+    pub multi_channel: bool,
+}
+
+/// One JPEG image inside the file (0 = primary, 1 = gain map)
+#[derive(Clone, Debug)]
+pub struct ContainerImageInfo {
+    pub index: u32,
+    /// MPF entry values, if the file has an MPF index
+    pub mpf_attribute: Option<u32>,
+    pub mpf_offset: Option<u32>,
+    pub mpf_size: Option<u32>,
+    pub width: u32,
+    pub height: u32,
+    pub components: u8,
+    pub has_exif: bool,
+    pub has_xmp: bool,
+    pub has_iso_21496: bool,
+    pub has_icc: bool,
+    pub icc_description: Option<String>,
+    /// Gamut / transfer signalled by the ICC profile (CICP tag or description)
+    pub color_gamut: Option<String>,
+    pub color_transfer: Option<String>,
+}
+
+/// Container structure and gain-map metadata of an Ultra HDR / ISO gain-map JPEG
+#[derive(Clone, Debug)]
+pub struct HdrImageInfo {
+    /// The primary image, then the images indexed by MPF
+    pub images: Vec<ContainerImageInfo>,
+    pub has_mpf: bool,
+    /// Why the MPF index could not be followed (entries past the end of the
+    /// file, a truncated secondary image); only the primary image is then
+    /// reported
+    pub container_error: Option<String>,
+    /// `hdrgm:Version` from the primary image's XMP
+    pub ultra_hdr_version: Option<String>,
+    pub xmp_gain_map: Option<GainMapInfo>,
+    pub iso_gain_map: Option<GainMapInfo>,
+    /// Why the ISO 21496-1 segment could not be parsed, if it is present
+    /// but malformed; the rest of the report is still filled in
+    pub iso_gain_map_error: Option<String>,
+    pub has_apple_gain_map: bool,
+    pub primary_xmp: Option<String>,
+    pub gain_map_xmp: Option<String>,
+}
+
+/// Report what a gain-map JPEG actually contains
+pub fn inspect_hdr_image(bytes: Vec<u8>) -> anyhow::Result<HdrImageInfo> {
+    crate::colorist::inspect::inspect_hdr_jpeg(&bytes)
+}
+
+/// A gain-map image as displayed at a given headroom
+#[derive(Clone, Debug)]
+pub struct HeadroomRendering {
+    pub width: u32,
+    pub height: u32,
+    /// Display headroom the image was rendered for (1.0 = SDR-only display)
+    pub headroom: f32,
+    /// Fraction of the gain map that was applied, 0.0 (base) to 1.0 (alternate)
+    pub gain_map_weight: f32,
+    /// RGBA8 as sent to the display: sRGB-encoded relative to its peak, so
+    /// 255 is `headroom` times SDR white (plain SDR at headroom 1.0)
+    pub rgba: Vec<u8>,
+}
+
+/// Preview an Ultra HDR / ISO gain-map JPEG on a display with `headroom`
+/// (e.g. 1.0 for SDR, 2.0 or 4.0 for HDR screens); it must be finite
+pub fn render_hdr_image_at_headroom(
+    bytes: Vec<u8>,
+    headroom: f32,
+) -> anyhow::Result<HeadroomRendering> {
+    crate::colorist::render::render_at_headroom(&bytes, headroom)
+}
+
+/// Operator used to squeeze HDR highlights into SDR range
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMapper {
+    /// Hard clip at SDR white
+    Clip,
+    /// Extended Reinhard on luminance, mapping the content peak to white
+    Reinhard,
+    /// Narkowicz ACES filmic curve
+    AcesFilmic,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdrImageFormat {
+    Jpeg,
+    Png,
+}
+
+/// Options for converting an Ultra HDR JPEG into a plain SDR image
+#[derive(Clone, Debug)]
+pub struct StripHdrOptions {
+    pub format: SdrImageFormat,
+    /// `None` keeps the SDR base image as is (lossless for JPEG output);
+    /// otherwise the HDR rendition is re-rendered with this tone mapper
+    pub tone_mapper: Option<ToneMapper>,
+    /// Quality for re-encoded JPEG output (1-100)
+    pub jpeg_quality: u8,
+}
+
+/// Convert an Ultra HDR JPEG into an SDR JPEG/PNG without MPF or gain-map metadata
+pub fn strip_hdr(bytes: Vec<u8>, options: StripHdrOptions) -> anyhow::Result<Vec<u8>> {
+    crate::colorist::strip::strip_hdr(&bytes, &options)
+}
@@ -2,5 +2,9 @@
 // Do not put code in `mod.rs`, but put in e.g. `simple.rs`.
 //
 
+pub mod analysis_api;
+pub mod edit_api;
+pub mod hdr_image_api;
+pub mod processing_api;
 pub mod screen_shot_api;
 pub mod simple;
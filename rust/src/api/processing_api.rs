@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+
+use crate::api::screen_shot_api::CaptureResult;
+
+/// Colour vision deficiency to simulate; severity 1.0 is the full dichromacy
+/// (protanopia / deuteranopia / tritanopia)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorVisionDeficiency {
+    Protanomaly,
+    Deuteranomaly,
+    Tritanomaly,
+}
+
+/// Linear-light adjustments; the default leaves the capture unchanged
+///
+/// Every field must be finite. FP16 results too bright to store saturate at
+/// the format's largest value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Adjustments {
+    /// Exposure change in stops
+    pub exposure: f32,
+    /// Contrast around middle grey, -1.0 (flat) to 1.0; 0.0 is neutral
+    pub contrast: f32,
+    /// Compress highlights above SDR white, 0.0 (off) to 1.0 (clip to white)
+    pub highlight_compression: f32,
+    /// -1.0 (greyscale) to 1.0 (double saturation); 0.0 is neutral
+    pub saturation: f32,
+    /// -1.0 (cooler) to 1.0 (warmer)
+    pub temperature: f32,
+    /// -1.0 (greener) to 1.0 (more magenta)
+    pub tint: f32,
+}
+
+/// Resampling filter for [`CaptureResult::resize`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResizeFilter {
+    /// Pixel art and 2x/3x exact downscales that must stay crisp
+    Nearest,
+    /// Area average
+    Box,
+    /// Mitchell–Netravali cubic (B = C = 1/3): soft, no visible ringing
+    Mitchell,
+    /// Sharpest, with slight ringing at hard edges
+    #[default]
+    Lanczos3,
+}
+
+/// Lossless orientation change; rotations are clockwise
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImageTransform {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipHorizontal,
+    FlipVertical,
+}
+
+impl CaptureResult {
+    /// Render the capture as seen with a colour vision deficiency
+    ///
+    /// `severity` ranges from 0.0 (typical vision) to 1.0 (dichromacy);
+    /// values outside are clamped, non-finite ones rejected.
+    pub fn simulate_color_vision(
+        &self,
+        deficiency: ColorVisionDeficiency,
+        severity: f32,
+    ) -> anyhow::Result<CaptureResult> {
+        crate::colorist::cvd::simulate(self, deficiency, severity)
+    }
+
+    /// Return a copy with `adjustments` applied to the linear pixel data
+    pub fn adjust(&self, adjustments: Adjustments) -> anyhow::Result<CaptureResult> {
+        crate::colorist::adjust::adjust(self, &adjustments)
+    }
+
+    /// Resample to `width`x`height` in linear light
+    pub fn resize(
+        &self,
+        width: u32,
+        height: u32,
+        filter: ResizeFilter,
+    ) -> anyhow::Result<CaptureResult> {
+        crate::colorist::resample::resize(self, width, height, filter)
+    }
+
+    /// Downscale a HiDPI capture to 1x logical pixels, given the display's
+    /// scale factor (e.g. 2.0 on Retina, 1.5 at 150% on Windows)
+    pub fn scale_to_logical(
+        &self,
+        scale_factor: f32,
+        filter: ResizeFilter,
+    ) -> anyhow::Result<CaptureResult> {
+        if !scale_factor.is_finite() || scale_factor <= 0.0 {
+            anyhow::bail!("Invalid display scale factor {scale_factor}");
+        }
+        let logical = |v: u32| ((v as f32 / scale_factor).round() as u32).max(1);
+        self.resize(
+            logical(self.frame_width),
+            logical(self.frame_height),
+            filter,
+        )
+    }
+
+    /// Rotate or mirror the raw buffer without touching pixel values
+    pub fn transform(&self, transform: ImageTransform) -> anyhow::Result<CaptureResult> {
+        crate::colorist::transform::transform(self, transform)
+    }
+
+    /// Crop to [`CaptureResult::find_trim_region`] in one step
+    pub fn auto_trim(&self, tolerance: f32) -> anyhow::Result<CaptureResult> {
+        let region = self.find_trim_region(tolerance)?;
+        self.crop(region.x, region.y, region.width, region.height)
+    }
+}
@@ -1,5 +1,33 @@
 use crate::frb_generated::StreamSink;
 
+/// Which gain-map metadata to write into Ultra HDR JPEG output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GainMapMetadataFormat {
+    /// ISO 21496-1 only (iOS 18 / macOS 15, newer browsers)
+    Iso,
+    /// Ultra HDR `hdrgm:` XMP only (Android, older Chrome)
+    UltraHdrXmp,
+    /// Both ISO 21496-1 and Ultra HDR XMP
+    #[default]
+    Both,
+}
+
+/// Options for Ultra HDR JPEG export
+#[derive(Clone, Debug, Default)]
+pub struct UltraHdrOptions {
+    pub metadata_format: GainMapMetadataFormat,
+    /// Also tag the gain map with Apple's `HDRGainMap` XMP
+    pub apple_gain_map: bool,
+    /// Adjust the linear pixels before the SDR base and gain map are derived
+    pub adjustments: Option<crate::api::processing_api::Adjustments>,
+    /// Tag the image with an EXIF orientation instead of moving pixels;
+    /// viewers apply the transform when displaying
+    pub orientation: Option<crate::api::processing_api::ImageTransform>,
+    /// JPEG has no alpha: transparent pixels are flattened against this
+    /// colour, SDR white if unset
+    pub background: Option<crate::api::edit_api::AnnotationColor>,
+}
+
 #[derive(Clone)]
 pub struct CaptureResult {
     pub mode: String,
@@ -10,17 +38,71 @@ pub struct CaptureResult {
 
 impl CaptureResult {
     pub fn to_ultra_hdr_jpeg(&self) -> anyhow::Result<Vec<u8>> {
+        self.to_ultra_hdr_jpeg_with_options(UltraHdrOptions::default())
+    }
+
+    /// Encode as Ultra HDR JPEG, choosing which gain-map metadata to embed
+    ///
+    /// The gain map and HDR capacity are sized from the capture's MaxCLL.
+    /// Neither gain-map metadata flavour can carry MaxCLL / MaxFALL as
+    /// static metadata; use [`CaptureResult::analyze_hdr_content`] or
+    /// [`CaptureResult::to_exr`] for those.
+    pub fn to_ultra_hdr_jpeg_with_options(
+        &self,
+        options: UltraHdrOptions,
+    ) -> anyhow::Result<Vec<u8>> {
+        if let Some(adjustments) = &options.adjustments {
+            let adjusted = crate::colorist::adjust::adjust(self, adjustments)?;
+            let options = UltraHdrOptions {
+                adjustments: None,
+                ..options
+            };
+            return adjusted.to_ultra_hdr_jpeg_with_options(options);
+        }
+        if crate::colorist::pixels::has_transparency(self) {
+            let background = options.background.map_or([1.0; 3], |c| c.linear());
+            let flattened = crate::editor::crop::flatten(self, background)?;
+            return flattened.to_ultra_hdr_jpeg_with_options(options);
+        }
         crate::colorist::raw_buffer_to_ultra_hdr_jpeg(
             self.raw_data.clone(),
             self.frame_width,
             self.frame_height,
             &self.mode,
+            &options,
         )
     }
 
+    /// Encode as a plain SDR JPEG or PNG; PNG keeps the alpha channel, JPEG
+    /// flattens transparency against `background` (SDR white if unset)
+    pub fn to_sdr_image(
+        &self,
+        options: crate::api::hdr_image_api::StripHdrOptions,
+        background: Option<crate::api::edit_api::AnnotationColor>,
+    ) -> anyhow::Result<Vec<u8>> {
+        crate::colorist::strip::capture_to_sdr(self, &options, background.map(|c| c.linear()))
+    }
+
+    /// Encode as a linear-light OpenEXR image; HDR values and the alpha
+    /// channel (e.g. from [`CaptureResult::crop_shape`]) are both kept, and
+    /// the header carries SDR white, MaxCLL and MaxFALL in nits
+    pub fn to_exr(&self) -> anyhow::Result<Vec<u8>> {
+        crate::colorist::exr::capture_to_exr(self)
+    }
+
+    /// Preview the Ultra HDR export of this capture on a display with `headroom`
+    pub fn render_at_headroom(
+        &self,
+        options: UltraHdrOptions,
+        headroom: f32,
+    ) -> anyhow::Result<crate::api::hdr_image_api::HeadroomRendering> {
+        let jpeg = self.to_ultra_hdr_jpeg_with_options(options)?;
+        crate::colorist::render::render_at_headroom(&jpeg, headroom)
+    }
+
     /// Crop the capture result to specific region
     pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> anyhow::Result<CaptureResult> {
-        let bpp = 8; // Both Windows (Rgba16F) and macOS (RGhA) are 64-bit (8 bytes) per pixel currently
+        let bpp = crate::colorist::pixels::bytes_per_pixel(&self.mode) as u32;
 
         // Basic bounds check
         if x + width > self.frame_width || y + height > self.frame_height {
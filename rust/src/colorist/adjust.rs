@@ -0,0 +1,227 @@
+//! Photographic adjustments on linear-light RGB (sRGB primaries, 1.0 = SDR white)
+//!
+//! Working on the linear data before encoding keeps the SDR base and the
+//! gain map consistent, since both are derived from the adjusted pixels.
+
+use anyhow::bail;
+
+use super::pixels;
+use super::tonemap;
+use crate::api::processing_api::Adjustments;
+use crate::api::screen_shot_api::CaptureResult;
+
+/// Middle grey, the pivot for contrast
+const MIDDLE_GREY: f32 = 0.18;
+/// Highlights above SDR white are the ones compressed
+const HIGHLIGHT_KNEE: f32 = 1.0;
+/// Stops of red/blue (temperature) or green (tint) shift at ±1.0
+const WHITE_BALANCE_STOPS: f32 = 0.5;
+
+/// Channel gains for temperature and tint, normalised so a neutral grey
+/// keeps its luminance
+fn white_balance_gains(temperature: f32, tint: f32) -> [f32; 3] {
+    let shift = |stops: f32| (stops * WHITE_BALANCE_STOPS).exp2();
+    let gains = [shift(temperature), shift(-tint), shift(-temperature)];
+    let norm = tonemap::luminance(gains);
+    gains.map(|g| g / norm)
+}
+
+/// Scale RGB so its luminance becomes `target`, keeping hue and saturation
+fn with_luminance(rgb: [f32; 3], luminance: f32, target: f32) -> [f32; 3] {
+    if luminance <= 0.0 {
+        return rgb;
+    }
+    rgb.map(|v| v * target / luminance)
+}
+
+fn adjust_pixel(
+    rgb: [f32; 3],
+    adjustments: &Adjustments,
+    gains: [f32; 3],
+    exposure: f32,
+) -> [f32; 3] {
+    let mut rgb = [0, 1, 2].map(|c| rgb[c].max(0.0) * gains[c] * exposure);
+
+    let l = tonemap::luminance(rgb);
+    if adjustments.contrast != 0.0 && l > 0.0 {
+        let gamma = (1.0 + adjustments.contrast).max(0.0);
+        rgb = with_luminance(rgb, l, MIDDLE_GREY * (l / MIDDLE_GREY).powf(gamma));
+    }
+
+    let l = tonemap::luminance(rgb);
+    if adjustments.highlight_compression > 0.0 && l > HIGHLIGHT_KNEE {
+        // Shrink the highlights' range in stops; 1.0 flattens them to the knee
+        let strength = adjustments.highlight_compression.min(1.0);
+        let target = HIGHLIGHT_KNEE * (l / HIGHLIGHT_KNEE).powf(1.0 - strength);
+        rgb = with_luminance(rgb, l, target);
+    }
+
+    if adjustments.saturation != 0.0 {
+        let l = tonemap::luminance(rgb);
+        let factor = (1.0 + adjustments.saturation).max(0.0);
+        rgb = rgb.map(|v| (l + (v - l) * factor).max(0.0));
+    }
+    rgb
+}
+
+/// Apply `adjustments` in order: white balance, exposure, contrast,
+/// highlight compression, saturation
+///
+/// A non-finite field (or an exposure too large for `f32`) would turn every
+/// pixel into NaN, so it is an error.
+pub fn apply_linear(linear: &mut [f32], adjustments: &Adjustments) -> anyhow::Result<()> {
+    for (name, value) in [
+        ("exposure", adjustments.exposure),
+        ("contrast", adjustments.contrast),
+        ("highlight compression", adjustments.highlight_compression),
+        ("saturation", adjustments.saturation),
+        ("temperature", adjustments.temperature),
+        ("tint", adjustments.tint),
+    ] {
+        if !value.is_finite() {
+            bail!("Adjustment {name} must be finite, got {value}");
+        }
+    }
+    let exposure = adjustments.exposure.exp2();
+    if !exposure.is_finite() || exposure == 0.0 {
+        bail!("Exposure of {} stops is out of range", adjustments.exposure);
+    }
+
+    let gains = white_balance_gains(adjustments.temperature, adjustments.tint);
+    for pixel in linear.chunks_exact_mut(4) {
+        let rgb = adjust_pixel([pixel[0], pixel[1], pixel[2]], adjustments, gains, exposure);
+        pixel[..3].copy_from_slice(&rgb);
+    }
+    Ok(())
+}
+
+pub fn adjust(capture: &CaptureResult, adjustments: &Adjustments) -> anyhow::Result<CaptureResult> {
+    let mut linear = pixels::decode_linear(capture)?;
+    apply_linear(&mut linear, adjustments)?;
+    Ok(pixels::capture_from_linear(
+        &capture.mode,
+        capture.frame_width,
+        capture.frame_height,
+        linear,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adjusted(rgb: [f32; 3], adjustments: Adjustments) -> [f32; 3] {
+        let mut linear = [rgb[0], rgb[1], rgb[2], 1.0];
+        apply_linear(&mut linear, &adjustments).unwrap();
+        assert_eq!(linear[3], 1.0, "alpha is never adjusted");
+        [linear[0], linear[1], linear[2]]
+    }
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-4, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn default_leaves_pixels_unchanged() {
+        for rgb in [[0.0; 3], [0.2, 0.5, 0.9], [4.0, 2.0, 1.0]] {
+            assert_close(adjusted(rgb, Adjustments::default()), rgb);
+        }
+    }
+
+    #[test]
+    fn exposure_is_in_stops() {
+        let adjustments = Adjustments {
+            exposure: 1.0,
+            ..Default::default()
+        };
+        assert_close(adjusted([0.2, 0.5, 0.9], adjustments), [0.4, 1.0, 1.8]);
+    }
+
+    #[test]
+    fn contrast_pivots_on_middle_grey() {
+        let adjustments = Adjustments {
+            contrast: 0.5,
+            ..Default::default()
+        };
+        assert_close(adjusted([MIDDLE_GREY; 3], adjustments), [MIDDLE_GREY; 3]);
+        assert!(adjusted([0.5; 3], adjustments)[0] > 0.5);
+        assert!(adjusted([0.05; 3], adjustments)[0] < 0.05);
+    }
+
+    #[test]
+    fn full_highlight_compression_flattens_hdr_to_the_knee() {
+        let adjustments = Adjustments {
+            highlight_compression: 1.0,
+            ..Default::default()
+        };
+        assert_close(adjusted([0.5; 3], adjustments), [0.5; 3]);
+        let hdr = adjusted([4.0, 2.0, 1.0], adjustments);
+        assert!((tonemap::luminance(hdr) - HIGHLIGHT_KNEE).abs() < 1e-4);
+        assert!((hdr[0] / hdr[2] - 4.0).abs() < 1e-4, "hue is kept");
+    }
+
+    #[test]
+    fn desaturation_keeps_luminance() {
+        let adjustments = Adjustments {
+            saturation: -1.0,
+            ..Default::default()
+        };
+        let rgb = [0.8, 0.3, 0.1];
+        let l = tonemap::luminance(rgb);
+        assert_close(adjusted(rgb, adjustments), [l; 3]);
+    }
+
+    #[test]
+    fn warmer_white_balance_keeps_grey_luminance() {
+        let adjustments = Adjustments {
+            temperature: 1.0,
+            ..Default::default()
+        };
+        let warm = adjusted([0.5; 3], adjustments);
+        assert!(warm[0] > warm[1] && warm[1] > warm[2]);
+        assert!((tonemap::luminance(warm) - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn non_finite_adjustments_are_rejected() {
+        for adjustments in [
+            Adjustments {
+                exposure: f32::NAN,
+                ..Default::default()
+            },
+            Adjustments {
+                contrast: f32::INFINITY,
+                ..Default::default()
+            },
+            Adjustments {
+                temperature: f32::NEG_INFINITY,
+                ..Default::default()
+            },
+            Adjustments {
+                exposure: 1000.0,
+                ..Default::default()
+            },
+        ] {
+            let mut linear = [0.5, 0.5, 0.5, 1.0];
+            assert!(
+                apply_linear(&mut linear, &adjustments).is_err(),
+                "{adjustments:?}"
+            );
+            assert_eq!(linear, [0.5, 0.5, 0.5, 1.0]);
+        }
+    }
+
+    #[test]
+    fn extreme_exposure_saturates_instead_of_overflowing() {
+        let capture = pixels::capture_from_linear("hdr_macos", 1, 1, vec![0.5, 0.5, 0.5, 1.0]);
+        let adjustments = Adjustments {
+            exposure: 40.0,
+            ..Default::default()
+        };
+        let signal = pixels::decode_signal(&adjust(&capture, &adjustments).unwrap()).unwrap();
+        assert!(signal.iter().all(|v| v.is_finite()), "{signal:?}");
+        assert_eq!(signal[0], half::f16::MAX.to_f32());
+    }
+}
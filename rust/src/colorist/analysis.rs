@@ -0,0 +1,185 @@
+//! HDR content statistics: how much real HDR a capture contains
+
+use super::pixels::{self, SDR_WHITE_NITS};
+use super::tonemap;
+use crate::api::analysis_api::{HdrContentStats, LuminancePercentile};
+use crate::api::screen_shot_api::CaptureResult;
+
+/// Number of histogram bins, spaced uniformly in PQ so they are perceptual
+pub const HISTOGRAM_BINS: usize = 256;
+
+const PQ_MAX_NITS: f32 = 10000.0;
+
+/// Pixels within one FP16 step of SDR white still count as SDR
+const SDR_WHITE_TOLERANCE: f32 = 1.0 + 1.0 / 1024.0;
+
+/// Percentiles reported by [`analyze_hdr_content`]
+const PERCENTILES: [f32; 8] = [1.0, 5.0, 25.0, 50.0, 75.0, 95.0, 99.0, 99.9];
+
+/// SMPTE ST 2084 inverse EOTF: absolute nits to a [0, 1] code value
+pub fn nits_to_pq(nits: f32) -> f32 {
+    super::linear_to_pq((nits / PQ_MAX_NITS).clamp(0.0, 1.0))
+}
+
+/// SMPTE ST 2084 EOTF: [0, 1] code value to absolute nits
+pub fn pq_to_nits(pq: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+    let e = pq.clamp(0.0, 1.0).powf(1.0 / M2);
+    ((e - C1).max(0.0) / (C2 - C3 * e)).powf(1.0 / M1) * PQ_MAX_NITS
+}
+
+fn histogram_bin(nits: f32) -> usize {
+    ((nits_to_pq(nits) * HISTOGRAM_BINS as f32) as usize).min(HISTOGRAM_BINS - 1)
+}
+
+/// CTA-861.3 MaxCLL and MaxFALL in nits of linear RGBA pixels (1.0 = SDR
+/// white): the brightest component over all pixels, and its mean
+pub fn light_levels(linear: &[f32]) -> (f32, f32) {
+    let mut max_cll = 0.0f32;
+    let mut max_rgb_sum = 0.0f64;
+    for pixel in linear.chunks_exact(4) {
+        let max_rgb = pixel[0].max(pixel[1]).max(pixel[2]).max(0.0) * SDR_WHITE_NITS;
+        max_cll = max_cll.max(max_rgb);
+        max_rgb_sum += max_rgb as f64;
+    }
+    let pixel_count = (linear.len() / 4).max(1);
+    (max_cll, (max_rgb_sum / pixel_count as f64) as f32)
+}
+
+/// Compute luminance statistics and CTA-861.3 light levels for a capture
+///
+/// MaxCLL / MaxFALL use the brightest component of each pixel in nits, as
+/// CTA-861.3 specifies; the histograms and percentiles use luminance.
+pub fn analyze_hdr_content(capture: &CaptureResult) -> anyhow::Result<HdrContentStats> {
+    let linear = pixels::decode_linear(capture)?;
+    let pixel_count = linear.len() / 4;
+    if pixel_count == 0 {
+        anyhow::bail!("Cannot analyze an empty capture");
+    }
+
+    let mut luminance = Vec::with_capacity(pixel_count);
+    let mut luminance_histogram = vec![0u32; HISTOGRAM_BINS];
+    let mut rgb_histograms = [
+        vec![0u32; HISTOGRAM_BINS],
+        vec![0u32; HISTOGRAM_BINS],
+        vec![0u32; HISTOGRAM_BINS],
+    ];
+    let (max_cll, max_fall) = light_levels(&linear);
+    let mut luminance_sum = 0.0f64;
+    let mut above_sdr_white = 0usize;
+
+    for pixel in linear.chunks_exact(4) {
+        let rgb = [pixel[0], pixel[1], pixel[2]].map(|v| v.max(0.0) * SDR_WHITE_NITS);
+        let nits = tonemap::luminance(rgb);
+        luminance_sum += nits as f64;
+        if nits > SDR_WHITE_NITS * SDR_WHITE_TOLERANCE {
+            above_sdr_white += 1;
+        }
+        luminance_histogram[histogram_bin(nits)] += 1;
+        for (histogram, value) in rgb_histograms.iter_mut().zip(rgb) {
+            histogram[histogram_bin(value)] += 1;
+        }
+        luminance.push(nits);
+    }
+
+    luminance.sort_unstable_by(f32::total_cmp);
+    let percentiles = PERCENTILES
+        .iter()
+        .map(|&percentile| {
+            let rank = (percentile / 100.0 * (pixel_count - 1) as f32).round() as usize;
+            LuminancePercentile {
+                percentile,
+                nits: luminance[rank],
+            }
+        })
+        .collect();
+
+    let [red_histogram, green_histogram, blue_histogram] = rgb_histograms;
+    Ok(HdrContentStats {
+        max_cll,
+        max_fall,
+        min_luminance: luminance[0],
+        max_luminance: luminance[pixel_count - 1],
+        average_luminance: (luminance_sum / pixel_count as f64) as f32,
+        fraction_above_sdr_white: above_sdr_white as f32 / pixel_count as f32,
+        percentiles,
+        histogram_bin_nits: (0..HISTOGRAM_BINS)
+            .map(|i| pq_to_nits(i as f32 / HISTOGRAM_BINS as f32))
+            .collect(),
+        luminance_histogram,
+        red_histogram,
+        green_histogram,
+        blue_histogram,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(linear: &[[f32; 4]]) -> CaptureResult {
+        pixels::capture_from_linear("hdr_macos", linear.len() as u32, 1, linear.concat())
+    }
+
+    fn assert_nits(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() <= expected * 0.005 + 1e-3,
+            "{actual} != {expected}"
+        );
+    }
+
+    #[test]
+    fn light_levels_use_the_brightest_component() {
+        let stats = analyze_hdr_content(&capture(&[
+            [1.0, 1.0, 1.0, 1.0],
+            [4.0, 0.0, 0.0, 1.0],
+            [2.0, 2.0, 2.0, 1.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]))
+        .unwrap();
+
+        // The red pixel holds MaxCLL although its luminance is below SDR white
+        assert_nits(stats.max_cll, 4.0 * SDR_WHITE_NITS);
+        assert_nits(stats.max_fall, (1.0 + 4.0 + 2.0) * SDR_WHITE_NITS / 4.0);
+        assert_nits(stats.max_luminance, 2.0 * SDR_WHITE_NITS);
+        assert_eq!(stats.min_luminance, 0.0);
+        assert_eq!(stats.fraction_above_sdr_white, 0.25);
+        let median = stats.percentiles.iter().find(|p| p.percentile == 50.0);
+        assert_nits(median.unwrap().nits, SDR_WHITE_NITS);
+    }
+
+    #[test]
+    fn histograms_count_every_pixel() {
+        let stats = analyze_hdr_content(&capture(&[[0.5, 1.0, 8.0, 1.0]; 10])).unwrap();
+        for histogram in [
+            &stats.luminance_histogram,
+            &stats.red_histogram,
+            &stats.green_histogram,
+            &stats.blue_histogram,
+        ] {
+            assert_eq!(histogram.len(), HISTOGRAM_BINS);
+            assert_eq!(histogram.iter().sum::<u32>(), 10);
+        }
+        assert!(stats.histogram_bin_nits.windows(2).all(|w| w[0] < w[1]));
+        // Brighter channels land in higher PQ bins
+        let bin = |h: &[u32]| h.iter().position(|&n| n > 0).unwrap();
+        assert!(bin(&stats.red_histogram) < bin(&stats.green_histogram));
+        assert!(bin(&stats.green_histogram) < bin(&stats.blue_histogram));
+    }
+
+    #[test]
+    fn pq_round_trips_nits() {
+        for nits in [0.1, 1.0, 100.0, SDR_WHITE_NITS, 1000.0, 10000.0] {
+            assert_nits(pq_to_nits(nits_to_pq(nits)), nits);
+        }
+    }
+
+    #[test]
+    fn empty_capture_is_rejected() {
+        assert!(analyze_hdr_content(&capture(&[])).is_err());
+    }
+}
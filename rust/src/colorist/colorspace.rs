@@ -0,0 +1,111 @@
+//! Colour space conversions and CSS colour formatting
+//!
+//! Inputs are linear sRGB with 1.0 = SDR white unless stated otherwise.
+
+use glam::f32::{Mat3, Vec3};
+
+use super::analysis::nits_to_pq;
+use super::pixels::{self, SDR_WHITE_NITS, SRGB_TO_P3};
+use super::SRGB_TO_BT2100;
+
+/// Linear sRGB to OKLab (Ottosson 2020)
+pub fn linear_srgb_to_oklab(rgb: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb;
+    let l = 0.4122215 * r + 0.5363325 * g + 0.05144599 * b;
+    let m = 0.2119035 * r + 0.6806995 * g + 0.107397 * b;
+    let s = 0.08830246 * r + 0.2817188 * g + 0.6299787 * b;
+    let (l, m, s) = (l.cbrt(), m.cbrt(), s.cbrt());
+    [
+        0.2104543 * l + 0.7936178 * m - 0.004072047 * s,
+        1.977998 * l - 2.428592 * m + 0.4505937 * s,
+        0.02590404 * l + 0.7827718 * m - 0.8086758 * s,
+    ]
+}
+
+/// OKLab to linear sRGB
+pub fn oklab_to_linear_srgb(lab: [f32; 3]) -> [f32; 3] {
+    let [l, a, b] = lab;
+    let l_ = l + 0.3963378 * a + 0.2158038 * b;
+    let m_ = l - 0.1055613 * a - 0.06385417 * b;
+    let s_ = l - 0.08948418 * a - 1.291486 * b;
+    let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+    [
+        4.076742 * l - 3.307712 * m + 0.2309699 * s,
+        -1.268438 * l + 2.609757 * m - 0.3413194 * s,
+        -0.004196086 * l - 0.7034186 * m + 1.707615 * s,
+    ]
+}
+
+/// OKLab to OKLCh, hue in degrees [0, 360) and 0 for achromatic colours
+pub fn oklab_to_oklch(lab: [f32; 3]) -> [f32; 3] {
+    let [l, a, b] = lab;
+    let chroma = (a * a + b * b).sqrt();
+    if chroma < 1e-4 {
+        return [l, 0.0, 0.0];
+    }
+    let hue = b.atan2(a).to_degrees().rem_euclid(360.0);
+    [l, chroma, hue]
+}
+
+fn mul(matrix: &[f32; 9], rgb: [f32; 3]) -> [f32; 3] {
+    Mat3::from_cols_array(matrix)
+        .mul_vec3(Vec3::from_array(rgb))
+        .to_array()
+}
+
+/// Sign-preserving sRGB transfer, so extended-range values survive
+fn encode_srgb_extended(v: f32) -> f32 {
+    if v.abs() <= 1.0 {
+        v.signum() * pixels::linear_to_srgb(v.abs())
+    } else {
+        v.signum() * (1.055 * v.abs().powf(1.0 / 2.4) - 0.055)
+    }
+}
+
+/// SDR code values for showing a linear colour as hex / `rgb()`
+///
+/// HDR colours are scaled down to SDR white keeping their hue, rather than
+/// clipped per channel.
+pub fn sdr_signal(linear: [f32; 3]) -> [f32; 3] {
+    let peak = linear[0].max(linear[1]).max(linear[2]);
+    let sdr = if peak > 1.0 {
+        linear.map(|v| v / peak)
+    } else {
+        linear
+    };
+    sdr.map(|v| pixels::linear_to_signal(v.max(0.0)))
+}
+
+/// `#rrggbb` from 8-bit-range values in [0, 1]
+pub fn css_hex(rgb: [f32; 3]) -> String {
+    let [r, g, b] = rgb.map(to_u8);
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// `rgb(r, g, b)` from 8-bit-range values in [0, 1]
+pub fn css_rgb(rgb: [f32; 3]) -> String {
+    let [r, g, b] = rgb.map(to_u8);
+    format!("rgb({r}, {g}, {b})")
+}
+
+fn to_u8(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// CSS `color(display-p3 …)`; HDR values are left above 1.0 as CSS allows
+pub fn css_display_p3(linear: [f32; 3]) -> String {
+    let [r, g, b] = mul(&SRGB_TO_P3, linear).map(encode_srgb_extended);
+    format!("color(display-p3 {r:.4} {g:.4} {b:.4})")
+}
+
+/// CSS `color(rec2100-pq …)`, placing SDR white at 203 nits
+pub fn css_rec2100_pq(linear: [f32; 3]) -> String {
+    let [r, g, b] = mul(&SRGB_TO_BT2100, linear).map(|v| nits_to_pq(v * SDR_WHITE_NITS));
+    format!("color(rec2100-pq {r:.4} {g:.4} {b:.4})")
+}
+
+/// CSS `oklch(L% C H)`
+pub fn css_oklch(linear: [f32; 3]) -> String {
+    let [l, c, h] = oklab_to_oklch(linear_srgb_to_oklab(linear));
+    format!("oklch({:.2}% {c:.4} {h:.2})", l * 100.0)
+}
@@ -0,0 +1,792 @@
+//! JPEG / MPF container handling for gain-map images
+//!
+//! Ultra HDR and ISO 21496-1 files are a primary JPEG followed by a gain map
+//! JPEG, tied together by a Multi-Picture Format (MPF) index in the primary
+//! image. This module splits such a file into its images, lets callers edit
+//! the marker segments of each image, and reassembles it with the MPF offsets
+//! recomputed.
+
+use anyhow::{anyhow, bail};
+
+pub const MARKER_SOI: u8 = 0xD8;
+pub const MARKER_EOI: u8 = 0xD9;
+pub const MARKER_SOS: u8 = 0xDA;
+pub const MARKER_APP1: u8 = 0xE1;
+pub const MARKER_APP2: u8 = 0xE2;
+
+pub const XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+pub const EXIF_NAMESPACE: &[u8] = b"Exif\0\0";
+pub const ISO_21496_NAMESPACE: &[u8] = b"urn:iso:std:iso:ts:21496:-1\0";
+pub const MPF_NAMESPACE: &[u8] = b"MPF\0";
+pub const ICC_NAMESPACE: &[u8] = b"ICC_PROFILE\0";
+
+const EXIF_TAG_ORIENTATION: u16 = 0x0112;
+const MPF_TAG_VERSION: u16 = 0xB000;
+const MPF_TAG_NUMBER_OF_IMAGES: u16 = 0xB001;
+const MPF_TAG_MP_ENTRY: u16 = 0xB002;
+const MPF_ENTRY_SIZE: usize = 16;
+
+/// A single marker segment preceding the scan data
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub marker: u8,
+    /// Payload without the marker and the two length bytes
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    pub fn new(marker: u8, data: Vec<u8>) -> Self {
+        Self { marker, data }
+    }
+
+    /// Whether this is an APPn segment whose payload starts with `namespace`
+    pub fn is_app(&self, marker: u8, namespace: &[u8]) -> bool {
+        self.marker == marker && self.data.starts_with(namespace)
+    }
+
+    /// Payload with the identifying namespace stripped
+    pub fn body(&self, namespace: &[u8]) -> &[u8] {
+        &self.data[namespace.len().min(self.data.len())..]
+    }
+}
+
+/// One JPEG image: its marker segments plus everything from SOS through EOI
+#[derive(Clone, Debug)]
+pub struct JpegImage {
+    pub segments: Vec<Segment>,
+    pub scan: Vec<u8>,
+}
+
+impl JpegImage {
+    /// Parse a JPEG starting at the beginning of `bytes`
+    ///
+    /// Returns the image and the number of bytes it occupies, so callers can
+    /// find data appended after EOI.
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<(Self, usize)> {
+        if bytes.len() < 4 || bytes[0] != 0xFF || bytes[1] != MARKER_SOI {
+            bail!("Not a JPEG stream (missing SOI marker)");
+        }
+
+        let mut segments = Vec::new();
+        let mut pos = 2;
+        loop {
+            // Skip fill bytes between segments
+            while pos < bytes.len() && bytes[pos] == 0xFF && bytes.get(pos + 1) == Some(&0xFF) {
+                pos += 1;
+            }
+            if pos + 4 > bytes.len() || bytes[pos] != 0xFF {
+                bail!("Truncated JPEG: expected marker at offset {pos}");
+            }
+            let marker = bytes[pos + 1];
+            let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+            if length < 2 || pos + 2 + length > bytes.len() {
+                bail!("Invalid segment length {length} for marker 0x{marker:02X}");
+            }
+            if marker == MARKER_SOS {
+                let end = find_scan_end(bytes, pos + 2 + length)?;
+                let image = JpegImage {
+                    segments,
+                    scan: bytes[pos..end].to_vec(),
+                };
+                return Ok((image, end));
+            }
+            segments.push(Segment::new(
+                marker,
+                bytes[pos + 4..pos + 2 + length].to_vec(),
+            ));
+            pos += 2 + length;
+        }
+    }
+
+    pub fn find(&self, marker: u8, namespace: &[u8]) -> Option<&Segment> {
+        self.segments.iter().find(|s| s.is_app(marker, namespace))
+    }
+
+    pub fn find_mut(&mut self, marker: u8, namespace: &[u8]) -> Option<&mut Segment> {
+        self.segments
+            .iter_mut()
+            .find(|s| s.is_app(marker, namespace))
+    }
+
+    /// Remove every segment matching `predicate`
+    pub fn remove_where(&mut self, predicate: impl Fn(&Segment) -> bool) {
+        self.segments.retain(|s| !predicate(s));
+    }
+
+    /// Insert an APPn segment after the existing APP0/APP1 run so that JFIF
+    /// and EXIF stay first, as readers expect.
+    pub fn insert_app(&mut self, segment: Segment) {
+        let index = self
+            .segments
+            .iter()
+            .position(|s| s.marker != 0xE0 && s.marker != MARKER_APP1)
+            .unwrap_or(self.segments.len());
+        self.segments.insert(index, segment);
+    }
+
+    /// Set the Orientation tag in IFD0 of the EXIF segment, keeping every
+    /// other tag; without a readable EXIF segment, one holding only the
+    /// Orientation tag goes right after SOI / JFIF where readers look for it
+    pub fn set_exif_orientation(&mut self, orientation: u16) {
+        if let Some(exif) = self.find_mut(MARKER_APP1, EXIF_NAMESPACE) {
+            let mut tiff = exif.body(EXIF_NAMESPACE).to_vec();
+            if set_tiff_orientation(&mut tiff, orientation).is_some() {
+                exif.data.truncate(EXIF_NAMESPACE.len());
+                exif.data.extend_from_slice(&tiff);
+                return;
+            }
+        }
+
+        self.remove_where(|s| s.is_app(MARKER_APP1, EXIF_NAMESPACE));
+        let mut data = EXIF_NAMESPACE.to_vec();
+        // Little-endian TIFF header, IFD0 at offset 8
+        data.extend_from_slice(b"II*\0");
+        data.extend_from_slice(&8u32.to_le_bytes());
+        // IFD0 with one entry: Orientation, SHORT, count 1, value padded to 4
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&EXIF_TAG_ORIENTATION.to_le_bytes());
+        data.extend_from_slice(&3u16.to_le_bytes());
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&orientation.to_le_bytes());
+        data.extend_from_slice(&[0, 0]);
+        // No next IFD
+        data.extend_from_slice(&0u32.to_le_bytes());
+
+        let index = self
+            .segments
+            .iter()
+            .position(|s| s.marker != 0xE0)
+            .unwrap_or(self.segments.len());
+        self.segments.insert(index, Segment::new(MARKER_APP1, data));
+    }
+
+    /// Serialized size in bytes
+    pub fn encoded_len(&self) -> usize {
+        2 + self
+            .segments
+            .iter()
+            .map(|s| 4 + s.data.len())
+            .sum::<usize>()
+            + self.scan.len()
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(self.encoded_len());
+        out.extend_from_slice(&[0xFF, MARKER_SOI]);
+        for segment in &self.segments {
+            let length = segment.data.len() + 2;
+            if length > u16::MAX as usize {
+                bail!(
+                    "Segment 0x{:02X} too large ({} bytes)",
+                    segment.marker,
+                    segment.data.len()
+                );
+            }
+            out.extend_from_slice(&[0xFF, segment.marker]);
+            out.extend_from_slice(&(length as u16).to_be_bytes());
+            out.extend_from_slice(&segment.data);
+        }
+        out.extend_from_slice(&self.scan);
+        Ok(out)
+    }
+}
+
+/// Find the offset just past EOI, starting inside the entropy-coded data
+fn find_scan_end(bytes: &[u8], mut pos: usize) -> anyhow::Result<usize> {
+    while pos + 1 < bytes.len() {
+        if bytes[pos] != 0xFF {
+            pos += 1;
+            continue;
+        }
+        match bytes[pos + 1] {
+            // Byte stuffing, restart markers and fill bytes are part of the scan
+            0x00 | 0xD0..=0xD7 | 0xFF => pos += 1,
+            MARKER_EOI => return Ok(pos + 2),
+            // Progressive JPEGs carry further tables and scans after the first SOS
+            _ => {
+                if pos + 4 > bytes.len() {
+                    break;
+                }
+                let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+                pos += 2 + length;
+            }
+        }
+    }
+    Err(anyhow!("Truncated JPEG: no EOI marker found"))
+}
+
+/// A parsed entry of the MPF index
+#[derive(Clone, Debug)]
+pub struct MpfEntry {
+    pub attribute: u32,
+    pub size: u32,
+    /// Offset relative to the MPF header of the primary image (0 for the primary)
+    pub offset: u32,
+}
+
+/// Location of the MP entry table inside an MPF segment payload
+#[derive(Clone, Debug)]
+struct MpfLayout {
+    big_endian: bool,
+    /// Offset of the first entry, relative to the TIFF header
+    entries_offset: usize,
+    entries: Vec<MpfEntry>,
+}
+
+fn read_u16(data: &[u8], pos: usize, big_endian: bool) -> Option<u16> {
+    let b: [u8; 2] = data.get(pos..pos + 2)?.try_into().ok()?;
+    Some(if big_endian {
+        u16::from_be_bytes(b)
+    } else {
+        u16::from_le_bytes(b)
+    })
+}
+
+fn read_u32(data: &[u8], pos: usize, big_endian: bool) -> Option<u32> {
+    let b: [u8; 4] = data.get(pos..pos + 4)?.try_into().ok()?;
+    Some(if big_endian {
+        u32::from_be_bytes(b)
+    } else {
+        u32::from_le_bytes(b)
+    })
+}
+
+fn write_u32(data: &mut [u8], pos: usize, value: u32, big_endian: bool) {
+    let b = if big_endian {
+        value.to_be_bytes()
+    } else {
+        value.to_le_bytes()
+    };
+    data[pos..pos + 4].copy_from_slice(&b);
+}
+
+/// Set Orientation in IFD0 of an EXIF TIFF structure; `None` if it is malformed
+///
+/// An existing entry is overwritten in place. Otherwise IFD0 is copied to the
+/// end with the new entry added in tag order, so no other offset moves.
+fn set_tiff_orientation(tiff: &mut Vec<u8>, orientation: u16) -> Option<()> {
+    let big_endian = match tiff.get(0..4)? {
+        [0x4D, 0x4D, 0x00, 0x2A] => true,
+        [0x49, 0x49, 0x2A, 0x00] => false,
+        _ => return None,
+    };
+    let u16_bytes = |v: u16| {
+        if big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        }
+    };
+    let ifd = read_u32(tiff, 4, big_endian)? as usize;
+    let count = read_u16(tiff, ifd, big_endian)? as usize;
+    let entries_end = ifd + 2 + count * 12;
+    let next_ifd = read_u32(tiff, entries_end, big_endian)?;
+
+    // SHORT, count 1, value left-aligned in the 4-byte value field
+    let mut value = [0u8; 10];
+    value[0..2].copy_from_slice(&u16_bytes(3));
+    value[2..6].copy_from_slice(&if big_endian {
+        1u32.to_be_bytes()
+    } else {
+        1u32.to_le_bytes()
+    });
+    value[6..8].copy_from_slice(&u16_bytes(orientation));
+
+    let mut entries: Vec<[u8; 12]> = tiff[ifd + 2..entries_end]
+        .chunks_exact(12)
+        .map(|e| e.try_into().unwrap())
+        .collect();
+    let tags: Vec<u16> = (0..count)
+        .map(|i| read_u16(tiff, ifd + 2 + i * 12, big_endian))
+        .collect::<Option<_>>()?;
+    if let Some(i) = tags.iter().position(|&t| t == EXIF_TAG_ORIENTATION) {
+        let pos = ifd + 2 + i * 12 + 2;
+        tiff[pos..pos + 10].copy_from_slice(&value);
+        return Some(());
+    }
+
+    let mut entry = [0u8; 12];
+    entry[0..2].copy_from_slice(&u16_bytes(EXIF_TAG_ORIENTATION));
+    entry[2..].copy_from_slice(&value);
+    let index = tags
+        .iter()
+        .position(|&t| t > EXIF_TAG_ORIENTATION)
+        .unwrap_or(count);
+    entries.insert(index, entry);
+
+    // IFDs start on a word boundary
+    if tiff.len() % 2 == 1 {
+        tiff.push(0);
+    }
+    let new_ifd = u32::try_from(tiff.len()).ok()?;
+    tiff.extend_from_slice(&u16_bytes(u16::try_from(entries.len()).ok()?));
+    tiff.extend(entries.iter().flatten());
+    tiff.extend_from_slice(&[0; 4]);
+    let end = tiff.len();
+    write_u32(tiff, end - 4, next_ifd, big_endian);
+    write_u32(tiff, 4, new_ifd, big_endian);
+    Some(())
+}
+
+/// Parse the TIFF structure following the "MPF\0" identifier
+fn parse_mpf(tiff: &[u8]) -> anyhow::Result<MpfLayout> {
+    let big_endian = match tiff.get(0..4) {
+        Some([0x4D, 0x4D, 0x00, 0x2A]) => true,
+        Some([0x49, 0x49, 0x2A, 0x00]) => false,
+        _ => bail!("Invalid MPF TIFF header"),
+    };
+    let ifd = read_u32(tiff, 4, big_endian).ok_or_else(|| anyhow!("Truncated MPF header"))?;
+    let ifd = ifd as usize;
+    let count = read_u16(tiff, ifd, big_endian).ok_or_else(|| anyhow!("Truncated MPF IFD"))?;
+
+    let mut number_of_images = None;
+    let mut entries_offset = None;
+    for i in 0..count as usize {
+        let entry = ifd + 2 + i * 12;
+        let tag = read_u16(tiff, entry, big_endian).ok_or_else(|| anyhow!("Truncated MPF IFD"))?;
+        let value =
+            read_u32(tiff, entry + 8, big_endian).ok_or_else(|| anyhow!("Truncated MPF IFD"))?;
+        match tag {
+            MPF_TAG_VERSION => {}
+            MPF_TAG_NUMBER_OF_IMAGES => number_of_images = Some(value as usize),
+            MPF_TAG_MP_ENTRY => entries_offset = Some(value as usize),
+            _ => {}
+        }
+    }
+
+    let number_of_images = number_of_images.ok_or_else(|| anyhow!("MPF has no image count"))?;
+    let entries_offset = entries_offset.ok_or_else(|| anyhow!("MPF has no MP entry table"))?;
+    let entries = (0..number_of_images)
+        .map(|i| {
+            let pos = entries_offset + i * MPF_ENTRY_SIZE;
+            Some(MpfEntry {
+                attribute: read_u32(tiff, pos, big_endian)?,
+                size: read_u32(tiff, pos + 4, big_endian)?,
+                offset: read_u32(tiff, pos + 8, big_endian)?,
+            })
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| anyhow!("Truncated MP entry table"))?;
+
+    Ok(MpfLayout {
+        big_endian,
+        entries_offset,
+        entries,
+    })
+}
+
+/// Rewrite `Item:Length` of the secondary items in the GContainer directory
+/// of the primary image's XMP
+///
+/// Ultra HDR readers locate the gain map from this directory as well as from
+/// MPF, so it has to follow every change in the gain map's size. Items are
+/// listed primary first; `lengths` holds the secondary images in order.
+fn set_container_item_lengths(primary: &mut JpegImage, lengths: &[usize]) {
+    const ITEM: &str = "<Container:Item";
+    const LENGTH: &str = "Item:Length=\"";
+
+    let Some(segment) = primary.find_mut(MARKER_APP1, XMP_NAMESPACE) else {
+        return;
+    };
+    let Ok(xmp) = std::str::from_utf8(segment.body(XMP_NAMESPACE)) else {
+        return;
+    };
+    if !xmp.contains(ITEM) {
+        return;
+    }
+
+    let mut items = xmp.split(ITEM);
+    let mut updated = items.next().unwrap_or_default().to_string();
+    for (index, item) in items.enumerate() {
+        updated.push_str(ITEM);
+        let length = index.checked_sub(1).and_then(|i| lengths.get(i));
+        let element_end = item.find('>').unwrap_or(item.len());
+        let value = item[..element_end].find(LENGTH).and_then(|start| {
+            let start = start + LENGTH.len();
+            Some(start..start + item[start..].find('"')?)
+        });
+        match (length, value) {
+            (Some(length), Some(value)) => {
+                updated.push_str(&item[..value.start]);
+                updated.push_str(&length.to_string());
+                updated.push_str(&item[value.end..]);
+            }
+            _ => updated.push_str(item),
+        }
+    }
+    segment.data = [XMP_NAMESPACE, updated.as_bytes()].concat();
+}
+
+/// A primary JPEG plus the additional images indexed by its MPF segment
+#[derive(Clone, Debug)]
+pub struct MultiPictureJpeg {
+    pub primary: JpegImage,
+    /// Secondary images in MPF order; for Ultra HDR the first one is the gain map
+    pub secondary: Vec<JpegImage>,
+    pub mpf_entries: Vec<MpfEntry>,
+}
+
+impl MultiPictureJpeg {
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        let (primary, primary_len) = JpegImage::parse(bytes)?;
+
+        let Some(mpf_index) = primary
+            .segments
+            .iter()
+            .position(|s| s.is_app(MARKER_APP2, MPF_NAMESPACE))
+        else {
+            return Ok(Self {
+                primary,
+                secondary: Vec::new(),
+                mpf_entries: Vec::new(),
+            });
+        };
+
+        // MPF offsets are relative to the TIFF header, i.e. just after "MPF\0"
+        let mut header_pos = 2;
+        for segment in &primary.segments[..mpf_index] {
+            header_pos += 4 + segment.data.len();
+        }
+        header_pos += 4 + MPF_NAMESPACE.len();
+
+        let layout = parse_mpf(primary.segments[mpf_index].body(MPF_NAMESPACE))?;
+        let mut secondary = Vec::new();
+        for entry in layout.entries.iter().skip(1) {
+            let start = header_pos + entry.offset as usize;
+            let end = start + entry.size as usize;
+            if entry.offset == 0 || start < primary_len || end > bytes.len() {
+                bail!(
+                    "MPF entry points outside the file (offset {}, size {})",
+                    entry.offset,
+                    entry.size
+                );
+            }
+            let (image, _) = JpegImage::parse(&bytes[start..end])?;
+            secondary.push(image);
+        }
+
+        Ok(Self {
+            primary,
+            secondary,
+            mpf_entries: layout.entries,
+        })
+    }
+
+    pub fn gain_map(&self) -> Option<&JpegImage> {
+        self.secondary.first()
+    }
+
+    pub fn gain_map_mut(&mut self) -> Option<&mut JpegImage> {
+        self.secondary.first_mut()
+    }
+
+    /// Serialize, rewriting the MPF sizes and offsets and the GContainer
+    /// item lengths to match the edited images
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let mut primary = self.primary.clone();
+        let Some(mpf_index) = primary
+            .segments
+            .iter()
+            .position(|s| s.is_app(MARKER_APP2, MPF_NAMESPACE))
+        else {
+            return primary.to_bytes();
+        };
+
+        let secondary_bytes = self
+            .secondary
+            .iter()
+            .map(JpegImage::to_bytes)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let lengths: Vec<usize> = secondary_bytes.iter().map(Vec::len).collect();
+        set_container_item_lengths(&mut primary, &lengths);
+
+        let mut header_pos = 2;
+        for segment in &primary.segments[..mpf_index] {
+            header_pos += 4 + segment.data.len();
+        }
+        header_pos += 4 + MPF_NAMESPACE.len();
+        // Only values change from here on, so the primary's length is final
+        let primary_len = primary.encoded_len();
+
+        let mpf = &mut primary.segments[mpf_index].data;
+        let layout = parse_mpf(&mpf[MPF_NAMESPACE.len()..])?;
+        if layout.entries.len() != secondary_bytes.len() + 1 {
+            bail!(
+                "MPF lists {} images but {} are present",
+                layout.entries.len(),
+                secondary_bytes.len() + 1
+            );
+        }
+
+        let mut next_offset = primary_len - header_pos;
+        let tiff = &mut mpf[MPF_NAMESPACE.len()..];
+        let first = layout.entries_offset;
+        write_u32(tiff, first + 4, primary_len as u32, layout.big_endian);
+        write_u32(tiff, first + 8, 0, layout.big_endian);
+        for (i, bytes) in secondary_bytes.iter().enumerate() {
+            let pos = first + (i + 1) * MPF_ENTRY_SIZE;
+            write_u32(tiff, pos + 4, bytes.len() as u32, layout.big_endian);
+            write_u32(tiff, pos + 8, next_offset as u32, layout.big_endian);
+            next_offset += bytes.len();
+        }
+
+        let mut out = primary.to_bytes()?;
+        for bytes in secondary_bytes {
+            out.extend_from_slice(&bytes);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use image::codecs::jpeg::JpegEncoder;
+    use image::ColorType;
+
+    use super::*;
+
+    /// A baseline 8x8 JPEG of one flat grey level
+    pub(crate) fn flat_jpeg(level: u8) -> JpegImage {
+        let mut bytes = Vec::new();
+        JpegEncoder::new(&mut bytes)
+            .encode(&[level; 8 * 8 * 3], 8, 8, ColorType::Rgb8)
+            .unwrap();
+        JpegImage::parse(&bytes).unwrap().0
+    }
+
+    /// Big-endian MPF index for the primary and `secondary` more images;
+    /// sizes and offsets stay zero for [`MultiPictureJpeg::to_bytes`] to fill
+    pub(crate) fn mpf_segment(secondary: usize) -> Segment {
+        let images = secondary as u32 + 1;
+        let entries_offset = 8 + 2 + 3 * 12 + 4;
+        let mut tiff = b"MM\0*".to_vec();
+        tiff.extend_from_slice(&8u32.to_be_bytes());
+        tiff.extend_from_slice(&3u16.to_be_bytes());
+        for (tag, kind, count, value) in [
+            (MPF_TAG_VERSION, 7u16, 4u32, u32::from_be_bytes(*b"0100")),
+            (MPF_TAG_NUMBER_OF_IMAGES, 4, 1, images),
+            (
+                MPF_TAG_MP_ENTRY,
+                7,
+                images * MPF_ENTRY_SIZE as u32,
+                entries_offset,
+            ),
+        ] {
+            tiff.extend_from_slice(&tag.to_be_bytes());
+            tiff.extend_from_slice(&kind.to_be_bytes());
+            tiff.extend_from_slice(&count.to_be_bytes());
+            tiff.extend_from_slice(&value.to_be_bytes());
+        }
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+        for i in 0..images {
+            // Baseline primary image, or an undefined dependent image
+            let attribute = if i == 0 { 0x0003_0000u32 } else { 0 };
+            tiff.extend_from_slice(&attribute.to_be_bytes());
+            tiff.extend_from_slice(&[0; MPF_ENTRY_SIZE - 4]);
+        }
+        Segment::new(MARKER_APP2, [MPF_NAMESPACE, &tiff].concat())
+    }
+
+    /// Primary and gain map JPEGs joined by an MPF index, each with the
+    /// given extra segments
+    pub(crate) fn gain_map_jpeg(primary: Vec<Segment>, gain_map: Vec<Segment>) -> Vec<u8> {
+        let mut image = flat_jpeg(200);
+        for segment in primary {
+            image.insert_app(segment);
+        }
+        image.insert_app(mpf_segment(1));
+        let mut map = flat_jpeg(64);
+        for segment in gain_map {
+            map.insert_app(segment);
+        }
+        MultiPictureJpeg {
+            primary: image,
+            secondary: vec![map],
+            mpf_entries: Vec::new(),
+        }
+        .to_bytes()
+        .unwrap()
+    }
+
+    fn xmp(text: &str) -> Segment {
+        Segment::new(MARKER_APP1, [XMP_NAMESPACE, text.as_bytes()].concat())
+    }
+
+    #[test]
+    fn mpf_index_locates_the_gain_map() {
+        let bytes = gain_map_jpeg(Vec::new(), Vec::new());
+        let container = MultiPictureJpeg::parse(&bytes).unwrap();
+
+        assert_eq!(container.secondary.len(), 1);
+        let primary_len = container.primary.encoded_len();
+        assert_eq!(container.mpf_entries[0].size as usize, primary_len);
+        assert_eq!(container.mpf_entries[0].offset, 0);
+        assert_eq!(
+            container.mpf_entries[1].size as usize,
+            bytes.len() - primary_len
+        );
+        let gain_map = container.gain_map().unwrap().to_bytes().unwrap();
+        assert!(gain_map == flat_jpeg(64).to_bytes().unwrap());
+        assert!(container.to_bytes().unwrap() == bytes);
+    }
+
+    #[test]
+    fn edits_rewrite_mpf_offsets() {
+        let bytes = gain_map_jpeg(Vec::new(), Vec::new());
+        let mut container = MultiPictureJpeg::parse(&bytes).unwrap();
+        // Lands before the MPF segment, moving its TIFF header
+        container.primary.insert_app(xmp(&"p".repeat(1000)));
+        container
+            .gain_map_mut()
+            .unwrap()
+            .insert_app(xmp(&"g".repeat(3000)));
+
+        let edited = MultiPictureJpeg::parse(&container.to_bytes().unwrap()).unwrap();
+        let gain_map = edited.gain_map().unwrap();
+        assert_eq!(
+            gain_map
+                .find(MARKER_APP1, XMP_NAMESPACE)
+                .unwrap()
+                .data
+                .len(),
+            XMP_NAMESPACE.len() + 3000
+        );
+        assert!(gain_map.scan == flat_jpeg(64).scan);
+        assert!(edited.primary.scan == container.primary.scan);
+    }
+
+    #[test]
+    fn mpf_entries_outside_the_file_are_rejected() {
+        let bytes = gain_map_jpeg(Vec::new(), Vec::new());
+        assert!(MultiPictureJpeg::parse(&bytes[..bytes.len() - 10]).is_err());
+    }
+
+    #[test]
+    fn jpeg_without_mpf_has_no_secondary_images() {
+        let bytes = flat_jpeg(128).to_bytes().unwrap();
+        let container = MultiPictureJpeg::parse(&bytes).unwrap();
+        assert!(container.secondary.is_empty());
+        assert!(container.to_bytes().unwrap() == bytes);
+    }
+
+    fn exif(tiff: &[u8]) -> Segment {
+        Segment::new(MARKER_APP1, [EXIF_NAMESPACE, tiff].concat())
+    }
+
+    /// IFD0 entries of an image's EXIF segment as (tag, type, count, value)
+    fn ifd0(image: &JpegImage) -> Vec<(u16, u16, u32, [u8; 4])> {
+        let tiff = image
+            .find(MARKER_APP1, EXIF_NAMESPACE)
+            .unwrap()
+            .body(EXIF_NAMESPACE);
+        let big_endian = tiff.starts_with(b"MM");
+        let ifd = read_u32(tiff, 4, big_endian).unwrap() as usize;
+        let count = read_u16(tiff, ifd, big_endian).unwrap() as usize;
+        (0..count)
+            .map(|i| {
+                let entry = ifd + 2 + i * 12;
+                (
+                    read_u16(tiff, entry, big_endian).unwrap(),
+                    read_u16(tiff, entry + 2, big_endian).unwrap(),
+                    read_u32(tiff, entry + 4, big_endian).unwrap(),
+                    tiff[entry + 8..entry + 12].try_into().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    /// Big-endian EXIF as a camera writes it: Make stored out of line,
+    /// Software inline, and an Orientation entry if given
+    fn camera_exif(orientation: Option<u16>) -> Vec<u8> {
+        let count = 2 + orientation.is_some() as u16;
+        let make_offset = 8 + 2 + count as u32 * 12 + 4;
+        let mut tiff = b"MM\0*".to_vec();
+        tiff.extend_from_slice(&8u32.to_be_bytes());
+        tiff.extend_from_slice(&count.to_be_bytes());
+        let mut entries = vec![
+            (0x010Fu16, 2u16, 6u32, make_offset.to_be_bytes()),
+            (0x0131, 2, 4, *b"abc\0"),
+        ];
+        if let Some(value) = orientation {
+            let [hi, lo] = value.to_be_bytes();
+            entries.insert(1, (EXIF_TAG_ORIENTATION, 3, 1, [hi, lo, 0, 0]));
+        }
+        for (tag, kind, count, value) in entries {
+            tiff.extend_from_slice(&tag.to_be_bytes());
+            tiff.extend_from_slice(&kind.to_be_bytes());
+            tiff.extend_from_slice(&count.to_be_bytes());
+            tiff.extend_from_slice(&value);
+        }
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+        tiff.extend_from_slice(b"Pixel\0");
+        tiff
+    }
+
+    #[test]
+    fn orientation_is_added_when_there_is_no_exif() {
+        let mut image = flat_jpeg(128);
+        image.set_exif_orientation(6);
+
+        assert_eq!(image.segments[0].marker, 0xE0);
+        assert!(image.segments[1].is_app(MARKER_APP1, EXIF_NAMESPACE));
+        assert_eq!(
+            ifd0(&image),
+            vec![(EXIF_TAG_ORIENTATION, 3, 1, [6, 0, 0, 0])]
+        );
+    }
+
+    #[test]
+    fn orientation_is_added_to_camera_exif_keeping_other_tags() {
+        let mut image = flat_jpeg(128);
+        image.insert_app(exif(&camera_exif(None)));
+        image.set_exif_orientation(8);
+
+        let entries = ifd0(&image);
+        let tags: Vec<u16> = entries.iter().map(|e| e.0).collect();
+        assert_eq!(tags, vec![0x010F, EXIF_TAG_ORIENTATION, 0x0131]);
+        assert_eq!(entries[1], (EXIF_TAG_ORIENTATION, 3, 1, [0, 8, 0, 0]));
+        assert_eq!(entries[2].3, *b"abc\0");
+        // Out-of-line values keep their offsets
+        let tiff = image
+            .find(MARKER_APP1, EXIF_NAMESPACE)
+            .unwrap()
+            .body(EXIF_NAMESPACE);
+        let make = u32::from_be_bytes(entries[0].3) as usize;
+        assert_eq!(&tiff[make..make + 6], b"Pixel\0");
+        assert_eq!(
+            image
+                .segments
+                .iter()
+                .filter(|s| s.is_app(MARKER_APP1, EXIF_NAMESPACE))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn existing_orientation_is_overwritten_in_place() {
+        let original = camera_exif(Some(1));
+        let mut image = flat_jpeg(128);
+        image.insert_app(exif(&original));
+        image.set_exif_orientation(3);
+
+        let tiff = image
+            .find(MARKER_APP1, EXIF_NAMESPACE)
+            .unwrap()
+            .body(EXIF_NAMESPACE);
+        assert_eq!(tiff.len(), original.len());
+        assert_eq!(ifd0(&image)[1], (EXIF_TAG_ORIENTATION, 3, 1, [0, 3, 0, 0]));
+    }
+
+    #[test]
+    fn unreadable_exif_is_replaced() {
+        let mut image = flat_jpeg(128);
+        image.insert_app(exif(b"garbage"));
+        image.set_exif_orientation(2);
+
+        assert_eq!(
+            ifd0(&image),
+            vec![(EXIF_TAG_ORIENTATION, 3, 1, [2, 0, 0, 0])]
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! WCAG 2.x and APCA contrast between two parts of a capture
+//!
+//! Both metrics are defined on SDR sRGB colours, so regions are measured on
+//! the SDR rendition (the clipped base image an SDR screen shows).
+
+use super::{colorspace, pixels, tonemap};
+use crate::api::analysis_api::{ContrastReport, SampleRegion};
+use crate::api::screen_shot_api::CaptureResult;
+
+// APCA 0.0.98G-4g constants
+const APCA_COEFFICIENTS: [f32; 3] = [0.2126729, 0.7151522, 0.072175];
+const APCA_BLACK_THRESHOLD: f32 = 0.022;
+const APCA_BLACK_CLAMP: f32 = 1.414;
+const APCA_DELTA_Y_MIN: f32 = 0.0005;
+const APCA_SCALE: f32 = 1.14;
+const APCA_OFFSET: f32 = 0.027;
+const APCA_LOW_CLIP: f32 = 0.1;
+
+/// Mean linear sRGB of the SDR rendition over a region
+fn sdr_region_linear(capture: &CaptureResult, region: &SampleRegion) -> anyhow::Result<[f32; 3]> {
+    pixels::check_region(capture, region.x, region.y, region.width, region.height)?;
+
+    let mut sum = [0.0f32; 3];
+    for y in region.y..region.y + region.height {
+        for x in region.x..region.x + region.width {
+            let signal = pixels::signal_at(capture, x, y);
+            for c in 0..3 {
+                sum[c] += pixels::srgb_to_linear(signal[c].clamp(0.0, 1.0));
+            }
+        }
+    }
+    let count = (region.width * region.height) as f32;
+    Ok(sum.map(|v| v / count))
+}
+
+/// WCAG 2.x contrast ratio between two relative luminances
+pub fn wcag_ratio(a: f32, b: f32) -> f32 {
+    let (lighter, darker) = if a > b { (a, b) } else { (b, a) };
+    (lighter + 0.05) / (darker + 0.05)
+}
+
+/// APCA screen luminance from sRGB code values in [0, 1]
+fn apca_luminance(srgb: [f32; 3]) -> f32 {
+    let y: f32 = srgb
+        .iter()
+        .zip(APCA_COEFFICIENTS)
+        .map(|(v, k)| v.powf(2.4) * k)
+        .sum();
+    if y < APCA_BLACK_THRESHOLD {
+        y + (APCA_BLACK_THRESHOLD - y).powf(APCA_BLACK_CLAMP)
+    } else {
+        y
+    }
+}
+
+/// APCA lightness contrast Lc of `text` on `background` (sRGB code values)
+///
+/// Positive for dark text on a light background, negative for light on dark.
+pub fn apca_lc(text: [f32; 3], background: [f32; 3]) -> f32 {
+    let y_text = apca_luminance(text);
+    let y_background = apca_luminance(background);
+    if (y_background - y_text).abs() < APCA_DELTA_Y_MIN {
+        return 0.0;
+    }
+    let lc = if y_background > y_text {
+        let sapc = (y_background.powf(0.56) - y_text.powf(0.57)) * APCA_SCALE;
+        if sapc < APCA_LOW_CLIP {
+            0.0
+        } else {
+            sapc - APCA_OFFSET
+        }
+    } else {
+        let sapc = (y_background.powf(0.65) - y_text.powf(0.62)) * APCA_SCALE;
+        if sapc > -APCA_LOW_CLIP {
+            0.0
+        } else {
+            sapc + APCA_OFFSET
+        }
+    };
+    lc * 100.0
+}
+
+pub fn measure_contrast(
+    capture: &CaptureResult,
+    foreground: &SampleRegion,
+    background: &SampleRegion,
+) -> anyhow::Result<ContrastReport> {
+    let foreground = sdr_region_linear(capture, foreground)?;
+    let background = sdr_region_linear(capture, background)?;
+    let foreground_srgb = foreground.map(pixels::linear_to_srgb);
+    let background_srgb = background.map(pixels::linear_to_srgb);
+
+    let ratio = wcag_ratio(
+        tonemap::luminance(foreground),
+        tonemap::luminance(background),
+    );
+    let lc = apca_lc(foreground_srgb, background_srgb);
+
+    Ok(ContrastReport {
+        foreground_hex: colorspace::css_hex(foreground_srgb),
+        background_hex: colorspace::css_hex(background_srgb),
+        wcag_ratio: ratio,
+        wcag_aa_normal_text: ratio >= 4.5,
+        wcag_aa_large_text: ratio >= 3.0,
+        wcag_aaa_normal_text: ratio >= 7.0,
+        wcag_aaa_large_text: ratio >= 4.5,
+        wcag_non_text: ratio >= 3.0,
+        apca_lc: lc,
+        apca_body_text: lc.abs() >= 75.0,
+        apca_content_text: lc.abs() >= 60.0,
+        apca_large_text: lc.abs() >= 45.0,
+        apca_non_text: lc.abs() >= 30.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(x: u32, width: u32) -> SampleRegion {
+        SampleRegion {
+            x,
+            y: 0,
+            width,
+            height: 2,
+        }
+    }
+
+    #[test]
+    fn black_on_white_is_21_to_1() {
+        assert!((wcag_ratio(1.0, 0.0) - 21.0).abs() < 1e-5);
+        assert_eq!(wcag_ratio(0.0, 1.0), wcag_ratio(1.0, 0.0));
+        assert_eq!(wcag_ratio(0.5, 0.5), 1.0);
+    }
+
+    #[test]
+    fn apca_matches_reference_values() {
+        // Published APCA 0.0.98G results for #000/#fff and #888 on #fff
+        let white = [1.0; 3];
+        let black = [0.0; 3];
+        let grey = [0x88 as f32 / 255.0; 3];
+        assert!((apca_lc(black, white) - 106.04).abs() < 0.05);
+        assert!((apca_lc(white, black) + 107.88).abs() < 0.05);
+        assert!((apca_lc(grey, white) - 63.06).abs() < 0.05);
+        assert_eq!(apca_lc(white, white), 0.0);
+    }
+
+    #[test]
+    fn measures_regions_on_the_sdr_rendition() {
+        // Black text next to an HDR highlight, which an SDR screen clips to white
+        let mut linear = Vec::new();
+        for _ in 0..2 {
+            linear.extend([0.0, 0.0, 0.0, 1.0].repeat(2));
+            linear.extend([4.0, 4.0, 4.0, 1.0].repeat(2));
+        }
+        let capture = pixels::capture_from_linear("hdr_macos", 4, 2, linear);
+        let report = measure_contrast(&capture, &region(0, 2), &region(2, 2)).unwrap();
+        assert_eq!(report.foreground_hex, "#000000");
+        assert_eq!(report.background_hex, "#ffffff");
+        assert!((report.wcag_ratio - 21.0).abs() < 1e-3);
+        assert!(report.wcag_aaa_normal_text && report.apca_body_text);
+        assert!(measure_contrast(&capture, &region(3, 2), &region(0, 1)).is_err());
+    }
+}
@@ -0,0 +1,365 @@
+//! Colour vision deficiency simulation (Machado, Oliveira & Fernandes 2009)
+//!
+//! The published matrices act on linear RGB. Machado tabulates them at
+//! severities 0.0–1.0 in steps of 0.1; they are not linear in severity, so
+//! in-between severities interpolate the two neighbouring tabulated matrices.
+
+use glam::f32::{Mat3, Vec3};
+
+use super::pixels;
+use crate::api::processing_api::ColorVisionDeficiency;
+use crate::api::screen_shot_api::CaptureResult;
+
+const IDENTITY: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// Row-major matrices at severity 0.0, 0.1, …, 1.0; the last entry is the
+/// dichromat (protanopia / deuteranopia / tritanopia)
+const PROTANOMALY: [[[f32; 3]; 3]; 11] = [
+    IDENTITY,
+    [
+        [0.856167, 0.182038, -0.038205],
+        [0.029342, 0.955115, 0.015544],
+        [-0.002880, -0.001563, 1.004443],
+    ],
+    [
+        [0.734766, 0.334872, -0.069637],
+        [0.051840, 0.919198, 0.028963],
+        [-0.004928, -0.004209, 1.009137],
+    ],
+    [
+        [0.630323, 0.465641, -0.095964],
+        [0.069181, 0.890046, 0.040773],
+        [-0.006308, -0.007724, 1.014032],
+    ],
+    [
+        [0.539009, 0.579343, -0.118352],
+        [0.082546, 0.866121, 0.051332],
+        [-0.007136, -0.011959, 1.019095],
+    ],
+    [
+        [0.458064, 0.679578, -0.137642],
+        [0.092785, 0.846313, 0.060902],
+        [-0.007494, -0.016807, 1.024301],
+    ],
+    [
+        [0.385450, 0.769005, -0.154455],
+        [0.100526, 0.829802, 0.069673],
+        [-0.007442, -0.022190, 1.029632],
+    ],
+    [
+        [0.319627, 0.849633, -0.169261],
+        [0.106241, 0.815969, 0.077790],
+        [-0.007025, -0.028051, 1.035076],
+    ],
+    [
+        [0.259411, 0.923008, -0.182420],
+        [0.110296, 0.804340, 0.085364],
+        [-0.006276, -0.034346, 1.040622],
+    ],
+    [
+        [0.203876, 0.990338, -0.194214],
+        [0.112975, 0.794542, 0.092483],
+        [-0.005222, -0.041043, 1.046265],
+    ],
+    [
+        [0.152286, 1.052583, -0.204868],
+        [0.114503, 0.786281, 0.099216],
+        [-0.003882, -0.048116, 1.051998],
+    ],
+];
+
+const DEUTERANOMALY: [[[f32; 3]; 3]; 11] = [
+    IDENTITY,
+    [
+        [0.866435, 0.177704, -0.044139],
+        [0.049567, 0.939063, 0.011370],
+        [-0.003453, 0.007233, 0.996220],
+    ],
+    [
+        [0.760729, 0.319078, -0.079807],
+        [0.090568, 0.889315, 0.020117],
+        [-0.006027, 0.013325, 0.992702],
+    ],
+    [
+        [0.675425, 0.433850, -0.109275],
+        [0.125303, 0.847755, 0.026942],
+        [-0.007950, 0.018572, 0.989378],
+    ],
+    [
+        [0.605511, 0.528560, -0.134071],
+        [0.155318, 0.812366, 0.032316],
+        [-0.009376, 0.023176, 0.986200],
+    ],
+    [
+        [0.547494, 0.607765, -0.155259],
+        [0.181692, 0.781742, 0.036566],
+        [-0.010410, 0.027275, 0.983136],
+    ],
+    [
+        [0.498864, 0.674741, -0.173604],
+        [0.205199, 0.754872, 0.039929],
+        [-0.011131, 0.030969, 0.980162],
+    ],
+    [
+        [0.457771, 0.731899, -0.189670],
+        [0.226409, 0.731012, 0.042579],
+        [-0.011595, 0.034333, 0.977261],
+    ],
+    [
+        [0.422823, 0.781057, -0.203881],
+        [0.245752, 0.709602, 0.044646],
+        [-0.011843, 0.037423, 0.974421],
+    ],
+    [
+        [0.392952, 0.823610, -0.216562],
+        [0.263559, 0.690210, 0.046232],
+        [-0.011910, 0.040281, 0.971630],
+    ],
+    [
+        [0.367322, 0.860646, -0.227968],
+        [0.280085, 0.672501, 0.047413],
+        [-0.011820, 0.042940, 0.968881],
+    ],
+];
+
+const TRITANOMALY: [[[f32; 3]; 3]; 11] = [
+    IDENTITY,
+    [
+        [0.926670, 0.092514, -0.019184],
+        [0.021191, 0.964503, 0.014306],
+        [0.008437, 0.054813, 0.936750],
+    ],
+    [
+        [0.895720, 0.133330, -0.029050],
+        [0.029997, 0.945400, 0.024603],
+        [0.013027, 0.104707, 0.882266],
+    ],
+    [
+        [0.905871, 0.127791, -0.033662],
+        [0.026856, 0.941251, 0.031893],
+        [0.013410, 0.148296, 0.838294],
+    ],
+    [
+        [0.948035, 0.089490, -0.037526],
+        [0.014364, 0.946792, 0.038844],
+        [0.010853, 0.193991, 0.795156],
+    ],
+    [
+        [1.017277, 0.027029, -0.044306],
+        [-0.006113, 0.958479, 0.047634],
+        [0.006379, 0.248708, 0.744913],
+    ],
+    [
+        [1.104996, -0.046633, -0.058363],
+        [-0.032137, 0.971635, 0.060503],
+        [0.001336, 0.317922, 0.680742],
+    ],
+    [
+        [1.193214, -0.109812, -0.083402],
+        [-0.058496, 0.979410, 0.079086],
+        [-0.002346, 0.403492, 0.598854],
+    ],
+    [
+        [1.257728, -0.139648, -0.118081],
+        [-0.078003, 0.975409, 0.102594],
+        [-0.003316, 0.501214, 0.502102],
+    ],
+    [
+        [1.278864, -0.125333, -0.153531],
+        [-0.084748, 0.957674, 0.127074],
+        [-0.000989, 0.601151, 0.399838],
+    ],
+    [
+        [1.255528, -0.076749, -0.178779],
+        [-0.078411, 0.930809, 0.147602],
+        [0.004733, 0.691367, 0.303900],
+    ],
+];
+
+fn simulation_matrix(deficiency: ColorVisionDeficiency, severity: f32) -> Mat3 {
+    let table = match deficiency {
+        ColorVisionDeficiency::Protanomaly => &PROTANOMALY,
+        ColorVisionDeficiency::Deuteranomaly => &DEUTERANOMALY,
+        ColorVisionDeficiency::Tritanomaly => &TRITANOMALY,
+    };
+    let position = severity.clamp(0.0, 1.0) * 10.0;
+    let lower = (position.floor() as usize).min(9);
+    let t = position - lower as f32;
+    let matrix = |rows: &[[f32; 3]; 3]| Mat3::from_cols_array_2d(rows).transpose();
+    matrix(&table[lower]) * (1.0 - t) + matrix(&table[lower + 1]) * t
+}
+
+/// Simulate how a viewer with `deficiency` sees the capture
+///
+/// Returns a capture in the same pixel format; HDR highlights stay HDR.
+pub fn simulate(
+    capture: &CaptureResult,
+    deficiency: ColorVisionDeficiency,
+    severity: f32,
+) -> anyhow::Result<CaptureResult> {
+    if !severity.is_finite() {
+        anyhow::bail!("Severity must be finite, got {severity}");
+    }
+    let matrix = simulation_matrix(deficiency, severity);
+    let mut linear = pixels::decode_linear(capture)?;
+    for pixel in linear.chunks_exact_mut(4) {
+        let rgb = matrix.mul_vec3(Vec3::new(pixel[0], pixel[1], pixel[2]));
+        pixel[..3].copy_from_slice(&rgb.max(Vec3::ZERO).to_array());
+    }
+    Ok(pixels::capture_from_linear(
+        &capture.mode,
+        capture.frame_width,
+        capture.frame_height,
+        linear,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [ColorVisionDeficiency; 3] = [
+        ColorVisionDeficiency::Protanomaly,
+        ColorVisionDeficiency::Deuteranomaly,
+        ColorVisionDeficiency::Tritanomaly,
+    ];
+
+    fn simulate_pixel(
+        linear: [f32; 3],
+        deficiency: ColorVisionDeficiency,
+        severity: f32,
+    ) -> [f32; 3] {
+        let [r, g, b] = linear;
+        let capture = pixels::capture_from_linear("hdr_macos", 1, 1, vec![r, g, b, 1.0]);
+        let out =
+            pixels::decode_linear(&simulate(&capture, deficiency, severity).unwrap()).unwrap();
+        [out[0], out[1], out[2]]
+    }
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a - e).abs() < e.abs() * 0.01 + 1e-3,
+                "{actual:?} != {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn greys_are_unchanged_and_stay_hdr() {
+        for deficiency in ALL {
+            assert_close(simulate_pixel([0.5; 3], deficiency, 1.0), [0.5; 3]);
+            assert_close(simulate_pixel([4.0; 3], deficiency, 1.0), [4.0; 3]);
+        }
+    }
+
+    #[test]
+    fn zero_severity_is_the_identity() {
+        for deficiency in ALL {
+            assert_close(
+                simulate_pixel([0.8, 0.2, 0.1], deficiency, 0.0),
+                [0.8, 0.2, 0.1],
+            );
+        }
+    }
+
+    #[test]
+    fn red_green_deficiencies_merge_red_and_green() {
+        let red = [0.8, 0.1, 0.1];
+        let green = [0.1, 0.5, 0.1];
+        // Gap between the two colours on the red-green axis
+        let gap = |severity: f32, deficiency| {
+            let [r1, g1, _] = simulate_pixel(red, deficiency, severity);
+            let [r2, g2, _] = simulate_pixel(green, deficiency, severity);
+            ((r1 - g1) - (r2 - g2)).abs()
+        };
+        for deficiency in [
+            ColorVisionDeficiency::Protanomaly,
+            ColorVisionDeficiency::Deuteranomaly,
+        ] {
+            let (none, half, full) = (
+                gap(0.0, deficiency),
+                gap(0.5, deficiency),
+                gap(1.0, deficiency),
+            );
+            assert!(
+                full < half && half < none,
+                "{deficiency:?}: {none} {half} {full}"
+            );
+            assert!(full < none * 0.2, "{deficiency:?}: {none} {full}");
+        }
+    }
+
+    fn assert_matrix(actual: Mat3, expected: [[f32; 3]; 3]) {
+        let actual = actual.transpose().to_cols_array_2d();
+        for (a, e) in actual.iter().flatten().zip(expected.iter().flatten()) {
+            assert!((a - e).abs() < 1e-6, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn half_severity_matches_the_published_matrices() {
+        // Machado et al. 2009, severity 0.5
+        let cases = [
+            (
+                ColorVisionDeficiency::Protanomaly,
+                [
+                    [0.458064, 0.679578, -0.137642],
+                    [0.092785, 0.846313, 0.060902],
+                    [-0.007494, -0.016807, 1.024301],
+                ],
+            ),
+            (
+                ColorVisionDeficiency::Deuteranomaly,
+                [
+                    [0.547494, 0.607765, -0.155259],
+                    [0.181692, 0.781742, 0.036566],
+                    [-0.010410, 0.027275, 0.983136],
+                ],
+            ),
+            (
+                ColorVisionDeficiency::Tritanomaly,
+                [
+                    [1.017277, 0.027029, -0.044306],
+                    [-0.006113, 0.958479, 0.047634],
+                    [0.006379, 0.248708, 0.744913],
+                ],
+            ),
+        ];
+        for (deficiency, expected) in cases {
+            assert_matrix(simulation_matrix(deficiency, 0.5), expected);
+        }
+    }
+
+    #[test]
+    fn severities_between_steps_interpolate_the_neighbours() {
+        let matrix = simulation_matrix(ColorVisionDeficiency::Protanomaly, 0.55);
+        let mut expected = PROTANOMALY[5];
+        for (row, next) in expected.iter_mut().zip(PROTANOMALY[6]) {
+            for (value, next) in row.iter_mut().zip(next) {
+                *value = (*value + next) / 2.0;
+            }
+        }
+        assert_matrix(matrix, expected);
+        assert_matrix(
+            simulation_matrix(ColorVisionDeficiency::Tritanomaly, 1.0),
+            TRITANOMALY[10],
+        );
+    }
+
+    #[test]
+    fn keeps_the_pixel_format() {
+        let capture = pixels::capture_from_linear(pixels::MODE_SDR_MACOS, 2, 1, vec![0.5; 8]);
+        let out = simulate(&capture, ColorVisionDeficiency::Tritanomaly, 1.0).unwrap();
+        assert_eq!(out.mode, pixels::MODE_SDR_MACOS);
+        assert_eq!(out.raw_data.len(), capture.raw_data.len());
+    }
+
+    #[test]
+    fn non_finite_severity_is_rejected() {
+        let capture = pixels::capture_from_linear("hdr_macos", 1, 1, vec![0.5; 4]);
+        for severity in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert!(simulate(&capture, ColorVisionDeficiency::Protanomaly, severity).is_err());
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Ultra HDR JPEG decoding back into capture buffers
+
+use anyhow::anyhow;
+use half::prelude::*;
+use image::ImageFormat;
+use ultrahdr::{sys, CompressedImage, Decoder};
+
+use super::container::MultiPictureJpeg;
+use super::pixels::{self, BT2020_TO_SRGB, P3_TO_SRGB};
+use crate::api::screen_shot_api::CaptureResult;
+
+/// Mode tag for captures reconstructed from an Ultra HDR JPEG
+pub const MODE_ULTRA_HDR: &str = "ultra_hdr";
+
+/// Decode an Ultra HDR JPEG with the gain map applied at full boost
+///
+/// The result uses the same FP16 layout as a live capture (gamma-encoded,
+/// sRGB primaries, 1.0 = SDR white), so it can go through crop and export
+/// again. A JPEG without a gain map decodes to its SDR image.
+pub fn ultra_hdr_jpeg_to_capture(jpeg: &[u8]) -> anyhow::Result<CaptureResult> {
+    // libultrahdr rejects input without a gain map
+    if MultiPictureJpeg::parse(jpeg).is_ok_and(|c| c.gain_map().is_none()) {
+        return sdr_jpeg_to_capture(jpeg);
+    }
+
+    let mut data = jpeg.to_vec();
+    let mut compressed = CompressedImage::from_bytes(
+        &mut data,
+        sys::uhdr_color_gamut::UHDR_CG_UNSPECIFIED,
+        sys::uhdr_color_transfer::UHDR_CT_UNSPECIFIED,
+        sys::uhdr_color_range::UHDR_CR_UNSPECIFIED,
+    );
+
+    let mut decoder = Decoder::new().map_err(|e| anyhow!("Failed to create decoder: {:?}", e))?;
+    decoder
+        .set_image(&mut compressed)
+        .map_err(|e| anyhow!("Failed to set compressed image: {:?}", e))?;
+    // The decoder clamps this to the image's HDR capacity, i.e. full boost
+    decoder
+        .set_out_max_display_boost(f32::MAX)
+        .map_err(|e| anyhow!("Failed to set display boost: {:?}", e))?;
+
+    let image = decoder
+        .decode_packed_view(
+            sys::uhdr_img_fmt::UHDR_IMG_FMT_64bppRGBAHalfFloat,
+            sys::uhdr_color_transfer::UHDR_CT_LINEAR,
+        )
+        .map_err(|e| anyhow!("Failed to decode: {:?}", e))?;
+    let width = image.width();
+    let height = image.height();
+    let (gamut, _, _) = image.meta();
+
+    // Widen to f32 row by row; the view skips any row padding
+    let row_len = width as usize * 4;
+    let mut linear = vec![0.0f32; row_len * height as usize];
+    for (y, row) in linear.chunks_exact_mut(row_len).enumerate() {
+        let src = image
+            .row(y)
+            .map_err(|e| anyhow!("Failed to read decoded row {y}: {:?}", e))?;
+        for (dst, chunk) in row.iter_mut().zip(src.chunks_exact(2)) {
+            *dst = f16::from_le_bytes([chunk[0], chunk[1]]).to_f32();
+        }
+    }
+
+    match gamut {
+        sys::uhdr_color_gamut::UHDR_CG_BT_2100 => {
+            pixels::convert_gamut(&mut linear, &BT2020_TO_SRGB)
+        }
+        sys::uhdr_color_gamut::UHDR_CG_DISPLAY_P3 => {
+            pixels::convert_gamut(&mut linear, &P3_TO_SRGB)
+        }
+        _ => {}
+    }
+
+    for pixel in linear.chunks_exact_mut(4) {
+        for v in &mut pixel[..3] {
+            *v = pixels::linear_to_signal(*v);
+        }
+    }
+
+    Ok(CaptureResult {
+        mode: MODE_ULTRA_HDR.to_string(),
+        raw_data: pixels::f32_to_f16_bytes(&linear),
+        frame_width: width,
+        frame_height: height,
+    })
+}
+
+/// Decode a JPEG without a gain map as its sRGB image
+fn sdr_jpeg_to_capture(jpeg: &[u8]) -> anyhow::Result<CaptureResult> {
+    let rgba = image::load_from_memory_with_format(jpeg, ImageFormat::Jpeg)?.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let linear = rgba
+        .as_raw()
+        .chunks_exact(4)
+        .flat_map(|p| {
+            let [r, g, b] = [p[0], p[1], p[2]].map(|v| pixels::srgb_to_linear(v as f32 / 255.0));
+            [r, g, b, p[3] as f32 / 255.0]
+        })
+        .collect();
+    Ok(pixels::capture_from_linear(
+        MODE_ULTRA_HDR,
+        width,
+        height,
+        linear,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::codecs::jpeg::JpegEncoder;
+    use image::ColorType;
+
+    use super::*;
+
+    /// Mid-grey capture with a 4x SDR white highlight in its left quarter
+    fn highlight_capture(width: u32, height: u32) -> CaptureResult {
+        let linear = (0..width * height)
+            .flat_map(|i| {
+                let level = if i % width < width / 4 { 4.0 } else { 0.2 };
+                [level, level, level, 1.0]
+            })
+            .collect();
+        pixels::capture_from_linear("hdr_macos", width, height, linear)
+    }
+
+    fn luminance_at(capture: &CaptureResult, x: u32, y: u32) -> f32 {
+        let linear = pixels::decode_linear(capture).unwrap();
+        let i = (y * capture.frame_width + x) as usize * 4;
+        linear[i + 1]
+    }
+
+    #[test]
+    fn decode_and_re_encode_keep_the_dimensions() {
+        let capture = highlight_capture(64, 48);
+        let decoded = ultra_hdr_jpeg_to_capture(&capture.to_ultra_hdr_jpeg().unwrap()).unwrap();
+        assert_eq!(decoded.mode, MODE_ULTRA_HDR);
+        assert_eq!((decoded.frame_width, decoded.frame_height), (64, 48));
+        assert_eq!(decoded.raw_data.len(), 64 * 48 * 8);
+
+        let again = ultra_hdr_jpeg_to_capture(&decoded.to_ultra_hdr_jpeg().unwrap()).unwrap();
+        assert_eq!((again.frame_width, again.frame_height), (64, 48));
+    }
+
+    #[test]
+    fn gain_map_restores_highlights_above_sdr_white() {
+        let capture = highlight_capture(64, 48);
+        let decoded = ultra_hdr_jpeg_to_capture(&capture.to_ultra_hdr_jpeg().unwrap()).unwrap();
+
+        let highlight = luminance_at(&decoded, 4, 24);
+        let grey = luminance_at(&decoded, 48, 24);
+        assert!((3.0..5.0).contains(&highlight), "highlight {highlight}");
+        assert!((0.15..0.25).contains(&grey), "grey {grey}");
+    }
+
+    #[test]
+    fn plain_jpeg_decodes_to_its_sdr_image() {
+        let mut jpeg = Vec::new();
+        JpegEncoder::new(&mut jpeg)
+            .encode(&[128; 16 * 8 * 3], 16, 8, ColorType::Rgb8)
+            .unwrap();
+
+        let decoded = ultra_hdr_jpeg_to_capture(&jpeg).unwrap();
+        assert_eq!((decoded.frame_width, decoded.frame_height), (16, 8));
+        let level = luminance_at(&decoded, 8, 4);
+        let expected = pixels::srgb_to_linear(128.0 / 255.0);
+        assert!((level - expected).abs() < 0.01, "{level} != {expected}");
+    }
+}
@@ -0,0 +1,268 @@
+//! Visual-regression diff between two captures in ICtCp
+//!
+//! Colours are compared with ΔE ITP (ITU-R BT.2124), which stays perceptually
+//! uniform into HDR highlights where CIE ΔE formulas break down.
+
+use anyhow::bail;
+use glam::f32::{Mat3, Vec3};
+
+use super::analysis::nits_to_pq;
+use super::pixels::{self, SDR_WHITE_NITS};
+use super::visualize;
+use crate::api::analysis_api::{CaptureDiff, DiffOptions, SampleRegion};
+use crate::api::screen_shot_api::CaptureResult;
+
+/// BT.2020 RGB to LMS, row-major, from BT.2100
+const RGB_TO_LMS: [[f32; 3]; 3] = [
+    [1688.0 / 4096.0, 2146.0 / 4096.0, 262.0 / 4096.0],
+    [683.0 / 4096.0, 2951.0 / 4096.0, 462.0 / 4096.0],
+    [99.0 / 4096.0, 309.0 / 4096.0, 3688.0 / 4096.0],
+];
+
+/// PQ-encoded LMS to ICtCp, row-major
+const LMS_TO_ICTCP: [[f32; 3]; 3] = [
+    [0.5, 0.5, 0.0],
+    [6610.0 / 4096.0, -13613.0 / 4096.0, 7003.0 / 4096.0],
+    [17933.0 / 4096.0, -17390.0 / 4096.0, -543.0 / 4096.0],
+];
+
+/// BT.2124 scale, so that 1.0 is about one just-noticeable difference
+const DELTA_E_ITP_SCALE: f32 = 720.0;
+
+/// ΔE at which the diff image reaches full red, as a multiple of the threshold
+const HEAT_RANGE: f32 = 10.0;
+
+/// Linear sRGB (1.0 = SDR white) to the ITP coordinates of BT.2124
+fn linear_to_itp(linear: [f32; 3], srgb_to_lms: &Mat3, lms_to_ictcp: &Mat3) -> Vec3 {
+    let lms = srgb_to_lms.mul_vec3(Vec3::from_array(linear).max(Vec3::ZERO));
+    let lms_pq = Vec3::from_array(lms.to_array().map(|v| nits_to_pq(v * SDR_WHITE_NITS)));
+    let ictcp = lms_to_ictcp.mul_vec3(lms_pq);
+    // T = 0.5 Ct makes the space closer to uniform
+    Vec3::new(ictcp.x, ictcp.y * 0.5, ictcp.z)
+}
+
+/// Grow the mask by `radius` pixels in each direction (square structuring
+/// element), done separably with running counts
+fn dilate(mask: &[bool], width: usize, height: usize, radius: usize) -> Vec<bool> {
+    if radius == 0 {
+        return mask.to_vec();
+    }
+    let mut horizontal = vec![false; mask.len()];
+    for y in 0..height {
+        let row = &mask[y * width..(y + 1) * width];
+        let mut prefix = vec![0u32; width + 1];
+        for x in 0..width {
+            prefix[x + 1] = prefix[x] + row[x] as u32;
+        }
+        for x in 0..width {
+            let lo = x.saturating_sub(radius);
+            let hi = (x + radius + 1).min(width);
+            horizontal[y * width + x] = prefix[hi] > prefix[lo];
+        }
+    }
+    let mut out = vec![false; mask.len()];
+    let mut prefix = vec![0u32; height + 1];
+    for x in 0..width {
+        for y in 0..height {
+            prefix[y + 1] = prefix[y] + horizontal[y * width + x] as u32;
+        }
+        for y in 0..height {
+            let lo = y.saturating_sub(radius);
+            let hi = (y + radius + 1).min(height);
+            out[y * width + x] = prefix[hi] > prefix[lo];
+        }
+    }
+    out
+}
+
+/// Bounding boxes of the changed pixels in each connected area of the
+/// dilated mask
+fn changed_regions(
+    mask: &[bool],
+    width: usize,
+    height: usize,
+    merge_distance: usize,
+) -> Vec<SampleRegion> {
+    let grown = dilate(mask, width, height, merge_distance.div_ceil(2));
+    let mut visited = vec![false; mask.len()];
+    let mut regions = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..mask.len() {
+        if !mask[start] || visited[start] {
+            continue;
+        }
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (usize::MAX, usize::MAX, 0, 0);
+        visited[start] = true;
+        stack.push(start);
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % width, i / width);
+            if mask[i] {
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x);
+                max_y = max_y.max(y);
+            }
+            let neighbours = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then(|| i + 1),
+                (y > 0).then(|| i - width),
+                (y + 1 < height).then(|| i + width),
+            ];
+            for n in neighbours.into_iter().flatten() {
+                if grown[n] && !visited[n] {
+                    visited[n] = true;
+                    stack.push(n);
+                }
+            }
+        }
+        regions.push(SampleRegion {
+            x: min_x as u32,
+            y: min_y as u32,
+            width: (max_x - min_x + 1) as u32,
+            height: (max_y - min_y + 1) as u32,
+        });
+    }
+    regions.sort_by_key(|r| std::cmp::Reverse(r.width as u64 * r.height as u64));
+    regions
+}
+
+fn heat(delta_e: f32, threshold: f32) -> [u8; 3] {
+    let t = ((delta_e / threshold - 1.0) / (HEAT_RANGE - 1.0)).clamp(0.0, 1.0);
+    [255, (224.0 * (1.0 - t)) as u8, 0]
+}
+
+pub fn diff(
+    before: &CaptureResult,
+    after: &CaptureResult,
+    options: &DiffOptions,
+) -> anyhow::Result<CaptureDiff> {
+    if before.frame_width != after.frame_width || before.frame_height != after.frame_height {
+        bail!(
+            "captures differ in size: {}x{} vs {}x{}",
+            before.frame_width,
+            before.frame_height,
+            after.frame_width,
+            after.frame_height
+        );
+    }
+    let threshold = options.threshold.max(f32::EPSILON);
+    let width = before.frame_width as usize;
+    let height = before.frame_height as usize;
+
+    let srgb_to_lms = Mat3::from_cols_array_2d(&RGB_TO_LMS).transpose()
+        * Mat3::from_cols_array(&super::SRGB_TO_BT2100);
+    let lms_to_ictcp = Mat3::from_cols_array_2d(&LMS_TO_ICTCP).transpose();
+
+    let before_linear = pixels::decode_linear(before)?;
+    let after_linear = pixels::decode_linear(after)?;
+
+    let mut delta_e = Vec::with_capacity(width * height);
+    let mut changed = Vec::with_capacity(width * height);
+    let mut diff_image = Vec::with_capacity(width * height * 4);
+    for (a, b) in before_linear
+        .chunks_exact(4)
+        .zip(after_linear.chunks_exact(4))
+    {
+        let a = [a[0], a[1], a[2]];
+        let b = [b[0], b[1], b[2]];
+        let distance = (linear_to_itp(a, &srgb_to_lms, &lms_to_ictcp)
+            - linear_to_itp(b, &srgb_to_lms, &lms_to_ictcp))
+        .length()
+            * DELTA_E_ITP_SCALE;
+        let is_changed = distance > threshold;
+        let [r, g, b] = if is_changed {
+            heat(distance, threshold)
+        } else {
+            visualize::dimmed_grey(b)
+        };
+        diff_image.extend_from_slice(&[r, g, b, 255]);
+        delta_e.push(distance);
+        changed.push(is_changed);
+    }
+
+    let changed_pixels = changed.iter().filter(|c| **c).count();
+    let pixel_count = delta_e.len().max(1);
+    Ok(CaptureDiff {
+        width: before.frame_width,
+        height: before.frame_height,
+        changed_regions: changed_regions(&changed, width, height, options.merge_distance as usize),
+        mask: changed.iter().map(|c| if *c { 255 } else { 0 }).collect(),
+        changed_pixels: changed_pixels as u64,
+        changed_fraction: changed_pixels as f32 / pixel_count as f32,
+        max_delta_e: delta_e.iter().copied().fold(0.0, f32::max),
+        mean_delta_e: delta_e.iter().sum::<f32>() / pixel_count as f32,
+        delta_e,
+        diff_image,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey_capture(width: u32, height: u32, changed: &[(u32, u32, f32)]) -> CaptureResult {
+        let mut linear = [0.3, 0.3, 0.3, 1.0].repeat((width * height) as usize);
+        for &(x, y, value) in changed {
+            let i = (y * width + x) as usize * 4;
+            linear[i..i + 3].fill(value);
+        }
+        pixels::capture_from_linear("hdr_macos", width, height, linear)
+    }
+
+    fn options(merge_distance: u32) -> DiffOptions {
+        DiffOptions {
+            threshold: 1.0,
+            merge_distance,
+        }
+    }
+
+    #[test]
+    fn identical_captures_have_no_difference() {
+        let capture = grey_capture(8, 6, &[(2, 2, 3.0)]);
+        let result = diff(&capture, &capture.clone(), &DiffOptions::default()).unwrap();
+        assert!(result.delta_e.iter().all(|d| *d == 0.0));
+        assert!(result.mask.iter().all(|m| *m == 0));
+        assert!(result.changed_regions.is_empty());
+        assert_eq!(result.changed_pixels, 0);
+        assert_eq!((result.max_delta_e, result.mean_delta_e), (0.0, 0.0));
+        assert_eq!(result.diff_image.len(), 8 * 6 * 4);
+    }
+
+    #[test]
+    fn nearby_changes_merge_into_one_region() {
+        let before = grey_capture(16, 8, &[]);
+        let after = grey_capture(16, 8, &[(2, 2, 1.0), (5, 3, 1.0), (14, 7, 1.0)]);
+
+        let merged = diff(&before, &after, &options(4)).unwrap();
+        assert_eq!(merged.changed_pixels, 3);
+        let boxes: Vec<_> = merged
+            .changed_regions
+            .iter()
+            .map(|r| (r.x, r.y, r.width, r.height))
+            .collect();
+        assert_eq!(boxes, [(2, 2, 4, 2), (14, 7, 1, 1)]);
+
+        let separate = diff(&before, &after, &options(0)).unwrap();
+        assert_eq!(separate.changed_regions.len(), 3);
+    }
+
+    #[test]
+    fn hdr_highlight_changes_are_detected() {
+        // Both values clip to the same SDR white
+        let before = grey_capture(2, 1, &[(0, 0, 2.0)]);
+        let after = grey_capture(2, 1, &[(0, 0, 3.0)]);
+        let result = diff(&before, &after, &DiffOptions::default()).unwrap();
+        assert_eq!(result.mask, [255, 0]);
+        assert_eq!(&result.diff_image[..3], [255, 0, 0]);
+    }
+
+    #[test]
+    fn captures_must_match_in_size() {
+        let result = diff(
+            &grey_capture(4, 4, &[]),
+            &grey_capture(4, 3, &[]),
+            &DiffOptions::default(),
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,203 @@
+//! Rectangular UI element detection for snap-to-element selection
+//!
+//! Neighbouring pixels whose colours differ by no more than a small step are
+//! joined into regions, so flat panels, buttons and gentle gradients each
+//! become one region while borders and glyphs break them apart. A region's
+//! bounding box is the element. Text and icons form tiny regions of their
+//! own; a query that lands on one looks outwards for the element around it.
+
+use super::pixels;
+use crate::api::analysis_api::{ElementMap, SampleRegion};
+use crate::api::screen_shot_api::CaptureResult;
+
+/// Largest per-channel step (signal units) between neighbours of one region
+const SAME_REGION_STEP: f32 = 0.02;
+/// Regions narrower or shorter than this are details, not elements
+const MIN_ELEMENT_SIZE: u32 = 8;
+/// How far from the query point to look for an enclosing element
+const SEARCH_RADIUS: i64 = 16;
+
+fn find(parent: &mut [u32], mut i: u32) -> u32 {
+    while parent[i as usize] != i {
+        let next = parent[parent[i as usize] as usize];
+        parent[i as usize] = next;
+        i = next;
+    }
+    i
+}
+
+fn union(parent: &mut [u32], a: u32, b: u32) {
+    let (a, b) = (find(parent, a), find(parent, b));
+    if a != b {
+        parent[a.max(b) as usize] = a.min(b);
+    }
+}
+
+pub fn detect(capture: &CaptureResult) -> anyhow::Result<ElementMap> {
+    let signal = pixels::decode_signal(capture)?;
+    let (width, height) = (capture.frame_width as usize, capture.frame_height as usize);
+    let similar = |a: usize, b: usize| {
+        signal[a * 4..a * 4 + 4]
+            .iter()
+            .zip(&signal[b * 4..b * 4 + 4])
+            .all(|(p, q)| (p - q).abs() <= SAME_REGION_STEP)
+    };
+
+    let mut parent: Vec<u32> = (0..(width * height) as u32).collect();
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            if x + 1 < width && similar(i, i + 1) {
+                union(&mut parent, i as u32, i as u32 + 1);
+            }
+            if y + 1 < height && similar(i, i + width) {
+                union(&mut parent, i as u32, (i + width) as u32);
+            }
+        }
+    }
+
+    // Number the regions densely and collect their bounds
+    let mut labels = vec![0u32; width * height];
+    let mut bounds: Vec<[u32; 4]> = Vec::new();
+    let mut label_of_root = vec![u32::MAX; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let root = find(&mut parent, i as u32) as usize;
+            if label_of_root[root] == u32::MAX {
+                label_of_root[root] = bounds.len() as u32;
+                bounds.push([x as u32, y as u32, x as u32 + 1, y as u32 + 1]);
+            }
+            let label = label_of_root[root];
+            labels[i] = label;
+            let b = &mut bounds[label as usize];
+            b[0] = b[0].min(x as u32);
+            b[2] = b[2].max(x as u32 + 1);
+            b[3] = y as u32 + 1;
+        }
+    }
+
+    Ok(ElementMap {
+        width: capture.frame_width,
+        height: capture.frame_height,
+        labels,
+        bounds,
+    })
+}
+
+fn is_element(bounds: &[u32; 4]) -> bool {
+    bounds[2] - bounds[0] >= MIN_ELEMENT_SIZE && bounds[3] - bounds[1] >= MIN_ELEMENT_SIZE
+}
+
+fn contains(bounds: &[u32; 4], x: u32, y: u32) -> bool {
+    (bounds[0]..bounds[2]).contains(&x) && (bounds[1]..bounds[3]).contains(&y)
+}
+
+fn area(bounds: &[u32; 4]) -> u64 {
+    (bounds[2] - bounds[0]) as u64 * (bounds[3] - bounds[1]) as u64
+}
+
+/// Bounds of the element at (x, y); over text or an icon, the smallest
+/// element nearby that encloses the point
+pub fn element_rect_at(map: &ElementMap, x: u32, y: u32) -> Option<SampleRegion> {
+    if x >= map.width || y >= map.height {
+        return None;
+    }
+    let label_at = |x: i64, y: i64| {
+        let inside = (0..map.width as i64).contains(&x) && (0..map.height as i64).contains(&y);
+        inside.then(|| map.labels[y as usize * map.width as usize + x as usize])
+    };
+
+    let mut best = label_at(x as i64, y as i64)
+        .map(|label| &map.bounds[label as usize])
+        .filter(|b| is_element(b));
+    // Walk square rings outwards; the first ring with a candidate decides
+    let (cx, cy) = (x as i64, y as i64);
+    for radius in 1..=SEARCH_RADIUS {
+        if best.is_some() {
+            break;
+        }
+        for offset in -radius..=radius {
+            for (px, py) in [
+                (cx + offset, cy - radius),
+                (cx + offset, cy + radius),
+                (cx - radius, cy + offset),
+                (cx + radius, cy + offset),
+            ] {
+                let Some(label) = label_at(px, py) else {
+                    continue;
+                };
+                let b = &map.bounds[label as usize];
+                if is_element(b)
+                    && contains(b, x, y)
+                    && best.is_none_or(|best| area(b) < area(best))
+                {
+                    best = Some(b);
+                }
+            }
+        }
+    }
+
+    best.map(|b| SampleRegion {
+        x: b[0],
+        y: b[1],
+        width: b[2] - b[0],
+        height: b[3] - b[1],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Background with a panel holding a button and a short run of "text"
+    fn mock_ui() -> CaptureResult {
+        let (width, height) = (64u32, 48u32);
+        let mut linear = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let inside = |x0, y0, x1, y1| (x0..x1).contains(&x) && (y0..y1).contains(&y);
+                let glyph = inside(14, 14, 26, 19) && x % 3 == 0;
+                let value = if glyph {
+                    0.05
+                } else if inside(30, 28, 50, 36) {
+                    0.3
+                } else if inside(8, 8, 56, 40) {
+                    0.6
+                } else {
+                    0.9
+                };
+                linear.extend([value, value, value, 1.0]);
+            }
+        }
+        pixels::capture_from_linear("hdr_macos", width, height, linear)
+    }
+
+    fn rect(region: Option<SampleRegion>) -> Option<(u32, u32, u32, u32)> {
+        region.map(|r| (r.x, r.y, r.width, r.height))
+    }
+
+    #[test]
+    fn text_inside_a_panel_snaps_to_the_panel() {
+        let map = detect(&mock_ui()).unwrap();
+        // On a glyph stroke, and in the gap between two strokes
+        assert_eq!(rect(element_rect_at(&map, 15, 16)), Some((8, 8, 48, 32)));
+        assert_eq!(rect(element_rect_at(&map, 14, 16)), Some((8, 8, 48, 32)));
+    }
+
+    #[test]
+    fn nested_elements_are_found_directly() {
+        let map = detect(&mock_ui()).unwrap();
+        assert_eq!(rect(element_rect_at(&map, 40, 30)), Some((30, 28, 20, 8)));
+        assert_eq!(rect(element_rect_at(&map, 10, 38)), Some((8, 8, 48, 32)));
+        assert_eq!(rect(element_rect_at(&map, 2, 2)), Some((0, 0, 64, 48)));
+    }
+
+    #[test]
+    fn points_without_an_element_give_none() {
+        let map = detect(&mock_ui()).unwrap();
+        assert_eq!(rect(element_rect_at(&map, 64, 0)), None);
+        let tiny = pixels::capture_from_linear("hdr_macos", 4, 4, vec![0.5; 64]);
+        assert_eq!(rect(element_rect_at(&detect(&tiny).unwrap(), 1, 1)), None);
+    }
+}
@@ -0,0 +1,83 @@
+//! OpenEXR export of captures
+//!
+//! EXR stores the linear light values themselves, so unlike the SDR formats
+//! it keeps both the HDR highlights and the alpha channel. The header gives
+//! the nits of (1, 1, 1) in the standard `whiteLuminance` attribute and the
+//! CTA-861.3 light levels in `maxCLL` / `maxFALL`, which OpenEXR has no
+//! standard attribute for.
+
+use std::io::Cursor;
+
+use exr::prelude::{AttributeValue, Image, SpecificChannels, Text, Vec2, WritableImage};
+
+use super::{analysis, pixels};
+use crate::api::screen_shot_api::CaptureResult;
+
+/// Header attribute names of the content light levels, in nits
+pub const MAX_CLL_ATTRIBUTE: &str = "maxCLL";
+pub const MAX_FALL_ATTRIBUTE: &str = "maxFALL";
+
+/// Encode as a 32-bit float RGBA EXR in linear light (sRGB primaries,
+/// 1.0 = SDR white), with colour premultiplied by alpha as OpenEXR expects
+pub fn capture_to_exr(capture: &CaptureResult) -> anyhow::Result<Vec<u8>> {
+    let mut linear = pixels::decode_linear(capture)?;
+    let (max_cll, max_fall) = analysis::light_levels(&linear);
+    for pixel in linear.chunks_exact_mut(4) {
+        let alpha = pixel[3].clamp(0.0, 1.0);
+        for v in &mut pixel[..3] {
+            *v *= alpha;
+        }
+        pixel[3] = alpha;
+    }
+
+    let width = capture.frame_width as usize;
+    let channels = SpecificChannels::rgba(|position: Vec2<usize>| {
+        let p = &linear[(position.y() * width + position.x()) * 4..][..4];
+        (p[0], p[1], p[2], p[3])
+    });
+    let mut image = Image::from_channels((width, capture.frame_height as usize), channels);
+    let attributes = &mut image.layer_data.attributes;
+    attributes.white_luminance = Some(pixels::SDR_WHITE_NITS);
+    for (name, nits) in [(MAX_CLL_ATTRIBUTE, max_cll), (MAX_FALL_ATTRIBUTE, max_fall)] {
+        attributes
+            .other
+            .insert(Text::from(name), AttributeValue::F32(nits));
+    }
+
+    let mut out = Cursor::new(Vec::new());
+    image.write().to_buffered(&mut out)?;
+    Ok(out.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exr::prelude::{ReadChannels, ReadLayers};
+
+    #[test]
+    fn header_carries_white_and_content_light_levels() {
+        // One pixel at SDR white, one at 4x SDR white
+        let linear = vec![1.0, 1.0, 1.0, 1.0, 4.0, 2.0, 1.0, 1.0];
+        let capture = pixels::capture_from_linear("hdr_macos", 2, 1, linear);
+        let bytes = capture_to_exr(&capture).unwrap();
+
+        let image = exr::prelude::read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .all_channels()
+            .first_valid_layer()
+            .all_attributes()
+            .from_buffered(Cursor::new(bytes))
+            .unwrap();
+        let attributes = &image.layer_data.attributes;
+        let white = attributes.white_luminance.unwrap();
+        assert!((white - pixels::SDR_WHITE_NITS).abs() < 1e-3);
+        let level = |name: &str| match attributes.other.get(&Text::from(name)) {
+            Some(AttributeValue::F32(nits)) => *nits,
+            other => panic!("{name}: {other:?}"),
+        };
+        let nits = pixels::SDR_WHITE_NITS;
+        assert!((level(MAX_CLL_ATTRIBUTE) - 4.0 * nits).abs() < 1.0);
+        assert!((level(MAX_FALL_ATTRIBUTE) - 2.5 * nits).abs() < 1.0);
+    }
+}
@@ -0,0 +1,386 @@
+//! Structural inspection of gain-map JPEGs, for diagnosing "doesn't look HDR"
+
+use super::container::{
+    JpegImage, MultiPictureJpeg, EXIF_NAMESPACE, ICC_NAMESPACE, ISO_21496_NAMESPACE, MARKER_APP1,
+    MARKER_APP2, MPF_NAMESPACE, XMP_NAMESPACE,
+};
+use super::metadata::{self, GainMapParams};
+use crate::api::hdr_image_api::{ContainerImageInfo, GainMapInfo, HdrImageInfo};
+
+/// Length of the "ICC_PROFILE\0" identifier plus sequence number and count
+const ICC_CHUNK_HEADER: usize = ICC_NAMESPACE.len() + 2;
+
+pub fn inspect_hdr_jpeg(bytes: &[u8]) -> anyhow::Result<HdrImageInfo> {
+    // A broken MPF index is what this report is for: fall back to the
+    // primary image alone and say why the container could not be read
+    let (container, container_error) = match MultiPictureJpeg::parse(bytes) {
+        Ok(container) => (container, None),
+        Err(e) => {
+            let (primary, _) = JpegImage::parse(bytes)?;
+            let container = MultiPictureJpeg {
+                primary,
+                secondary: Vec::new(),
+                mpf_entries: Vec::new(),
+            };
+            (container, Some(e.to_string()))
+        }
+    };
+
+    let mut images = vec![image_info(&container.primary, 0)];
+    images.extend(
+        container
+            .secondary
+            .iter()
+            .enumerate()
+            .map(|(i, image)| image_info(image, i + 1)),
+    );
+    for (info, entry) in images.iter_mut().zip(&container.mpf_entries) {
+        info.mpf_attribute = Some(entry.attribute);
+        info.mpf_offset = Some(entry.offset);
+        info.mpf_size = Some(entry.size);
+    }
+
+    let gain_map = container.gain_map();
+    // A malformed segment is reported rather than failing the inspection
+    let (iso_gain_map, iso_gain_map_error) = match gain_map
+        .and_then(|image| image.find(MARKER_APP2, ISO_21496_NAMESPACE))
+        .map(|segment| GainMapParams::from_iso(segment.body(ISO_21496_NAMESPACE)))
+    {
+        Some(Ok(params)) => (Some(gain_map_info(params)), None),
+        Some(Err(e)) => (None, Some(e.to_string())),
+        None => (None, None),
+    };
+    let xmp_gain_map = gain_map
+        .and_then(metadata::xmp_packet)
+        .and_then(|xmp| GainMapParams::from_xmp(&xmp))
+        .map(gain_map_info);
+
+    let primary_xmp = metadata::xmp_packet(&container.primary);
+    let gain_map_xmp = gain_map.and_then(metadata::xmp_packet);
+    let has_apple_gain_map = gain_map_xmp
+        .as_deref()
+        .is_some_and(|xmp| xmp.contains("HDRGainMap:") || xmp.contains("aux:hdrgainmap"));
+
+    Ok(HdrImageInfo {
+        ultra_hdr_version: primary_xmp
+            .as_deref()
+            .and_then(|xmp| metadata::xmp_attribute(xmp, "hdrgm:Version"))
+            .map(str::to_string),
+        has_mpf: container.primary.find(MARKER_APP2, MPF_NAMESPACE).is_some(),
+        container_error,
+        has_apple_gain_map,
+        iso_gain_map,
+        iso_gain_map_error,
+        xmp_gain_map,
+        images,
+        primary_xmp,
+        gain_map_xmp,
+    })
+}
+
+fn image_info(image: &JpegImage, index: usize) -> ContainerImageInfo {
+    // SOFn markers, excluding DHT (C4), JPG (C8) and DAC (CC)
+    let frame = image
+        .segments
+        .iter()
+        .find(|s| matches!(s.marker, 0xC0..=0xCF) && !matches!(s.marker, 0xC4 | 0xC8 | 0xCC))
+        .filter(|s| s.data.len() >= 6);
+    let (width, height, components) = frame.map_or((0, 0, 0), |s| {
+        (
+            u16::from_be_bytes([s.data[3], s.data[4]]) as u32,
+            u16::from_be_bytes([s.data[1], s.data[2]]) as u32,
+            s.data[5],
+        )
+    });
+
+    let icc = icc_profile(image);
+    let icc_description = icc.as_deref().and_then(icc_description);
+    let cicp = icc.as_deref().and_then(icc_cicp);
+    let color_gamut = cicp
+        .and_then(|(primaries, _)| cicp_primaries_name(primaries))
+        .or_else(|| icc_description.as_deref().and_then(gamut_from_description))
+        .map(str::to_string);
+    let color_transfer = cicp
+        .and_then(|(_, transfer)| cicp_transfer_name(transfer))
+        .or_else(|| {
+            icc_description
+                .as_deref()
+                .and_then(transfer_from_description)
+        })
+        .map(str::to_string);
+
+    ContainerImageInfo {
+        index: index as u32,
+        mpf_attribute: None,
+        mpf_offset: None,
+        mpf_size: None,
+        width,
+        height,
+        components,
+        has_exif: image.find(MARKER_APP1, EXIF_NAMESPACE).is_some(),
+        has_xmp: image.find(MARKER_APP1, XMP_NAMESPACE).is_some(),
+        has_iso_21496: image.find(MARKER_APP2, ISO_21496_NAMESPACE).is_some(),
+        has_icc: icc.is_some(),
+        icc_description,
+        color_gamut,
+        color_transfer,
+    }
+}
+
+fn gain_map_info(params: GainMapParams) -> GainMapInfo {
+    let linear = |values: [f32; 3]| values.iter().map(|v| v.exp2()).collect();
+    GainMapInfo {
+        min_content_boost: linear(params.gain_map_min),
+        max_content_boost: linear(params.gain_map_max),
+        gamma: params.gamma.to_vec(),
+        offset_sdr: params.offset_sdr.to_vec(),
+        offset_hdr: params.offset_hdr.to_vec(),
+        hdr_capacity_min: params.hdr_capacity_min.exp2(),
+        hdr_capacity_max: params.hdr_capacity_max.exp2(),
+        base_rendition_is_hdr: params.base_rendition_is_hdr,
+        use_base_color_space: params.use_base_color_space,
+        multi_channel: params.multi_channel,
+    }
+}
+
+/// Reassemble an ICC profile that may be split across several APP2 chunks
+fn icc_profile(image: &JpegImage) -> Option<Vec<u8>> {
+    let mut chunks: Vec<(u8, &[u8])> = image
+        .segments
+        .iter()
+        .filter(|s| s.is_app(MARKER_APP2, ICC_NAMESPACE) && s.data.len() > ICC_CHUNK_HEADER)
+        .map(|s| (s.data[ICC_NAMESPACE.len()], &s.data[ICC_CHUNK_HEADER..]))
+        .collect();
+    if chunks.is_empty() {
+        return None;
+    }
+    chunks.sort_by_key(|(sequence, _)| *sequence);
+    Some(
+        chunks
+            .into_iter()
+            .flat_map(|(_, data)| data.to_vec())
+            .collect(),
+    )
+}
+
+/// Find a tag in the ICC tag table, returning its data
+fn icc_tag<'a>(profile: &'a [u8], signature: &[u8; 4]) -> Option<&'a [u8]> {
+    let be32 = |pos: usize| -> Option<usize> {
+        Some(u32::from_be_bytes(profile.get(pos..pos + 4)?.try_into().ok()?) as usize)
+    };
+    // The count is untrusted; only entries that fit in the profile can exist
+    let count = be32(128)?.min(profile.len().saturating_sub(132) / 12);
+    (0..count).find_map(|i| {
+        let entry = 132 + i * 12;
+        if profile.get(entry..entry + 4)? != signature {
+            return None;
+        }
+        let offset = be32(entry + 4)?;
+        let size = be32(entry + 8)?;
+        profile.get(offset..offset.checked_add(size)?)
+    })
+}
+
+/// Profile description from a v2 `desc` or v4 `mluc` tag
+fn icc_description(profile: &[u8]) -> Option<String> {
+    let tag = icc_tag(profile, b"desc")?;
+    match tag.get(0..4)? {
+        b"desc" => {
+            let length = u32::from_be_bytes(tag.get(8..12)?.try_into().ok()?) as usize;
+            let text = tag.get(12..12 + length)?;
+            let text = text.split(|b| *b == 0).next().unwrap_or_default();
+            Some(String::from_utf8_lossy(text).into_owned())
+        }
+        b"mluc" => {
+            let length = u32::from_be_bytes(tag.get(20..24)?.try_into().ok()?) as usize;
+            let offset = u32::from_be_bytes(tag.get(24..28)?.try_into().ok()?) as usize;
+            let units: Vec<u16> = tag
+                .get(offset..offset + length)?
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            Some(String::from_utf16_lossy(&units))
+        }
+        _ => None,
+    }
+}
+
+/// Colour primaries and transfer characteristics from a `cicp` tag
+fn icc_cicp(profile: &[u8]) -> Option<(u8, u8)> {
+    let tag = icc_tag(profile, b"cicp")?;
+    Some((*tag.get(8)?, *tag.get(9)?))
+}
+
+fn cicp_primaries_name(primaries: u8) -> Option<&'static str> {
+    match primaries {
+        1 => Some("BT.709"),
+        9 => Some("BT.2020"),
+        12 => Some("Display P3"),
+        _ => None,
+    }
+}
+
+fn cicp_transfer_name(transfer: u8) -> Option<&'static str> {
+    match transfer {
+        8 => Some("Linear"),
+        13 => Some("sRGB"),
+        16 => Some("PQ"),
+        18 => Some("HLG"),
+        _ => None,
+    }
+}
+
+fn gamut_from_description(description: &str) -> Option<&'static str> {
+    let description = description.to_ascii_lowercase();
+    if description.contains("p3") {
+        Some("Display P3")
+    } else if description.contains("2020") || description.contains("2100") {
+        Some("BT.2020")
+    } else if description.contains("srgb") || description.contains("709") {
+        Some("BT.709")
+    } else {
+        None
+    }
+}
+
+fn transfer_from_description(description: &str) -> Option<&'static str> {
+    let description = description.to_ascii_lowercase();
+    if description.contains("pq") {
+        Some("PQ")
+    } else if description.contains("hlg") {
+        Some("HLG")
+    } else if description.contains("linear") {
+        Some("Linear")
+    } else if description.contains("srgb") || description.contains("p3") {
+        Some("sRGB")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colorist::container::tests::gain_map_jpeg;
+    use crate::colorist::container::Segment;
+    use crate::colorist::metadata::tests::{hdrgm_segment, iso_segment, ultra_hdr_jpeg};
+
+    /// ISO 21496-1 with per-channel fractions and an HDR base image
+    fn multi_channel_iso_segment() -> Segment {
+        let mut data = ISO_21496_NAMESPACE.to_vec();
+        // Versions, then flags: multi-channel and backward direction
+        data.extend_from_slice(&[0, 0, 0, 0, 0x80 | 0x04]);
+        let mut fraction = |n: i32, d: u32| {
+            data.extend_from_slice(&n.to_be_bytes());
+            data.extend_from_slice(&d.to_be_bytes());
+        };
+        // Base (HDR) and alternate (SDR) headroom
+        fraction(3, 1);
+        fraction(0, 1);
+        for channel in 1..=3 {
+            fraction(-1, 2);
+            fraction(channel, 1);
+            fraction(1, 1);
+            fraction(1, 32);
+            fraction(1, 64);
+        }
+        Segment::new(MARKER_APP2, data)
+    }
+
+    #[test]
+    fn reports_both_metadata_flavours() {
+        let info = inspect_hdr_jpeg(&ultra_hdr_jpeg()).unwrap();
+
+        assert!(info.has_mpf);
+        assert!(!info.has_apple_gain_map);
+        assert_eq!(info.ultra_hdr_version.as_deref(), Some("1.0"));
+        assert_eq!(info.images.len(), 2);
+        for image in &info.images {
+            assert_eq!((image.width, image.height, image.components), (8, 8, 3));
+            assert!(image.has_xmp && image.has_iso_21496);
+        }
+        assert_eq!(info.images[0].mpf_offset, Some(0));
+        assert!(info.images[1].mpf_offset.is_some_and(|offset| offset > 0));
+
+        let iso = info.iso_gain_map.unwrap();
+        assert_eq!(iso.max_content_boost, vec![4.0; 3]);
+        assert_eq!(iso.min_content_boost, vec![1.0; 3]);
+        assert_eq!(iso.hdr_capacity_max, 4.0);
+        assert_eq!(iso.offset_sdr, vec![1.0 / 64.0; 3]);
+        assert!(!iso.multi_channel);
+        let xmp = info.xmp_gain_map.unwrap();
+        assert_eq!(xmp.max_content_boost, iso.max_content_boost);
+        assert_eq!(xmp.hdr_capacity_max, iso.hdr_capacity_max);
+        assert!(info.iso_gain_map_error.is_none());
+        assert!(info.container_error.is_none());
+    }
+
+    #[test]
+    fn reads_multi_channel_iso_with_hdr_base() {
+        let bytes = gain_map_jpeg(Vec::new(), vec![multi_channel_iso_segment()]);
+        let iso = inspect_hdr_jpeg(&bytes).unwrap().iso_gain_map.unwrap();
+
+        assert!(iso.multi_channel && iso.base_rendition_is_hdr);
+        assert_eq!(iso.max_content_boost, vec![2.0, 4.0, 8.0]);
+        assert_eq!(iso.min_content_boost, vec![0.5f32.sqrt(); 3]);
+        // Headrooms and offsets are swapped into the SDR/HDR convention
+        assert_eq!(iso.hdr_capacity_min, 1.0);
+        assert_eq!(iso.hdr_capacity_max, 8.0);
+        assert_eq!(iso.offset_sdr, vec![1.0 / 64.0; 3]);
+        assert_eq!(iso.offset_hdr, vec![1.0 / 32.0; 3]);
+    }
+
+    #[test]
+    fn malformed_iso_segment_does_not_fail_the_report() {
+        let mut truncated = iso_segment(2.0, 2.0);
+        truncated.data.truncate(truncated.data.len() - 6);
+        let bytes = gain_map_jpeg(
+            vec![hdrgm_segment("")],
+            vec![
+                hdrgm_segment("hdrgm:GainMapMax=\"2.0\" hdrgm:HDRCapacityMax=\"2.0\""),
+                truncated,
+            ],
+        );
+        let info = inspect_hdr_jpeg(&bytes).unwrap();
+
+        assert!(info.iso_gain_map.is_none());
+        assert!(info
+            .iso_gain_map_error
+            .is_some_and(|e| e.contains("Truncated")));
+        assert!(info.xmp_gain_map.is_some());
+        assert_eq!(info.images.len(), 2);
+    }
+
+    #[test]
+    fn truncated_mpf_file_still_reports_the_primary_image() {
+        let bytes = ultra_hdr_jpeg();
+        let info = inspect_hdr_jpeg(&bytes[..bytes.len() - 10]).unwrap();
+
+        assert!(info
+            .container_error
+            .is_some_and(|e| e.contains("outside the file")));
+        assert!(info.has_mpf);
+        assert_eq!(info.images.len(), 1);
+        let primary = &info.images[0];
+        assert_eq!((primary.width, primary.height), (8, 8));
+        assert!(primary.has_xmp && primary.has_iso_21496);
+        assert_eq!(primary.mpf_offset, None);
+        assert_eq!(info.ultra_hdr_version.as_deref(), Some("1.0"));
+        assert!(info.primary_xmp.is_some());
+        assert!(info.iso_gain_map.is_none() && info.xmp_gain_map.is_none());
+    }
+
+    #[test]
+    fn icc_tag_count_is_bounded_by_the_profile() {
+        let mut profile = vec![0u8; 160];
+        profile[128..132].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(icc_tag(&profile, b"desc"), None);
+
+        // One real entry pointing past the end of the profile
+        profile[128..132].copy_from_slice(&1u32.to_be_bytes());
+        profile[132..136].copy_from_slice(b"desc");
+        profile[136..140].copy_from_slice(&u32::MAX.to_be_bytes());
+        profile[140..144].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(icc_tag(&profile, b"desc"), None);
+    }
+}
@@ -0,0 +1,105 @@
+//! Magnifier view around the cursor
+//!
+//! Only the loupe's own pixels are read from the capture on each query, so
+//! the cost is independent of the capture's size.
+
+use super::{pixels, sample, tonemap};
+use crate::api::analysis_api::{LoupeView, Magnifier};
+use crate::api::hdr_image_api::ToneMapper;
+use crate::api::screen_shot_api::CaptureResult;
+
+/// Largest loupe edge in pixels
+const MAX_LOUPE_SIZE: u32 = 255;
+
+pub fn magnifier(capture: CaptureResult, tone_mapper: ToneMapper) -> anyhow::Result<Magnifier> {
+    // Decoding validates the buffer once, so queries can index it freely
+    let signal = pixels::decode_signal(&capture)?;
+    let peak = signal
+        .chunks_exact(4)
+        .map(|p| p[0].max(p[1]).max(p[2]))
+        .fold(1.0f32, f32::max);
+    Ok(Magnifier {
+        capture,
+        tone_mapper,
+        peak: pixels::signal_to_linear(peak),
+    })
+}
+
+/// `size`² pixels centred on (x, y); even sizes put (x, y) just right of and
+/// below the middle
+pub fn loupe(magnifier: &Magnifier, x: u32, y: u32, size: u32) -> anyhow::Result<LoupeView> {
+    if size == 0 || size > MAX_LOUPE_SIZE {
+        anyhow::bail!("Loupe size {size} outside 1..={MAX_LOUPE_SIZE}");
+    }
+    let capture = &magnifier.capture;
+    let center = sample::sample_pixel(capture, x, y, 0)?;
+
+    let half = (size / 2) as i64;
+    let mut rgba = vec![0u8; size as usize * size as usize * 4];
+    for (row, line) in rgba.chunks_exact_mut(size as usize * 4).enumerate() {
+        let sy = y as i64 - half + row as i64;
+        if !(0..capture.frame_height as i64).contains(&sy) {
+            continue;
+        }
+        for (column, out) in line.chunks_exact_mut(4).enumerate() {
+            let sx = x as i64 - half + column as i64;
+            if !(0..capture.frame_width as i64).contains(&sx) {
+                continue;
+            }
+            let signal = pixels::signal_at(capture, sx as u32, sy as u32);
+            let linear = [signal[0], signal[1], signal[2]].map(pixels::signal_to_linear);
+            let [r, g, b] = tonemap::tone_map(linear, magnifier.tone_mapper, magnifier.peak)
+                .map(pixels::linear_to_srgb8);
+            out.copy_from_slice(&[r, g, b, (signal[3].clamp(0.0, 1.0) * 255.0).round() as u8]);
+        }
+    }
+
+    Ok(LoupeView { size, rgba, center })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 8x8 grey capture with one HDR highlight at (5, 2)
+    fn capture() -> CaptureResult {
+        let mut linear = [0.2, 0.2, 0.2, 1.0].repeat(64);
+        linear[(2 * 8 + 5) * 4..][..3].fill(4.0);
+        pixels::capture_from_linear("hdr_macos", 8, 8, linear)
+    }
+
+    fn rgba_at(view: &LoupeView, column: u32, row: u32) -> &[u8] {
+        &view.rgba[((row * view.size + column) * 4) as usize..][..4]
+    }
+
+    #[test]
+    fn loupe_is_centred_on_the_cursor() {
+        let magnifier = magnifier(capture(), ToneMapper::Reinhard).unwrap();
+        assert!((magnifier.peak - 4.0).abs() < 1e-2);
+        let view = loupe(&magnifier, 5, 2, 5).unwrap();
+        assert_eq!(view.rgba.len(), 5 * 5 * 4);
+        assert_eq!((view.center.x, view.center.y), (5, 2));
+        assert!(view.center.is_hdr);
+        // The highlight maps to white, its neighbours stay darker
+        assert_eq!(rgba_at(&view, 2, 2), [255, 255, 255, 255]);
+        assert!(rgba_at(&view, 1, 2)[0] < 128);
+    }
+
+    #[test]
+    fn outside_the_frame_is_transparent() {
+        let magnifier = magnifier(capture(), ToneMapper::Clip).unwrap();
+        let view = loupe(&magnifier, 0, 7, 4).unwrap();
+        // Even size: the cursor is just right of and below the middle
+        assert_eq!(rgba_at(&view, 0, 0), [0, 0, 0, 0]);
+        assert_eq!(rgba_at(&view, 2, 2)[3], 255);
+        assert_eq!(rgba_at(&view, 2, 3), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn size_and_position_are_checked() {
+        let magnifier = magnifier(capture(), ToneMapper::Clip).unwrap();
+        assert!(loupe(&magnifier, 1, 1, 0).is_err());
+        assert!(loupe(&magnifier, 1, 1, MAX_LOUPE_SIZE + 1).is_err());
+        assert!(loupe(&magnifier, 8, 1, 3).is_err());
+    }
+}
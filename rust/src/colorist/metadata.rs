@@ -0,0 +1,700 @@
+//! Gain-map metadata: parsing and selecting between the on-disk flavours
+//!
+//! The same gain map can be described three ways:
+//! - Ultra HDR XMP (`hdrgm:` namespace), read by Android and Chrome
+//! - ISO 21496-1 binary metadata in an APP2 segment, read by iOS 18 / macOS 15
+//!   and newer browsers
+//! - Apple's `HDRGainMap` XMP on the auxiliary image, read by older Apple Photos
+//!
+//! libultrahdr writes the first two; this module trims or augments its output.
+
+use anyhow::{anyhow, bail};
+
+use super::container::{
+    JpegImage, MultiPictureJpeg, Segment, ISO_21496_NAMESPACE, MARKER_APP1, MARKER_APP2,
+    XMP_NAMESPACE,
+};
+use crate::api::screen_shot_api::{GainMapMetadataFormat, UltraHdrOptions};
+
+const ISO_FLAG_MULTI_CHANNEL: u8 = 1 << 7;
+const ISO_FLAG_USE_BASE_COLOUR_SPACE: u8 = 1 << 6;
+const ISO_FLAG_COMMON_DENOMINATOR: u8 = 1 << 3;
+const ISO_FLAG_BACKWARD_DIRECTION: u8 = 1 << 2;
+
+const APPLE_GAIN_MAP_NAMESPACE: &str = "http://ns.apple.com/HDRGainMap/1.0/";
+const APPLE_PIXEL_DATA_NAMESPACE: &str = "http://ns.apple.com/pixeldatainfo/1.0/";
+const APPLE_GAIN_MAP_AUX_TYPE: &str = "urn:com:apple:photo:2020:aux:hdrgainmap";
+const APPLE_GAIN_MAP_VERSION: u32 = 0x20000;
+
+/// Gain map parameters in the Ultra HDR convention
+///
+/// Boosts and capacities are log2 values, per channel where applicable
+/// (single-channel maps repeat the value three times).
+#[derive(Clone, Debug, PartialEq)]
+pub struct GainMapParams {
+    pub gain_map_min: [f32; 3],
+    pub gain_map_max: [f32; 3],
+    pub gamma: [f32; 3],
+    pub offset_sdr: [f32; 3],
+    pub offset_hdr: [f32; 3],
+    pub hdr_capacity_min: f32,
+    pub hdr_capacity_max: f32,
+    pub base_rendition_is_hdr: bool,
+    pub use_base_color_space: bool,
+    pub multi_channel: bool,
+}
+
+impl GainMapParams {
+    /// Parse `hdrgm:` properties from an XMP packet, in attribute or element form
+    pub fn from_xmp(xmp: &str) -> Option<Self> {
+        if !xmp.contains("hdrgm:") {
+            return None;
+        }
+        let gain_map_max = xmp_values(xmp, "hdrgm:GainMapMax")?;
+        let hdr_capacity_max = xmp_values(xmp, "hdrgm:HDRCapacityMax")?[0];
+
+        let channels = |name: &str, default: f32| -> [f32; 3] {
+            expand(xmp_values(xmp, name).unwrap_or_else(|| vec![default]))
+        };
+        Some(Self {
+            gain_map_min: channels("hdrgm:GainMapMin", 0.0),
+            multi_channel: gain_map_max.len() == 3,
+            gain_map_max: expand(gain_map_max),
+            gamma: channels("hdrgm:Gamma", 1.0),
+            offset_sdr: channels("hdrgm:OffsetSDR", 1.0 / 64.0),
+            offset_hdr: channels("hdrgm:OffsetHDR", 1.0 / 64.0),
+            hdr_capacity_min: xmp_values(xmp, "hdrgm:HDRCapacityMin").map_or(0.0, |v| v[0]),
+            hdr_capacity_max,
+            base_rendition_is_hdr: xmp_attribute(xmp, "hdrgm:BaseRenditionIsHDR")
+                .is_some_and(|v| v.eq_ignore_ascii_case("true")),
+            use_base_color_space: true,
+        })
+    }
+
+    /// Parse the ISO 21496-1 binary payload (after the URN identifier)
+    pub fn from_iso(data: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = IsoReader { data, pos: 0 };
+        let min_version = reader.u16()?;
+        let _writer_version = reader.u16()?;
+        if min_version != 0 {
+            bail!("Unsupported ISO 21496-1 metadata version {min_version}");
+        }
+        let flags = reader.u8()?;
+        let channel_count = if flags & ISO_FLAG_MULTI_CHANNEL != 0 {
+            3
+        } else {
+            1
+        };
+
+        let mut params = Self {
+            gain_map_min: [0.0; 3],
+            gain_map_max: [0.0; 3],
+            gamma: [1.0; 3],
+            offset_sdr: [0.0; 3],
+            offset_hdr: [0.0; 3],
+            hdr_capacity_min: 0.0,
+            hdr_capacity_max: 0.0,
+            base_rendition_is_hdr: flags & ISO_FLAG_BACKWARD_DIRECTION != 0,
+            use_base_color_space: flags & ISO_FLAG_USE_BASE_COLOUR_SPACE != 0,
+            multi_channel: channel_count == 3,
+        };
+
+        let (base_headroom, alternate_headroom);
+        if flags & ISO_FLAG_COMMON_DENOMINATOR != 0 {
+            let d = reader.u32()? as f32;
+            if d == 0.0 {
+                bail!("ISO 21496-1 metadata has a zero denominator");
+            }
+            base_headroom = reader.u32()? as f32 / d;
+            alternate_headroom = reader.u32()? as f32 / d;
+            for c in 0..channel_count {
+                params.gain_map_min[c] = reader.i32()? as f32 / d;
+                params.gain_map_max[c] = reader.i32()? as f32 / d;
+                params.gamma[c] = reader.u32()? as f32 / d;
+                params.offset_sdr[c] = reader.i32()? as f32 / d;
+                params.offset_hdr[c] = reader.i32()? as f32 / d;
+            }
+        } else {
+            base_headroom = reader.unsigned_fraction()?;
+            alternate_headroom = reader.unsigned_fraction()?;
+            for c in 0..channel_count {
+                params.gain_map_min[c] = reader.signed_fraction()?;
+                params.gain_map_max[c] = reader.signed_fraction()?;
+                params.gamma[c] = reader.unsigned_fraction()?;
+                params.offset_sdr[c] = reader.signed_fraction()?;
+                params.offset_hdr[c] = reader.signed_fraction()?;
+            }
+        }
+        if channel_count == 1 {
+            for field in [
+                &mut params.gain_map_min,
+                &mut params.gain_map_max,
+                &mut params.gamma,
+                &mut params.offset_sdr,
+                &mut params.offset_hdr,
+            ] {
+                *field = [field[0]; 3];
+            }
+        }
+
+        // ISO describes base/alternate; Ultra HDR describes SDR/HDR
+        if params.base_rendition_is_hdr {
+            params.hdr_capacity_min = alternate_headroom;
+            params.hdr_capacity_max = base_headroom;
+            std::mem::swap(&mut params.offset_sdr, &mut params.offset_hdr);
+        } else {
+            params.hdr_capacity_min = base_headroom;
+            params.hdr_capacity_max = alternate_headroom;
+        }
+        Ok(params)
+    }
+
+    /// Read the gain map parameters of a parsed file, preferring ISO metadata
+    pub fn from_container(container: &MultiPictureJpeg) -> anyhow::Result<Self> {
+        let gain_map = container
+            .gain_map()
+            .ok_or_else(|| anyhow!("Image has no gain map"))?;
+        if let Some(iso) = gain_map.find(MARKER_APP2, ISO_21496_NAMESPACE) {
+            return Self::from_iso(iso.body(ISO_21496_NAMESPACE));
+        }
+        xmp_packet(gain_map)
+            .and_then(|xmp| Self::from_xmp(&xmp))
+            .ok_or_else(|| anyhow!("Gain map has neither ISO 21496-1 nor Ultra HDR XMP metadata"))
+    }
+
+    /// Largest per-channel content boost as a linear factor
+    pub fn max_content_boost(&self) -> f32 {
+        self.gain_map_max
+            .iter()
+            .copied()
+            .fold(f32::MIN, f32::max)
+            .exp2()
+    }
+}
+
+struct IsoReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl IsoReader<'_> {
+    fn take<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| anyhow!("Truncated ISO 21496-1 metadata"))?;
+        self.pos += N;
+        Ok(bytes.try_into()?)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_be_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_be_bytes(self.take()?))
+    }
+
+    fn i32(&mut self) -> anyhow::Result<i32> {
+        Ok(i32::from_be_bytes(self.take()?))
+    }
+
+    fn unsigned_fraction(&mut self) -> anyhow::Result<f32> {
+        let n = self.u32()?;
+        let d = self.u32()?;
+        if d == 0 {
+            bail!("ISO 21496-1 metadata has a zero denominator");
+        }
+        Ok(n as f32 / d as f32)
+    }
+
+    fn signed_fraction(&mut self) -> anyhow::Result<f32> {
+        let n = self.i32()?;
+        let d = self.u32()?;
+        if d == 0 {
+            bail!("ISO 21496-1 metadata has a zero denominator");
+        }
+        Ok(n as f32 / d as f32)
+    }
+}
+
+fn expand(values: Vec<f32>) -> [f32; 3] {
+    match values.as_slice() {
+        [r, g, b, ..] => [*r, *g, *b],
+        [v, ..] => [*v; 3],
+        [] => [0.0; 3],
+    }
+}
+
+/// Value of an XMP property written as `name="value"`
+pub fn xmp_attribute<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!("{name}=\"");
+    let start = xmp.find(&pattern)? + pattern.len();
+    let end = xmp[start..].find('"')?;
+    Some(&xmp[start..start + end])
+}
+
+/// Numeric values of an XMP property in attribute, element or `rdf:Seq` form
+pub fn xmp_values(xmp: &str, name: &str) -> Option<Vec<f32>> {
+    if let Some(value) = xmp_attribute(xmp, name) {
+        return value.trim().parse().ok().map(|v| vec![v]);
+    }
+    let open = format!("<{name}>");
+    let close = format!("</{name}>");
+    let start = xmp.find(&open)? + open.len();
+    let end = xmp[start..].find(&close)? + start;
+    let body = &xmp[start..end];
+    if !body.contains("<rdf:li") {
+        return body.trim().parse().ok().map(|v| vec![v]);
+    }
+    let values = body
+        .split("<rdf:li")
+        .skip(1)
+        .filter_map(|item| {
+            let value = &item[item.find('>')? + 1..];
+            value[..value.find('<')?].trim().parse().ok()
+        })
+        .collect::<Vec<f32>>();
+    (!values.is_empty()).then_some(values)
+}
+
+/// The standard XMP packet of an image, if any
+pub fn xmp_packet(image: &JpegImage) -> Option<String> {
+    image
+        .find(MARKER_APP1, XMP_NAMESPACE)
+        .map(|s| String::from_utf8_lossy(s.body(XMP_NAMESPACE)).into_owned())
+}
+
+fn is_gain_map_xmp(segment: &Segment) -> bool {
+    segment.is_app(MARKER_APP1, XMP_NAMESPACE)
+        && String::from_utf8_lossy(segment.body(XMP_NAMESPACE)).contains("hdrgm:")
+}
+
+/// Remove the Ultra HDR properties from an image's XMP packet
+///
+/// Only `hdrgm:` properties and the gain map's GContainer item go; the rest
+/// of the packet (the directory's primary item, unrelated XMP) is kept.
+pub(crate) fn remove_gain_map_xmp(image: &mut JpegImage) {
+    let Some(segment) = image.segments.iter_mut().find(|s| is_gain_map_xmp(s)) else {
+        return;
+    };
+    let xmp = String::from_utf8_lossy(segment.body(XMP_NAMESPACE)).into_owned();
+    let stripped = remove_gain_map_item(&remove_hdrgm_attributes(&remove_hdrgm_elements(&xmp)));
+    segment.data = [XMP_NAMESPACE, stripped.as_bytes()].concat();
+}
+
+/// Drop `<hdrgm:Name>…</hdrgm:Name>` and `<hdrgm:Name/>` properties
+fn remove_hdrgm_elements(xmp: &str) -> String {
+    let mut out = String::with_capacity(xmp.len());
+    let mut rest = xmp;
+    while let Some(start) = rest.find("<hdrgm:") {
+        let tag = &rest[start..];
+        let Some(tag_end) = tag.find('>').map(|i| i + 1) else {
+            break;
+        };
+        let end = if tag[..tag_end].ends_with("/>") {
+            Some(tag_end)
+        } else {
+            let name_end = tag[1..tag_end]
+                .find(|c: char| c == '>' || c.is_whitespace())
+                .map_or(tag_end - 1, |i| i + 1);
+            let close = format!("</{}>", &tag[1..name_end]);
+            tag[tag_end..]
+                .find(&close)
+                .map(|i| tag_end + i + close.len())
+        };
+        let Some(end) = end else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        rest = &rest[start + end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Drop `hdrgm:Name="…"` attributes and the `xmlns:hdrgm` declaration
+fn remove_hdrgm_attributes(xmp: &str) -> String {
+    let mut out = String::with_capacity(xmp.len());
+    let mut rest = xmp;
+    while let Some(equals) = rest.find("=\"") {
+        let name_start = rest[..equals]
+            .rfind(|c: char| c.is_whitespace())
+            .map_or(0, |i| i + 1);
+        let value_start = equals + 2;
+        let Some(value_end) = rest[value_start..].find('"').map(|i| value_start + i + 1) else {
+            break;
+        };
+        let name = &rest[name_start..equals];
+        if name.starts_with("hdrgm:") || name == "xmlns:hdrgm" {
+            // Take the whitespace separating the attribute with it
+            let cut = rest[..name_start].trim_end().len();
+            out.push_str(&rest[..cut]);
+        } else {
+            out.push_str(&rest[..value_end]);
+        }
+        rest = &rest[value_end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Drop the `rdf:li` holding the gain map's `Container:Item`
+fn remove_gain_map_item(xmp: &str) -> String {
+    const OPEN: &str = "<rdf:li";
+    const CLOSE: &str = "</rdf:li>";
+
+    let mut out = String::with_capacity(xmp.len());
+    let mut rest = xmp;
+    while let Some(start) = rest.find(OPEN) {
+        let Some(end) = rest[start..].find(CLOSE).map(|i| start + i + CLOSE.len()) else {
+            break;
+        };
+        let item = &rest[start..end];
+        out.push_str(&rest[..start]);
+        if !(item.contains("<Container:Item") && item.contains("Item:Semantic=\"GainMap\"")) {
+            out.push_str(item);
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Add an `rdf:Description` to an image's XMP packet, creating one if needed
+///
+/// JPEG allows a single standard XMP packet per image, so the description is
+/// merged into the existing packet rather than written as a second segment.
+fn add_xmp_description(image: &mut JpegImage, description: &str) {
+    if let Some(segment) = image.find_mut(MARKER_APP1, XMP_NAMESPACE) {
+        let xmp = String::from_utf8_lossy(segment.body(XMP_NAMESPACE)).into_owned();
+        if let Some(index) = xmp.rfind("</rdf:RDF>") {
+            let merged = format!("{}{description}{}", &xmp[..index], &xmp[index..]);
+            segment.data = [XMP_NAMESPACE, merged.as_bytes()].concat();
+            return;
+        }
+    }
+    let packet = format!(
+        "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+         {description}</rdf:RDF></x:xmpmeta>"
+    );
+    image.remove_where(|s| s.is_app(MARKER_APP1, XMP_NAMESPACE));
+    image.insert_app(Segment::new(
+        MARKER_APP1,
+        [XMP_NAMESPACE, packet.as_bytes()].concat(),
+    ));
+}
+
+/// Rewrite the gain-map metadata of an encoded Ultra HDR JPEG per `options`
+pub fn apply_metadata_options(jpeg: Vec<u8>, options: &UltraHdrOptions) -> anyhow::Result<Vec<u8>> {
+    if options.metadata_format == GainMapMetadataFormat::Both
+        && !options.apple_gain_map
+        && options.orientation.is_none()
+    {
+        return Ok(jpeg);
+    }
+
+    let mut container = MultiPictureJpeg::parse(&jpeg)?;
+    // Read the parameters before any flavour is stripped
+    let params = GainMapParams::from_container(&container)?;
+
+    let images = std::iter::once(&mut container.primary).chain(container.secondary.iter_mut());
+    for image in images {
+        match options.metadata_format {
+            GainMapMetadataFormat::Iso => remove_gain_map_xmp(image),
+            GainMapMetadataFormat::UltraHdrXmp => {
+                image.remove_where(|s| s.is_app(MARKER_APP2, ISO_21496_NAMESPACE))
+            }
+            GainMapMetadataFormat::Both => {}
+        }
+    }
+
+    if options.apple_gain_map {
+        let description = format!(
+            "<rdf:Description rdf:about=\"\" \
+             xmlns:HDRGainMap=\"{APPLE_GAIN_MAP_NAMESPACE}\" \
+             xmlns:apdi=\"{APPLE_PIXEL_DATA_NAMESPACE}\" \
+             HDRGainMap:HDRGainMapVersion=\"{APPLE_GAIN_MAP_VERSION}\" \
+             HDRGainMap:HDRGainMapHeadroom=\"{:.6}\" \
+             apdi:AuxiliaryImageType=\"{APPLE_GAIN_MAP_AUX_TYPE}\"/>",
+            params.max_content_boost()
+        );
+        let gain_map = container
+            .gain_map_mut()
+            .ok_or_else(|| anyhow!("Image has no gain map"))?;
+        add_xmp_description(gain_map, &description);
+    }
+
+    if let Some(transform) = options.orientation {
+        container
+            .primary
+            .set_exif_orientation(super::transform::exif_orientation(transform));
+    }
+
+    container.to_bytes()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::api::processing_api::ImageTransform;
+    use crate::colorist::container::tests::gain_map_jpeg;
+
+    /// Denominator used by [`iso_segment`]; 1/64 offsets are exact with it
+    const DENOMINATOR: u32 = 1 << 16;
+
+    /// Single-channel ISO 21496-1 metadata with a common denominator, SDR
+    /// base, log2 `gain_map_max` and `hdr_capacity_max`
+    pub(crate) fn iso_segment(gain_map_max: f32, hdr_capacity_max: f32) -> Segment {
+        let fixed = |v: f32| (v * DENOMINATOR as f32) as i32;
+        let mut data = ISO_21496_NAMESPACE.to_vec();
+        data.extend_from_slice(&[0, 0, 0, 0, ISO_FLAG_COMMON_DENOMINATOR]);
+        for value in [
+            DENOMINATOR as i32,
+            0,
+            fixed(hdr_capacity_max),
+            0,
+            fixed(gain_map_max),
+            fixed(1.0),
+            fixed(1.0 / 64.0),
+            fixed(1.0 / 64.0),
+        ] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        Segment::new(MARKER_APP2, data)
+    }
+
+    /// Ultra HDR XMP; the primary image only carries the version
+    pub(crate) fn hdrgm_segment(properties: &str) -> Segment {
+        let packet = format!(
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\
+             <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+             <rdf:Description rdf:about=\"\" \
+             xmlns:hdrgm=\"http://ns.adobe.com/hdr-gain-map/1.0/\" \
+             hdrgm:Version=\"1.0\" {properties}/></rdf:RDF></x:xmpmeta>"
+        );
+        Segment::new(MARKER_APP1, [XMP_NAMESPACE, packet.as_bytes()].concat())
+    }
+
+    /// Primary image XMP as libultrahdr writes it: the Ultra HDR version and
+    /// a GContainer directory, whose gain map length serialization fills in
+    pub(crate) fn primary_xmp_segment() -> Segment {
+        let packet = "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\
+             <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+             <rdf:Description \
+             xmlns:Container=\"http://ns.google.com/photos/1.0/container/\" \
+             xmlns:Item=\"http://ns.google.com/photos/1.0/container/item/\" \
+             xmlns:hdrgm=\"http://ns.adobe.com/hdr-gain-map/1.0/\" hdrgm:Version=\"1.0\">\
+             <Container:Directory><rdf:Seq>\
+             <rdf:li rdf:parseType=\"Resource\"><Container:Item \
+             Item:Semantic=\"Primary\" Item:Mime=\"image/jpeg\"/></rdf:li>\
+             <rdf:li rdf:parseType=\"Resource\"><Container:Item \
+             Item:Semantic=\"GainMap\" Item:Mime=\"image/jpeg\" Item:Length=\"0\"/></rdf:li>\
+             </rdf:Seq></Container:Directory></rdf:Description></rdf:RDF></x:xmpmeta>";
+        Segment::new(MARKER_APP1, [XMP_NAMESPACE, packet.as_bytes()].concat())
+    }
+
+    /// Both flavours, as libultrahdr writes them: gain map boost 4x, HDR
+    /// capacity 4x
+    pub(crate) fn ultra_hdr_jpeg() -> Vec<u8> {
+        gain_map_jpeg(
+            vec![primary_xmp_segment(), iso_segment(0.0, 0.0)],
+            vec![
+                hdrgm_segment("hdrgm:GainMapMax=\"2.0\" hdrgm:HDRCapacityMax=\"2.0\""),
+                iso_segment(2.0, 2.0),
+            ],
+        )
+    }
+
+    fn expected_params() -> GainMapParams {
+        GainMapParams {
+            gain_map_min: [0.0; 3],
+            gain_map_max: [2.0; 3],
+            gamma: [1.0; 3],
+            offset_sdr: [1.0 / 64.0; 3],
+            offset_hdr: [1.0 / 64.0; 3],
+            hdr_capacity_min: 0.0,
+            hdr_capacity_max: 2.0,
+            base_rendition_is_hdr: false,
+            use_base_color_space: false,
+            multi_channel: false,
+        }
+    }
+
+    fn count(image: &JpegImage, predicate: impl Fn(&Segment) -> bool) -> usize {
+        image.segments.iter().filter(|s| predicate(s)).count()
+    }
+
+    fn apply(options: UltraHdrOptions) -> MultiPictureJpeg {
+        let bytes = apply_metadata_options(ultra_hdr_jpeg(), &options).unwrap();
+        MultiPictureJpeg::parse(&bytes).unwrap()
+    }
+
+    #[test]
+    fn iso_and_xmp_describe_the_same_gain_map() {
+        let container = MultiPictureJpeg::parse(&ultra_hdr_jpeg()).unwrap();
+        let gain_map = container.gain_map().unwrap();
+        let iso = gain_map.find(MARKER_APP2, ISO_21496_NAMESPACE).unwrap();
+
+        assert_eq!(
+            GainMapParams::from_iso(iso.body(ISO_21496_NAMESPACE)).unwrap(),
+            expected_params()
+        );
+        let xmp = GainMapParams::from_xmp(&xmp_packet(gain_map).unwrap()).unwrap();
+        assert_eq!(xmp.gain_map_max, [2.0; 3]);
+        assert_eq!(xmp.hdr_capacity_max, 2.0);
+        assert_eq!(xmp.max_content_boost(), 4.0);
+    }
+
+    #[test]
+    fn default_options_keep_the_encoder_output() {
+        let bytes = ultra_hdr_jpeg();
+        let options = UltraHdrOptions::default();
+        assert!(apply_metadata_options(bytes.clone(), &options).unwrap() == bytes);
+    }
+
+    #[test]
+    fn iso_only_drops_ultra_hdr_xmp() {
+        let container = apply(UltraHdrOptions {
+            metadata_format: GainMapMetadataFormat::Iso,
+            ..Default::default()
+        });
+        for image in [&container.primary, container.gain_map().unwrap()] {
+            assert_eq!(count(image, is_gain_map_xmp), 0);
+            assert_eq!(
+                count(image, |s| s.is_app(MARKER_APP2, ISO_21496_NAMESPACE)),
+                1
+            );
+        }
+        assert_eq!(
+            GainMapParams::from_container(&container).unwrap(),
+            expected_params()
+        );
+    }
+
+    #[test]
+    fn iso_only_keeps_the_rest_of_the_primary_xmp() {
+        let mut fixture = MultiPictureJpeg::parse(&ultra_hdr_jpeg()).unwrap();
+        add_xmp_description(
+            &mut fixture.primary,
+            "<rdf:Description rdf:about=\"\" xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\" \
+             xmp:CreatorTool=\"shotHDR\"/>",
+        );
+        let options = UltraHdrOptions {
+            metadata_format: GainMapMetadataFormat::Iso,
+            ..Default::default()
+        };
+        let bytes = apply_metadata_options(fixture.to_bytes().unwrap(), &options).unwrap();
+        let container = MultiPictureJpeg::parse(&bytes).unwrap();
+
+        let xmp = xmp_packet(&container.primary).unwrap();
+        assert!(!xmp.contains("hdrgm"), "{xmp}");
+        assert!(!xmp.contains("Item:Semantic=\"GainMap\""), "{xmp}");
+        assert!(xmp.contains("<Container:Directory>"), "{xmp}");
+        assert!(xmp.contains("Item:Semantic=\"Primary\""), "{xmp}");
+        assert_eq!(xmp_attribute(&xmp, "xmp:CreatorTool"), Some("shotHDR"));
+        assert!(xmp.ends_with("</rdf:RDF></x:xmpmeta>"), "{xmp}");
+    }
+
+    #[test]
+    fn hdrgm_elements_and_attributes_are_removed() {
+        let xmp = "<rdf:Description xmlns:hdrgm=\"http://ns.adobe.com/hdr-gain-map/1.0/\" \
+                   hdrgm:Version=\"1.0\" tiff:Make=\"x\">\
+                   <hdrgm:GainMapMax><rdf:Seq><rdf:li>1</rdf:li></rdf:Seq></hdrgm:GainMapMax>\
+                   <hdrgm:Gamma>1</hdrgm:Gamma><dc:title>t</dc:title></rdf:Description>";
+        assert_eq!(
+            remove_hdrgm_attributes(&remove_hdrgm_elements(xmp)),
+            "<rdf:Description tiff:Make=\"x\"><dc:title>t</dc:title></rdf:Description>"
+        );
+    }
+
+    #[test]
+    fn xmp_only_drops_iso() {
+        let container = apply(UltraHdrOptions {
+            metadata_format: GainMapMetadataFormat::UltraHdrXmp,
+            ..Default::default()
+        });
+        for image in [&container.primary, container.gain_map().unwrap()] {
+            assert_eq!(count(image, is_gain_map_xmp), 1);
+            assert_eq!(
+                count(image, |s| s.is_app(MARKER_APP2, ISO_21496_NAMESPACE)),
+                0
+            );
+        }
+        let params = GainMapParams::from_container(&container).unwrap();
+        assert_eq!(params.gain_map_max, [2.0; 3]);
+    }
+
+    #[test]
+    fn apple_gain_map_merges_into_the_existing_xmp() {
+        let container = apply(UltraHdrOptions {
+            apple_gain_map: true,
+            ..Default::default()
+        });
+        let gain_map = container.gain_map().unwrap();
+        assert_eq!(count(gain_map, |s| s.is_app(MARKER_APP1, XMP_NAMESPACE)), 1);
+        let xmp = xmp_packet(gain_map).unwrap();
+        assert!(xmp.contains("hdrgm:GainMapMax=\"2.0\""));
+        assert_eq!(
+            xmp_attribute(&xmp, "HDRGainMap:HDRGainMapHeadroom"),
+            Some("4.000000")
+        );
+        assert!(xmp.contains(APPLE_GAIN_MAP_AUX_TYPE));
+    }
+
+    /// `Item:Length` of the gain map in the primary's GContainer directory
+    fn directory_gain_map_length(container: &MultiPictureJpeg) -> Option<usize> {
+        let xmp = xmp_packet(&container.primary)?;
+        let item = &xmp[xmp.find("Item:Semantic=\"GainMap\"")?..];
+        xmp_attribute(item, "Item:Length")?.parse().ok()
+    }
+
+    #[test]
+    fn container_directory_follows_the_gain_map_size() {
+        let fixture = MultiPictureJpeg::parse(&ultra_hdr_jpeg()).unwrap();
+        let original_len = fixture.gain_map().unwrap().encoded_len();
+        assert_eq!(directory_gain_map_length(&fixture), Some(original_len));
+
+        for options in [
+            UltraHdrOptions {
+                metadata_format: GainMapMetadataFormat::UltraHdrXmp,
+                ..Default::default()
+            },
+            UltraHdrOptions {
+                apple_gain_map: true,
+                ..Default::default()
+            },
+            UltraHdrOptions {
+                orientation: Some(ImageTransform::Rotate90),
+                ..Default::default()
+            },
+        ] {
+            let container = apply(options.clone());
+            let gain_map_len = container.gain_map().unwrap().encoded_len();
+            assert_eq!(
+                directory_gain_map_length(&container),
+                Some(gain_map_len),
+                "{options:?}"
+            );
+            assert_eq!(container.mpf_entries[1].size as usize, gain_map_len);
+        }
+    }
+
+    #[test]
+    fn xmp_values_reads_element_and_sequence_forms() {
+        let xmp = "<hdrgm:Gamma>1.5</hdrgm:Gamma>\
+                   <hdrgm:GainMapMax><rdf:Seq><rdf:li>1</rdf:li><rdf:li>2</rdf:li>\
+                   <rdf:li>3</rdf:li></rdf:Seq></hdrgm:GainMapMax>";
+        assert_eq!(xmp_values(xmp, "hdrgm:Gamma"), Some(vec![1.5]));
+        assert_eq!(
+            xmp_values(xmp, "hdrgm:GainMapMax"),
+            Some(vec![1.0, 2.0, 3.0])
+        );
+        assert_eq!(xmp_values(xmp, "hdrgm:OffsetSDR"), None);
+    }
+}
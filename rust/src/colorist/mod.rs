@@ -3,13 +3,41 @@
 //! This module handles the conversion of raw HDR screen capture data to
 //! Ultra HDR JPEG format which is compatible with Android's UltraHDR standard.
 
+pub mod adjust;
+pub mod analysis;
+pub mod colorspace;
+pub mod container;
+pub mod contrast;
+pub mod cvd;
+pub mod decode;
+pub mod diff;
+pub mod elements;
+pub mod exr;
+pub mod inspect;
+pub mod loupe;
+pub mod metadata;
+pub mod palette;
+pub mod pixels;
+pub mod render;
+pub mod resample;
+pub mod sample;
+pub mod strip;
+pub mod tonemap;
+pub mod transform;
+pub mod trim;
+pub mod visualize;
+
 use anyhow::anyhow;
 use glam::f32::{Mat3, Vec3};
 use half::prelude::*;
 use ultrahdr::{sys, Encoder, ImgLabel, RawImage};
 
+use crate::api::screen_shot_api::UltraHdrOptions;
+
 /// Convert half-float scRGB linear values to PQ (Perceptual Quantizer) values
-fn linear_to_pq(linear: f32) -> f32 {
+// m1 = 2610 / 16384 is exact in f32, clippy only counts the literal's digits
+#[allow(clippy::excessive_precision)]
+pub(crate) fn linear_to_pq(linear: f32) -> f32 {
     let pow_linear = linear.powf(0.1593017578125f32);
     let num = 0.1640625f32 * pow_linear - 0.1640625f32;
     let den = 1.0f32 + 18.6875f32 * pow_linear;
@@ -17,7 +45,7 @@ fn linear_to_pq(linear: f32) -> f32 {
 }
 
 /// scRGB to BT.2020 color matrix
-const SRGB_TO_BT2100: [f32; 9] = [
+pub(crate) const SRGB_TO_BT2100: [f32; 9] = [
     0.627409, 0.0691248, 0.0164234, 0.32926, 0.919549, 0.0880478, 0.0432719, 0.0113208, 0.895617,
 ];
 
@@ -29,12 +57,14 @@ const SRGB_TO_BT2100: [f32; 9] = [
 /// 2. HDR gain map that allows reconstruction of HDR content
 ///
 /// The output is a backwards-compatible JPEG that displays correctly on SDR screens
-/// but contains HDR information for HDR-capable displays.
+/// but contains HDR information for HDR-capable displays. `options` selects which
+/// gain-map metadata (ISO 21496-1, Ultra HDR XMP, Apple) ends up in the file.
 pub fn raw_buffer_to_ultra_hdr_jpeg(
     buf: Vec<u8>,
     frame_width: u32,
     frame_height: u32,
     mode: &str,
+    options: &UltraHdrOptions,
 ) -> anyhow::Result<Vec<u8>> {
     let width = frame_width as usize;
     let height = frame_height as usize;
@@ -94,6 +124,10 @@ pub fn raw_buffer_to_ultra_hdr_jpeg(
 
     let srgb_to_bt2100 = Mat3::from_cols_array(&SRGB_TO_BT2100);
 
+    // Brightest component in nits (MaxCLL, CTA-861.3), used to size the gain
+    // map; libultrahdr has no setter for content light levels
+    let mut max_cll = 0.0f32;
+
     for (i, pixel) in f32_data.chunks_exact(4).enumerate() {
         let r = pixel[0];
         let g = pixel[1];
@@ -125,6 +159,7 @@ pub fn raw_buffer_to_ultra_hdr_jpeg(
 
         // Scale to absolute brightness (assuming 1.0 = SDR White)
         let linear_absolute = sc_rgb * SDR_WHITE;
+        max_cll = max_cll.max(linear_absolute.max_element());
         let linear_normalized_bt2100 = linear_absolute / REC2100_MAX;
 
         // Convert Color Space (sRGB Primaries -> BT.2020 Primaries)
@@ -183,6 +218,12 @@ pub fn raw_buffer_to_ultra_hdr_jpeg(
         .set_raw_image(&mut hdr_image, ImgLabel::UHDR_HDR_IMG)
         .map_err(|e| anyhow!("Failed to set HDR image: {:?}", e))?;
 
+    // Target the content's real peak instead of the 10000 nit PQ ceiling, so the
+    // gain map range (and HDR capacity) matches what the capture actually holds
+    encoder
+        .set_target_display_peak_brightness(max_cll.clamp(pixels::SDR_WHITE_NITS, 10000.0))
+        .map_err(|e| anyhow!("Failed to set target peak brightness: {:?}", e))?;
+
     // Set output format
     encoder
         .set_output_format(sys::uhdr_codec::UHDR_CODEC_JPG)
@@ -209,5 +250,43 @@ pub fn raw_buffer_to_ultra_hdr_jpeg(
         .bytes()
         .map_err(|e| anyhow!("Failed to get bytes: {:?}", e))?;
 
-    Ok(bytes.to_vec())
+    metadata::apply_metadata_options(bytes.to_vec(), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colorist::container::MultiPictureJpeg;
+    use crate::colorist::metadata::GainMapParams;
+
+    /// HDR capacity (log2) that the encoder signalled for a flat capture
+    /// holding one highlight of `peak` times SDR white
+    fn hdr_capacity_for_peak(peak: f32) -> f32 {
+        let mut linear = vec![0.25; 32 * 32 * 4];
+        linear[..4].copy_from_slice(&[peak, peak, peak, 1.0]);
+        for alpha in linear.iter_mut().skip(3).step_by(4) {
+            *alpha = 1.0;
+        }
+        let capture = pixels::capture_from_linear("hdr_macos", 32, 32, linear);
+        let jpeg = raw_buffer_to_ultra_hdr_jpeg(
+            capture.raw_data,
+            32,
+            32,
+            &capture.mode,
+            &UltraHdrOptions::default(),
+        )
+        .unwrap();
+        let container = MultiPictureJpeg::parse(&jpeg).unwrap();
+        GainMapParams::from_container(&container)
+            .unwrap()
+            .hdr_capacity_max
+    }
+
+    #[test]
+    fn gain_map_capacity_follows_the_content_peak() {
+        for peak in [2.0f32, 4.0, 8.0] {
+            let capacity = hdr_capacity_for_peak(peak);
+            assert!((capacity - peak.log2()).abs() < 0.1, "{peak}: {capacity}");
+        }
+    }
 }
@@ -0,0 +1,236 @@
+//! Dominant colour extraction by k-means clustering in OKLab
+
+use super::{colorspace, pixels};
+use crate::api::analysis_api::{PaletteColor, SampleRegion};
+use crate::api::screen_shot_api::CaptureResult;
+
+/// Pixels clustered at most; larger regions are sampled on a regular grid
+const MAX_SAMPLES: usize = 1 << 16;
+const MAX_ITERATIONS: usize = 24;
+/// Stop once no centroid moves further than this (OKLab distance)
+const CONVERGENCE: f32 = 1e-4;
+/// Most colours one palette may ask for; k-means cost grows with the count
+const MAX_PALETTE_COLORS: u32 = 32;
+
+fn distance_squared(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+fn nearest(centroids: &[[f32; 3]], point: [f32; 3]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (i, distance_squared(*c, point)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map_or(0, |(i, _)| i)
+}
+
+/// Deterministic k-means++ seeding, so the same capture gives the same palette
+fn seed_centroids(points: &[[f32; 3]], count: usize) -> Vec<[f32; 3]> {
+    // xorshift32 with a fixed seed
+    let mut state = 0x9E37_79B9u32;
+    let mut random = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as f32 / u32::MAX as f32
+    };
+
+    let mut centroids = vec![points[(random() * (points.len() - 1) as f32) as usize]];
+    let mut distances: Vec<f32> = points
+        .iter()
+        .map(|p| distance_squared(*p, centroids[0]))
+        .collect();
+    while centroids.len() < count {
+        let total: f32 = distances.iter().sum();
+        if total <= 0.0 {
+            break; // fewer distinct colours than requested
+        }
+        let mut target = random() * total;
+        let index = distances
+            .iter()
+            .position(|d| {
+                target -= d;
+                target <= 0.0
+            })
+            .unwrap_or(points.len() - 1);
+        let centroid = points[index];
+        centroids.push(centroid);
+        for (d, p) in distances.iter_mut().zip(points) {
+            *d = d.min(distance_squared(*p, centroid));
+        }
+    }
+    centroids
+}
+
+/// The `count` most dominant colours of a region, by coverage
+pub fn extract_palette(
+    capture: &CaptureResult,
+    region: &SampleRegion,
+    count: u32,
+) -> anyhow::Result<Vec<PaletteColor>> {
+    pixels::check_region(capture, region.x, region.y, region.width, region.height)?;
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    if count > MAX_PALETTE_COLORS {
+        anyhow::bail!(
+            "Palette of {count} colours requested, at most {MAX_PALETTE_COLORS} supported"
+        );
+    }
+
+    let area = region.width as usize * region.height as usize;
+    let step = ((area as f32 / MAX_SAMPLES as f32).sqrt().ceil() as u32).max(1);
+    let mut points = Vec::with_capacity(area.min(MAX_SAMPLES * 2));
+    let mut linear_points = Vec::with_capacity(points.capacity());
+    for y in (region.y..region.y + region.height).step_by(step as usize) {
+        for x in (region.x..region.x + region.width).step_by(step as usize) {
+            let signal = pixels::signal_at(capture, x, y);
+            if signal[3] <= 0.0 {
+                continue; // fully transparent pixels have no colour
+            }
+            let linear =
+                [signal[0], signal[1], signal[2]].map(|v| pixels::signal_to_linear(v).max(0.0));
+            points.push(colorspace::linear_srgb_to_oklab(linear));
+            linear_points.push(linear);
+        }
+    }
+    if points.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut centroids = seed_centroids(&points, count as usize);
+    let mut assignment = vec![0usize; points.len()];
+    for _ in 0..MAX_ITERATIONS {
+        for (a, p) in assignment.iter_mut().zip(&points) {
+            *a = nearest(&centroids, *p);
+        }
+        let mut sums = vec![([0.0f32; 3], 0usize); centroids.len()];
+        for (a, p) in assignment.iter().zip(&points) {
+            let (sum, n) = &mut sums[*a];
+            for c in 0..3 {
+                sum[c] += p[c];
+            }
+            *n += 1;
+        }
+        let mut moved = 0.0f32;
+        for (centroid, (sum, n)) in centroids.iter_mut().zip(&sums) {
+            if *n == 0 {
+                continue;
+            }
+            let updated = sum.map(|v| v / *n as f32);
+            moved = moved.max(distance_squared(*centroid, updated).sqrt());
+            *centroid = updated;
+        }
+        if moved < CONVERGENCE {
+            break;
+        }
+    }
+
+    // Report each cluster's mean colour in linear light: exact for flat
+    // colours, where the OKLab round trip would be off by a code value
+    let mut clusters = vec![([0.0f32; 3], 0usize); centroids.len()];
+    for (a, linear) in assignment.iter().zip(&linear_points) {
+        let (sum, n) = &mut clusters[*a];
+        for c in 0..3 {
+            sum[c] += linear[c];
+        }
+        *n += 1;
+    }
+    let mut palette: Vec<PaletteColor> = clusters
+        .iter()
+        .filter(|(_, n)| *n > 0)
+        .map(|(sum, n)| {
+            let linear = sum.map(|v| v / *n as f32);
+            PaletteColor {
+                linear: linear.to_vec(),
+                coverage: *n as f32 * 100.0 / points.len() as f32,
+                hex: colorspace::css_hex(colorspace::sdr_signal(linear)),
+                css_display_p3: colorspace::css_display_p3(linear),
+                css_oklch: colorspace::css_oklch(linear),
+            }
+        })
+        .collect();
+    palette.sort_by(|a, b| b.coverage.total_cmp(&a.coverage));
+    Ok(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn whole(capture: &CaptureResult) -> SampleRegion {
+        SampleRegion {
+            x: 0,
+            y: 0,
+            width: capture.frame_width,
+            height: capture.frame_height,
+        }
+    }
+
+    /// Smooth gradient with no obvious clusters, so seeding matters
+    fn gradient(width: u32, height: u32) -> CaptureResult {
+        let mut linear = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let (u, v) = (x as f32 / width as f32, y as f32 / height as f32);
+                linear.extend([u, v, 1.0 - u * v, 1.0]);
+            }
+        }
+        pixels::capture_from_linear("hdr_macos", width, height, linear)
+    }
+
+    #[test]
+    fn flat_colours_are_reported_with_their_coverage() {
+        let mut linear = [0.8, 0.1, 0.1, 1.0].repeat(12);
+        linear.extend([0.1, 0.1, 0.8, 1.0].repeat(4));
+        let capture = pixels::capture_from_linear("hdr_macos", 4, 4, linear);
+        let palette = extract_palette(&capture, &whole(&capture), 4).unwrap();
+
+        assert_eq!(palette.len(), 2, "only two distinct colours");
+        assert_eq!(palette[0].coverage, 75.0);
+        assert_eq!(palette[1].coverage, 25.0);
+        for (color, expected) in palette.iter().zip([[0.8, 0.1, 0.1], [0.1, 0.1, 0.8]]) {
+            for (a, b) in color.linear.iter().zip(expected) {
+                assert!((a - b).abs() < 2e-3, "{:?}", color.linear);
+            }
+        }
+    }
+
+    #[test]
+    fn same_capture_gives_the_same_palette() {
+        let capture = gradient(64, 48);
+        let first = extract_palette(&capture, &whole(&capture), 6).unwrap();
+        let second = extract_palette(&capture, &whole(&capture), 6).unwrap();
+        assert_eq!(first.len(), 6);
+        for (a, b) in first.iter().zip(&second) {
+            assert_eq!(a.linear, b.linear);
+            assert_eq!(a.coverage, b.coverage);
+        }
+        let total: f32 = first.iter().map(|c| c.coverage).sum();
+        assert!((total - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn palette_size_is_capped() {
+        let capture = gradient(64, 48);
+        let region = whole(&capture);
+        assert_eq!(
+            extract_palette(&capture, &region, MAX_PALETTE_COLORS)
+                .unwrap()
+                .len(),
+            MAX_PALETTE_COLORS as usize
+        );
+        assert!(extract_palette(&capture, &region, MAX_PALETTE_COLORS + 1).is_err());
+        assert!(extract_palette(&capture, &region, u32::MAX).is_err());
+    }
+
+    #[test]
+    fn transparent_pixels_are_ignored() {
+        let linear = [0.5, 0.5, 0.5, 0.0].repeat(4);
+        let capture = pixels::capture_from_linear("hdr_macos", 2, 2, linear);
+        assert!(extract_palette(&capture, &whole(&capture), 3)
+            .unwrap()
+            .is_empty());
+    }
+}
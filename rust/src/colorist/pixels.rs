@@ -0,0 +1,226 @@
+//! Pixel-level helpers shared by the colorist passes
+//!
+//! FP16 capture buffers hold gamma-encoded values with sRGB primaries where
+//! 1.0 is SDR white and values above 1.0 are HDR highlights (see the encoder
+//! in the parent module). Processing happens on linear light derived from
+//! those values and is written back in the same encoding.
+
+use glam::f32::{Mat3, Vec3};
+use half::prelude::*;
+
+use crate::api::screen_shot_api::CaptureResult;
+
+/// Mode tag of 8-bit BGRA captures (SDR displays on macOS)
+pub const MODE_SDR_MACOS: &str = "sdr_macos";
+
+/// Reference white for SDR content in HDR (ITU-R BT.2408)
+pub const SDR_WHITE_NITS: f32 = 203.0;
+
+/// Display gamma assumed for FP16 capture buffers
+pub const CAPTURE_GAMMA: f32 = 2.2;
+
+/// Linear BT.2020 to linear sRGB/BT.709 (column-major)
+pub const BT2020_TO_SRGB: [f32; 9] = [
+    1.6605, -0.1246, -0.0182, -0.5876, 1.1329, -0.1006, -0.0728, -0.0083, 1.1187,
+];
+
+/// Linear Display P3 to linear sRGB/BT.709 (column-major)
+pub const P3_TO_SRGB: [f32; 9] = [
+    1.2249, -0.0420, -0.0197, -0.2247, 1.0419, -0.0786, 0.0, 0.0, 1.0979,
+];
+
+/// Linear sRGB/BT.709 to linear Display P3 (column-major)
+pub const SRGB_TO_P3: [f32; 9] = [
+    0.8225, 0.0332, 0.0171, 0.1774, 0.9669, 0.0724, 0.0, 0.0, 0.9108,
+];
+
+/// Gamma-encoded capture value to linear light, keeping the sign of
+/// out-of-gamut scRGB values
+pub fn signal_to_linear(v: f32) -> f32 {
+    v.signum() * v.abs().powf(CAPTURE_GAMMA)
+}
+
+/// Linear light back to the capture's gamma encoding
+pub fn linear_to_signal(v: f32) -> f32 {
+    v.signum() * v.abs().powf(1.0 / CAPTURE_GAMMA)
+}
+
+/// Apply a column-major 3x3 gamut matrix to every RGBA pixel in place
+pub fn convert_gamut(pixels: &mut [f32], matrix: &[f32; 9]) {
+    let matrix = Mat3::from_cols_array(matrix);
+    for pixel in pixels.chunks_exact_mut(4) {
+        let rgb = matrix.mul_vec3(Vec3::new(pixel[0], pixel[1], pixel[2]));
+        pixel[..3].copy_from_slice(&rgb.to_array());
+    }
+}
+
+/// Pack f32 RGBA values as little-endian FP16 bytes; values beyond the FP16
+/// range saturate at ±65504 rather than becoming infinite
+pub fn f32_to_f16_bytes(pixels: &[f32]) -> Vec<u8> {
+    let limit = f16::MAX.to_f32();
+    let clamped: Vec<f32> = pixels.iter().map(|v| v.clamp(-limit, limit)).collect();
+    let mut halves = vec![f16::ZERO; pixels.len()];
+    halves.convert_from_f32_slice(&clamped);
+    halves.iter().flat_map(|h| h.to_le_bytes()).collect()
+}
+
+/// sRGB EOTF: encoded value in [0, 1] to linear light
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Inverse sRGB EOTF: linear light to encoded value, clamped to [0, 1]
+pub fn linear_to_srgb(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Linear light to an 8-bit sRGB code value
+pub fn linear_to_srgb8(v: f32) -> u8 {
+    (linear_to_srgb(v) * 255.0).round() as u8
+}
+
+/// Unpack a capture buffer into RGBA f32 values in the capture's encoding
+///
+/// FP16 modes are returned as stored; `sdr_macos` BGRA8 is reordered and
+/// normalised to [0, 1].
+pub fn decode_signal(capture: &CaptureResult) -> anyhow::Result<Vec<f32>> {
+    let num_values = capture.frame_width as usize * capture.frame_height as usize * 4;
+    if capture.mode == MODE_SDR_MACOS {
+        if capture.raw_data.len() != num_values {
+            anyhow::bail!("Invalid buffer size for sdr_macos (BGRA)");
+        }
+        return Ok(capture
+            .raw_data
+            .chunks_exact(4)
+            .flat_map(|p| [p[2], p[1], p[0], p[3]].map(|v| v as f32 / 255.0))
+            .collect());
+    }
+
+    if capture.raw_data.len() != num_values * 2 {
+        anyhow::bail!(
+            "Invalid buffer size for {}x{} FP16 capture: {} bytes",
+            capture.frame_width,
+            capture.frame_height,
+            capture.raw_data.len()
+        );
+    }
+    let halves: Vec<f16> = capture
+        .raw_data
+        .chunks_exact(2)
+        .map(|c| f16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    let mut values = vec![0.0f32; num_values];
+    halves.convert_to_f32_slice(&mut values);
+    Ok(values)
+}
+
+/// Unpack a capture buffer into linear RGBA (sRGB primaries, 1.0 = SDR white)
+pub fn decode_linear(capture: &CaptureResult) -> anyhow::Result<Vec<f32>> {
+    let mut values = decode_signal(capture)?;
+    for pixel in values.chunks_exact_mut(4) {
+        for v in &mut pixel[..3] {
+            *v = signal_to_linear(*v);
+        }
+    }
+    Ok(values)
+}
+
+/// Bytes per pixel of a capture buffer in `mode`
+pub fn bytes_per_pixel(mode: &str) -> usize {
+    if mode == MODE_SDR_MACOS {
+        4
+    } else {
+        8
+    }
+}
+
+/// Read one pixel as RGBA in the capture's encoding, without unpacking the
+/// whole buffer; the caller guarantees (x, y) is inside the frame
+pub fn signal_at(capture: &CaptureResult, x: u32, y: u32) -> [f32; 4] {
+    let bpp = bytes_per_pixel(&capture.mode);
+    let offset = (y as usize * capture.frame_width as usize + x as usize) * bpp;
+    let bytes = &capture.raw_data[offset..offset + bpp];
+    if bpp == 4 {
+        [bytes[2], bytes[1], bytes[0], bytes[3]].map(|v| v as f32 / 255.0)
+    } else {
+        std::array::from_fn(|c| f16::from_le_bytes([bytes[c * 2], bytes[c * 2 + 1]]).to_f32())
+    }
+}
+
+/// Whether any pixel is less than fully opaque
+pub fn has_transparency(capture: &CaptureResult) -> bool {
+    let bpp = bytes_per_pixel(&capture.mode);
+    capture.raw_data.chunks_exact(bpp).any(|pixel| {
+        if bpp == 4 {
+            pixel[3] < 255
+        } else {
+            f16::from_le_bytes([pixel[6], pixel[7]]).to_f32() < 1.0
+        }
+    })
+}
+
+/// Fail unless the rectangle is non-empty and lies inside the frame
+pub fn check_region(
+    capture: &CaptureResult,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> anyhow::Result<()> {
+    let inside = x
+        .checked_add(width)
+        .is_some_and(|right| right <= capture.frame_width)
+        && y.checked_add(height)
+            .is_some_and(|bottom| bottom <= capture.frame_height);
+    if width == 0 || height == 0 || !inside {
+        anyhow::bail!(
+            "Region ({x},{y},{width},{height}) outside frame ({}x{})",
+            capture.frame_width,
+            capture.frame_height
+        );
+    }
+    Ok(())
+}
+
+/// Pack RGBA values in the capture's encoding into a buffer for `mode`
+pub fn encode_signal(mode: &str, values: &[f32]) -> Vec<u8> {
+    if mode == MODE_SDR_MACOS {
+        values
+            .chunks_exact(4)
+            .flat_map(|p| {
+                [p[2], p[1], p[0], p[3]].map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8)
+            })
+            .collect()
+    } else {
+        f32_to_f16_bytes(values)
+    }
+}
+
+/// Build a capture in `mode` from linear RGBA (sRGB primaries, 1.0 = SDR white)
+pub fn capture_from_linear(
+    mode: &str,
+    width: u32,
+    height: u32,
+    mut linear: Vec<f32>,
+) -> CaptureResult {
+    for pixel in linear.chunks_exact_mut(4) {
+        for v in &mut pixel[..3] {
+            *v = linear_to_signal(*v);
+        }
+    }
+    CaptureResult {
+        mode: mode.to_string(),
+        raw_data: encode_signal(mode, &linear),
+        frame_width: width,
+        frame_height: height,
+    }
+}
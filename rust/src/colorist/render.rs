@@ -0,0 +1,258 @@
+//! Gain map application at an arbitrary display headroom
+//!
+//! Implements the ISO 21496-1 / Ultra HDR rendering model in Rust so previews
+//! and tests don't depend on the decoder's own display-boost handling.
+
+use anyhow::{anyhow, bail};
+use image::{ImageFormat, RgbImage};
+
+use super::container::MultiPictureJpeg;
+use super::metadata::GainMapParams;
+use super::pixels;
+use crate::api::hdr_image_api::HeadroomRendering;
+
+/// Gain map weight for a display with `headroom` (linear, 1.0 = SDR only)
+///
+/// Negative weights never occur: the direction is handled by treating the base
+/// rendition as the starting point and the alternate as the target.
+pub fn gain_map_weight(params: &GainMapParams, headroom: f32) -> f32 {
+    let (base, alternate) = base_and_alternate_headroom(params);
+    let target = headroom.max(1.0).log2();
+    if (alternate - base).abs() < f32::EPSILON {
+        let reached = if alternate >= base {
+            target >= alternate
+        } else {
+            target <= alternate
+        };
+        return if reached { 1.0 } else { 0.0 };
+    }
+    ((target - base) / (alternate - base)).clamp(0.0, 1.0)
+}
+
+/// log2 headroom of the base and alternate renditions
+fn base_and_alternate_headroom(params: &GainMapParams) -> (f32, f32) {
+    if params.base_rendition_is_hdr {
+        (params.hdr_capacity_max, params.hdr_capacity_min)
+    } else {
+        (params.hdr_capacity_min, params.hdr_capacity_max)
+    }
+}
+
+/// Offsets applied to the base and alternate renditions
+fn base_and_alternate_offset(params: &GainMapParams) -> ([f32; 3], [f32; 3]) {
+    if params.base_rendition_is_hdr {
+        (params.offset_hdr, params.offset_sdr)
+    } else {
+        (params.offset_sdr, params.offset_hdr)
+    }
+}
+
+/// Bilinearly sample the gain map at the position of base pixel (x, y)
+fn sample_gain_map(gain_map: &RgbImage, x: u32, y: u32, width: u32, height: u32) -> [f32; 3] {
+    let (gw, gh) = gain_map.dimensions();
+    let gx = ((x as f32 + 0.5) * gw as f32 / width as f32 - 0.5).clamp(0.0, (gw - 1) as f32);
+    let gy = ((y as f32 + 0.5) * gh as f32 / height as f32 - 0.5).clamp(0.0, (gh - 1) as f32);
+    let (x0, y0) = (gx.floor() as u32, gy.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(gw - 1), (y0 + 1).min(gh - 1));
+    let (fx, fy) = (gx - x0 as f32, gy - y0 as f32);
+
+    let mut out = [0.0; 3];
+    for (c, value) in out.iter_mut().enumerate() {
+        let p = |px: u32, py: u32| gain_map.get_pixel(px, py)[c] as f32 / 255.0;
+        let top = p(x0, y0) * (1.0 - fx) + p(x1, y0) * fx;
+        let bottom = p(x0, y1) * (1.0 - fx) + p(x1, y1) * fx;
+        *value = top * (1.0 - fy) + bottom * fy;
+    }
+    out
+}
+
+/// A rendering before display encoding
+pub struct LinearRendering {
+    pub width: u32,
+    pub height: u32,
+    pub headroom: f32,
+    pub gain_map_weight: f32,
+    /// Linear RGBA in the base image's primaries, 1.0 = SDR white, clipped
+    /// to the display's headroom
+    pub pixels: Vec<f32>,
+}
+
+/// Render a gain-map JPEG as it would appear on a display with `headroom`
+///
+/// The RGBA8 output is sRGB-encoded relative to the display's peak, the way
+/// the display drives its pixels: 255 is `headroom` times SDR white.
+pub fn render_at_headroom(jpeg: &[u8], headroom: f32) -> anyhow::Result<HeadroomRendering> {
+    let rendering = render_container(&MultiPictureJpeg::parse(jpeg)?, headroom)?;
+    let scale = 1.0 / rendering.headroom;
+    let rgba = rendering
+        .pixels
+        .chunks_exact(4)
+        .flat_map(|p| {
+            let [r, g, b] = [p[0], p[1], p[2]].map(|v| pixels::linear_to_srgb8(v * scale));
+            [r, g, b, (p[3].clamp(0.0, 1.0) * 255.0).round() as u8]
+        })
+        .collect();
+    Ok(HeadroomRendering {
+        width: rendering.width,
+        height: rendering.height,
+        headroom: rendering.headroom,
+        gain_map_weight: rendering.gain_map_weight,
+        rgba,
+    })
+}
+
+/// Render an already parsed file in linear light
+///
+/// Headrooms below 1.0 render as SDR; a non-finite headroom is an error.
+pub fn render_container(
+    container: &MultiPictureJpeg,
+    headroom: f32,
+) -> anyhow::Result<LinearRendering> {
+    if !headroom.is_finite() {
+        bail!("Display headroom must be finite, got {headroom}");
+    }
+    let params = GainMapParams::from_container(container)?;
+
+    let base =
+        image::load_from_memory_with_format(&container.primary.to_bytes()?, ImageFormat::Jpeg)?
+            .to_rgba8();
+    let gain_map_bytes = container
+        .gain_map()
+        .ok_or_else(|| anyhow!("Image has no gain map"))?
+        .to_bytes()?;
+    let gain_map =
+        image::load_from_memory_with_format(&gain_map_bytes, ImageFormat::Jpeg)?.to_rgb8();
+
+    let (width, height) = base.dimensions();
+    let weight = gain_map_weight(&params, headroom);
+    let (base_offset, alternate_offset) = base_and_alternate_offset(&params);
+    let peak = headroom.max(1.0);
+
+    let mut out = Vec::with_capacity(width as usize * height as usize * 4);
+    for (x, y, pixel) in base.enumerate_pixels() {
+        let gain = sample_gain_map(&gain_map, x, y, width, height);
+        for c in 0..3 {
+            let base_linear = pixels::srgb_to_linear(pixel[c] as f32 / 255.0);
+            let recovery = gain[c].powf(1.0 / params.gamma[c]);
+            let log_boost =
+                params.gain_map_min[c] * (1.0 - recovery) + params.gain_map_max[c] * recovery;
+            let value =
+                (base_linear + base_offset[c]) * (log_boost * weight).exp2() - alternate_offset[c];
+            out.push(value.clamp(0.0, peak));
+        }
+        out.push(pixel[3] as f32 / 255.0);
+    }
+
+    Ok(LinearRendering {
+        width,
+        height,
+        headroom: peak,
+        gain_map_weight: weight,
+        pixels: out,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colorist::container::JpegImage;
+    use crate::colorist::metadata::tests::ultra_hdr_jpeg;
+
+    fn container() -> MultiPictureJpeg {
+        MultiPictureJpeg::parse(&ultra_hdr_jpeg()).unwrap()
+    }
+
+    /// Decoded 8-bit RGBA of one image of the file
+    fn decode(image: &JpegImage) -> image::RgbaImage {
+        image::load_from_memory_with_format(&image.to_bytes().unwrap(), ImageFormat::Jpeg)
+            .unwrap()
+            .to_rgba8()
+    }
+
+    #[test]
+    fn weight_interpolates_in_log2_headroom() {
+        // HDR capacity from 1x to 4x
+        let params = GainMapParams::from_container(&container()).unwrap();
+        assert_eq!(gain_map_weight(&params, 0.5), 0.0);
+        assert_eq!(gain_map_weight(&params, 1.0), 0.0);
+        assert_eq!(gain_map_weight(&params, 2.0), 0.5);
+        assert_eq!(gain_map_weight(&params, 4.0), 1.0);
+        assert_eq!(gain_map_weight(&params, 16.0), 1.0);
+    }
+
+    #[test]
+    fn hdr_base_is_mapped_down_for_sdr_displays() {
+        let params = GainMapParams {
+            base_rendition_is_hdr: true,
+            ..GainMapParams::from_container(&container()).unwrap()
+        };
+        assert_eq!(gain_map_weight(&params, 1.0), 1.0);
+        assert_eq!(gain_map_weight(&params, 2.0), 0.5);
+        assert_eq!(gain_map_weight(&params, 4.0), 0.0);
+    }
+
+    #[test]
+    fn sdr_display_sees_the_base_image() {
+        let container = container();
+        let rendering = render_container(&container, 1.0).unwrap();
+        let base = decode(&container.primary);
+
+        assert_eq!(rendering.gain_map_weight, 0.0);
+        assert_eq!((rendering.width, rendering.height), base.dimensions());
+        for (rendered, base) in rendering.pixels.chunks_exact(4).zip(base.pixels()) {
+            for c in 0..3 {
+                let expected = pixels::srgb_to_linear(base[c] as f32 / 255.0);
+                assert!((rendered[c] - expected).abs() < 1e-4);
+            }
+            assert_eq!(rendered[3], 1.0);
+        }
+    }
+
+    #[test]
+    fn full_headroom_applies_the_whole_boost() {
+        let container = container();
+        let rendering = render_container(&container, 4.0).unwrap();
+        let base = decode(&container.primary);
+        let gain_map = decode(container.gain_map().unwrap());
+
+        assert_eq!(rendering.gain_map_weight, 1.0);
+        assert_eq!(rendering.headroom, 4.0);
+        let offset = 1.0 / 64.0;
+        for ((rendered, base), gain) in rendering
+            .pixels
+            .chunks_exact(4)
+            .zip(base.pixels())
+            .zip(gain_map.pixels())
+        {
+            for c in 0..3 {
+                // Linear gamma, log2 boost from 0 to 2
+                let boost = (2.0 * gain[c] as f32 / 255.0).exp2();
+                let sdr = pixels::srgb_to_linear(base[c] as f32 / 255.0);
+                let expected = (sdr + offset) * boost - offset;
+                assert!((rendered[c] - expected).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn rgba_at_sdr_headroom_is_the_base_image() {
+        let bytes = ultra_hdr_jpeg();
+        let rendering = render_at_headroom(&bytes, 1.0).unwrap();
+        let base = decode(&MultiPictureJpeg::parse(&bytes).unwrap().primary);
+
+        assert_eq!(rendering.headroom, 1.0);
+        for (rendered, base) in rendering.rgba.chunks_exact(4).zip(base.pixels()) {
+            for c in 0..4 {
+                assert!(rendered[c].abs_diff(base[c]) <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn non_finite_headroom_is_rejected() {
+        let bytes = ultra_hdr_jpeg();
+        for headroom in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert!(render_at_headroom(&bytes, headroom).is_err());
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Separable resampling of linear-light RGBA
+//!
+//! Filtering in linear light with premultiplied alpha keeps thin text and
+//! small highlights at their true brightness when downscaling; averaging
+//! gamma-encoded values darkens them.
+
+use anyhow::bail;
+
+use super::pixels;
+use crate::api::processing_api::ResizeFilter;
+use crate::api::screen_shot_api::CaptureResult;
+
+fn support(filter: ResizeFilter) -> f32 {
+    match filter {
+        ResizeFilter::Nearest | ResizeFilter::Box => 0.5,
+        ResizeFilter::Mitchell => 2.0,
+        ResizeFilter::Lanczos3 => 3.0,
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        1.0
+    } else {
+        let x = x * std::f32::consts::PI;
+        x.sin() / x
+    }
+}
+
+fn kernel(filter: ResizeFilter, x: f32) -> f32 {
+    let x = x.abs();
+    match filter {
+        ResizeFilter::Nearest | ResizeFilter::Box => (x < 0.5) as u8 as f32,
+        ResizeFilter::Mitchell => {
+            // Mitchell–Netravali with B = C = 1/3
+            const B: f32 = 1.0 / 3.0;
+            const C: f32 = 1.0 / 3.0;
+            if x < 1.0 {
+                ((12.0 - 9.0 * B - 6.0 * C) * x * x * x
+                    + (-18.0 + 12.0 * B + 6.0 * C) * x * x
+                    + (6.0 - 2.0 * B))
+                    / 6.0
+            } else if x < 2.0 {
+                ((-B - 6.0 * C) * x * x * x
+                    + (6.0 * B + 30.0 * C) * x * x
+                    + (-12.0 * B - 48.0 * C) * x
+                    + (8.0 * B + 24.0 * C))
+                    / 6.0
+            } else {
+                0.0
+            }
+        }
+        ResizeFilter::Lanczos3 => {
+            if x < 3.0 {
+                sinc(x) * sinc(x / 3.0)
+            } else {
+                0.0
+            }
+        }
+    }
+}
+
+/// Source taps of one output sample: first index and normalised weights
+struct Taps {
+    start: usize,
+    weights: Vec<f32>,
+}
+
+fn taps(filter: ResizeFilter, source: usize, target: usize) -> Vec<Taps> {
+    let ratio = source as f32 / target as f32;
+    // Widen the kernel when shrinking so it also acts as the low-pass filter
+    let scale = ratio.max(1.0);
+    let radius = support(filter) * scale;
+    (0..target)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * ratio;
+            if filter == ResizeFilter::Nearest {
+                let nearest = (center as usize).min(source - 1);
+                return Taps {
+                    start: nearest,
+                    weights: vec![1.0],
+                };
+            }
+            let start = (center - radius).floor().max(0.0) as usize;
+            let end = ((center + radius).ceil() as usize).min(source);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|j| kernel(filter, (j as f32 + 0.5 - center) / scale))
+                .collect();
+            let sum: f32 = weights.iter().sum();
+            if sum.abs() > f32::EPSILON {
+                weights.iter_mut().for_each(|w| *w /= sum);
+            } else {
+                // Box upscaling can fall between taps; use the nearest one
+                let nearest = (center as usize).min(source - 1);
+                return Taps {
+                    start: nearest,
+                    weights: vec![1.0],
+                };
+            }
+            Taps { start, weights }
+        })
+        .collect()
+}
+
+/// Resample premultiplied RGBA along one axis, keeping the other
+fn resample_axis(
+    input: &[f32],
+    (width, height): (usize, usize),
+    taps: &[Taps],
+    horizontal: bool,
+) -> Vec<f32> {
+    let (out_width, out_height) = if horizontal {
+        (taps.len(), height)
+    } else {
+        (width, taps.len())
+    };
+    let mut out = vec![0.0f32; out_width * out_height * 4];
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let (tap, fixed) = if horizontal {
+                (&taps[x], y)
+            } else {
+                (&taps[y], x)
+            };
+            let mut sum = [0.0f32; 4];
+            for (k, w) in tap.weights.iter().enumerate() {
+                let source = tap.start + k;
+                let index = if horizontal {
+                    (fixed * width + source) * 4
+                } else {
+                    (source * width + fixed) * 4
+                };
+                for c in 0..4 {
+                    sum[c] += input[index + c] * w;
+                }
+            }
+            let index = (y * out_width + x) * 4;
+            out[index..index + 4].copy_from_slice(&sum);
+        }
+    }
+    out
+}
+
+pub fn resize(
+    capture: &CaptureResult,
+    width: u32,
+    height: u32,
+    filter: ResizeFilter,
+) -> anyhow::Result<CaptureResult> {
+    if width == 0 || height == 0 {
+        bail!("Cannot resize to {width}x{height}");
+    }
+    if capture.frame_width == 0 || capture.frame_height == 0 {
+        bail!("Cannot resize an empty capture");
+    }
+    let source = (capture.frame_width as usize, capture.frame_height as usize);
+
+    let mut linear = pixels::decode_linear(capture)?;
+    for pixel in linear.chunks_exact_mut(4) {
+        let alpha = pixel[3];
+        pixel[..3].iter_mut().for_each(|v| *v *= alpha);
+    }
+
+    let horizontal = taps(filter, source.0, width as usize);
+    let vertical = taps(filter, source.1, height as usize);
+    let linear = resample_axis(&linear, source, &horizontal, true);
+    let mut linear = resample_axis(&linear, (width as usize, source.1), &vertical, false);
+
+    for pixel in linear.chunks_exact_mut(4) {
+        // Colour keeps its sign (scRGB uses negatives for wide gamut), but
+        // alpha ringing from Lanczos / Mitchell must stay in range
+        let alpha = pixel[3].clamp(0.0, 1.0);
+        pixel[3] = alpha;
+        for v in &mut pixel[..3] {
+            *v = if alpha > 0.0 { *v / alpha } else { 0.0 };
+        }
+    }
+    Ok(pixels::capture_from_linear(
+        &capture.mode,
+        width,
+        height,
+        linear,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// FP16 capture from linear RGBA
+    fn capture(width: u32, height: u32, linear: &[[f32; 4]]) -> CaptureResult {
+        pixels::capture_from_linear("hdr_macos", width, height, linear.concat())
+    }
+
+    fn linear(capture: &CaptureResult) -> Vec<[f32; 4]> {
+        pixels::decode_linear(capture)
+            .unwrap()
+            .chunks_exact(4)
+            .map(|p| [p[0], p[1], p[2], p[3]])
+            .collect()
+    }
+
+    fn assert_close(actual: [f32; 4], expected: [f32; 4]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 0.01, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn box_downscale_averages_in_linear_light() {
+        let white = [1.0, 1.0, 1.0, 1.0];
+        let black = [0.0, 0.0, 0.0, 1.0];
+        let source = capture(2, 2, &[white, black, black, white]);
+
+        let resized = resize(&source, 1, 1, ResizeFilter::Box).unwrap();
+        assert_eq!((resized.frame_width, resized.frame_height), (1, 1));
+        assert_close(linear(&resized)[0], [0.5, 0.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn transparent_pixels_do_not_bleed_colour() {
+        let clear_red = [1.0, 0.0, 0.0, 0.0];
+        let green = [0.0, 1.0, 0.0, 1.0];
+        let source = capture(2, 1, &[clear_red, green]);
+
+        let resized = resize(&source, 1, 1, ResizeFilter::Box).unwrap();
+        assert_close(linear(&resized)[0], [0.0, 1.0, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn flat_hdr_content_keeps_its_level() {
+        let highlight = [4.0, 2.0, 1.0, 1.0];
+        let source = capture(7, 5, &[highlight; 35]);
+        for filter in [ResizeFilter::Mitchell, ResizeFilter::Lanczos3] {
+            for (width, height) in [(3, 2), (16, 11)] {
+                let resized = resize(&source, width, height, filter).unwrap();
+                for pixel in linear(&resized) {
+                    assert_close(pixel, highlight);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_upscale_repeats_pixels_exactly() {
+        let source = pixels::capture_from_linear(
+            pixels::MODE_SDR_MACOS,
+            2,
+            1,
+            [[0.2, 0.4, 0.6, 1.0], [0.9, 0.1, 0.3, 1.0]].concat(),
+        );
+        let resized = resize(&source, 4, 2, ResizeFilter::Nearest).unwrap();
+
+        let (a, b) = (&source.raw_data[..4], &source.raw_data[4..]);
+        let row = [a, a, b, b].concat();
+        assert!(resized.raw_data == [row.as_slice(), &row].concat());
+    }
+
+    #[test]
+    fn zero_size_is_rejected() {
+        let source = capture(2, 2, &[[0.5, 0.5, 0.5, 1.0]; 4]);
+        assert!(resize(&source, 0, 2, ResizeFilter::Box).is_err());
+        assert!(resize(&source, 2, 0, ResizeFilter::Box).is_err());
+    }
+}
@@ -0,0 +1,69 @@
+//! HDR-aware eyedropper
+
+use super::{colorspace, pixels, tonemap};
+use crate::api::analysis_api::PixelSample;
+use crate::api::screen_shot_api::CaptureResult;
+
+/// Average the (2r+1)² window around (x, y) in linear light
+///
+/// Returns linear RGBA and the number of pixels that fell inside the frame.
+pub fn average_linear(
+    capture: &CaptureResult,
+    x: u32,
+    y: u32,
+    radius: u32,
+) -> anyhow::Result<([f32; 4], u32)> {
+    if x >= capture.frame_width || y >= capture.frame_height {
+        anyhow::bail!(
+            "Sample point ({x},{y}) outside frame ({}x{})",
+            capture.frame_width,
+            capture.frame_height
+        );
+    }
+    let x0 = x.saturating_sub(radius);
+    let y0 = y.saturating_sub(radius);
+    let x1 = x.saturating_add(radius).min(capture.frame_width - 1);
+    let y1 = y.saturating_add(radius).min(capture.frame_height - 1);
+
+    let mut sum = [0.0f32; 4];
+    for sy in y0..=y1 {
+        for sx in x0..=x1 {
+            let signal = pixels::signal_at(capture, sx, sy);
+            for c in 0..3 {
+                sum[c] += pixels::signal_to_linear(signal[c]);
+            }
+            sum[3] += signal[3];
+        }
+    }
+    let count = (x1 - x0 + 1) * (y1 - y0 + 1);
+    Ok((sum.map(|v| v / count as f32), count))
+}
+
+/// Colour readout for the pixel (or averaged window) at (x, y)
+pub fn sample_pixel(
+    capture: &CaptureResult,
+    x: u32,
+    y: u32,
+    radius: u32,
+) -> anyhow::Result<PixelSample> {
+    let (linear, sample_count) = average_linear(capture, x, y, radius)?;
+    let rgb = [linear[0], linear[1], linear[2]];
+    let peak = rgb[0].max(rgb[1]).max(rgb[2]);
+
+    let sdr_signal = colorspace::sdr_signal(rgb);
+
+    Ok(PixelSample {
+        x,
+        y,
+        radius,
+        sample_count,
+        linear: linear.to_vec(),
+        nits: tonemap::luminance(rgb) * pixels::SDR_WHITE_NITS,
+        is_hdr: peak > 1.0,
+        hex: colorspace::css_hex(sdr_signal),
+        css_rgb: colorspace::css_rgb(sdr_signal),
+        css_display_p3: colorspace::css_display_p3(rgb),
+        css_rec2100_pq: colorspace::css_rec2100_pq(rgb),
+        css_oklch: colorspace::css_oklch(rgb),
+    })
+}
@@ -0,0 +1,218 @@
+//! Conversion of gain-map JPEGs into plain SDR images
+//!
+//! Some destinations mangle or reject Ultra HDR, so the output carries no MPF
+//! index, no secondary image and no gain-map XMP / ISO 21496-1 metadata.
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageEncoder, ImageFormat};
+
+use super::container::{
+    JpegImage, MultiPictureJpeg, ISO_21496_NAMESPACE, MARKER_APP2, MPF_NAMESPACE,
+};
+use super::metadata::{self, GainMapParams};
+use super::{pixels, render, tonemap};
+use crate::api::hdr_image_api::{SdrImageFormat, StripHdrOptions, ToneMapper};
+use crate::api::screen_shot_api::CaptureResult;
+
+pub fn strip_hdr(jpeg: &[u8], options: &StripHdrOptions) -> anyhow::Result<Vec<u8>> {
+    let container = MultiPictureJpeg::parse(jpeg)?;
+
+    let Some(mapper) = options.tone_mapper else {
+        let primary = sdr_primary(&container.primary);
+        return match options.format {
+            // The primary image is already a valid SDR JPEG: copy its scan data as is
+            SdrImageFormat::Jpeg => primary.to_bytes(),
+            SdrImageFormat::Png => {
+                let rgba =
+                    image::load_from_memory_with_format(&primary.to_bytes()?, ImageFormat::Jpeg)?
+                        .to_rgba8();
+                let (width, height) = rgba.dimensions();
+                encode_sdr(rgba.as_raw(), width, height, options)
+            }
+        };
+    };
+
+    // Re-render the full HDR rendition, then tone map it back into SDR range
+    let params = GainMapParams::from_container(&container)?;
+    let peak = params.hdr_capacity_max.exp2();
+    let rendering = render::render_container(&container, peak)?;
+    let rgba: Vec<u8> = rendering
+        .pixels
+        .chunks_exact(4)
+        .flat_map(|p| {
+            let [r, g, b] =
+                tonemap::tone_map([p[0], p[1], p[2]], mapper, peak).map(pixels::linear_to_srgb8);
+            [r, g, b, (p[3].clamp(0.0, 1.0) * 255.0).round() as u8]
+        })
+        .collect();
+    encode_sdr(&rgba, rendering.width, rendering.height, options)
+}
+
+/// Encode a capture as SDR JPEG / PNG, tone mapping with `options.tone_mapper`
+/// (clipping if unset) up to the capture's brightest pixel
+pub fn capture_to_sdr(
+    capture: &CaptureResult,
+    options: &StripHdrOptions,
+    background: Option<[f32; 3]>,
+) -> anyhow::Result<Vec<u8>> {
+    let capture = if options.format == SdrImageFormat::Jpeg && pixels::has_transparency(capture) {
+        crate::editor::crop::flatten(capture, background.unwrap_or([1.0; 3]))?
+    } else {
+        capture.clone()
+    };
+    let linear = pixels::decode_linear(&capture)?;
+    let peak = linear
+        .chunks_exact(4)
+        .map(|p| p[0].max(p[1]).max(p[2]))
+        .fold(1.0f32, f32::max);
+    let mapper = options.tone_mapper.unwrap_or(ToneMapper::Clip);
+    let rgba: Vec<u8> = linear
+        .chunks_exact(4)
+        .flat_map(|p| {
+            let [r, g, b] =
+                tonemap::tone_map([p[0], p[1], p[2]], mapper, peak).map(pixels::linear_to_srgb8);
+            [r, g, b, (p[3].clamp(0.0, 1.0) * 255.0).round() as u8]
+        })
+        .collect();
+    encode_sdr(&rgba, capture.frame_width, capture.frame_height, options)
+}
+
+/// The primary image with every trace of the gain map removed; unrelated
+/// XMP such as rights or creator is kept
+fn sdr_primary(primary: &JpegImage) -> JpegImage {
+    let mut primary = primary.clone();
+    primary.remove_where(|s| {
+        s.is_app(MARKER_APP2, MPF_NAMESPACE) || s.is_app(MARKER_APP2, ISO_21496_NAMESPACE)
+    });
+    metadata::remove_gain_map_xmp(&mut primary);
+    primary
+}
+
+/// Encode 8-bit sRGB RGBA as the requested SDR format
+fn encode_sdr(
+    rgba: &[u8],
+    width: u32,
+    height: u32,
+    options: &StripHdrOptions,
+) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    match options.format {
+        SdrImageFormat::Jpeg => {
+            let rgb: Vec<u8> = rgba
+                .chunks_exact(4)
+                .flat_map(|p| [p[0], p[1], p[2]])
+                .collect();
+            JpegEncoder::new_with_quality(&mut out, options.jpeg_quality.clamp(1, 100)).encode(
+                &rgb,
+                width,
+                height,
+                ColorType::Rgb8,
+            )?;
+        }
+        SdrImageFormat::Png => {
+            PngEncoder::new(&mut out).write_image(rgba, width, height, ColorType::Rgba8)?;
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::hdr_image_api::ToneMapper;
+    use crate::colorist::container::tests::gain_map_jpeg;
+    use crate::colorist::container::{MARKER_APP1, XMP_NAMESPACE};
+    use crate::colorist::metadata::tests::{hdrgm_segment, iso_segment, ultra_hdr_jpeg};
+
+    fn options(format: SdrImageFormat, tone_mapper: Option<ToneMapper>) -> StripHdrOptions {
+        StripHdrOptions {
+            format,
+            tone_mapper,
+            jpeg_quality: 90,
+        }
+    }
+
+    /// Mean green channel of an encoded SDR image
+    fn mean_level(bytes: &[u8]) -> f32 {
+        let rgba = image::load_from_memory(bytes).unwrap().to_rgba8();
+        let sum: u32 = rgba.pixels().map(|p| p[1] as u32).sum();
+        sum as f32 / (rgba.width() * rgba.height()) as f32
+    }
+
+    #[test]
+    fn jpeg_keeps_the_primary_scan_without_gain_map_traces() {
+        let bytes = ultra_hdr_jpeg();
+        let stripped = strip_hdr(&bytes, &options(SdrImageFormat::Jpeg, None)).unwrap();
+
+        let original = MultiPictureJpeg::parse(&bytes).unwrap();
+        let container = MultiPictureJpeg::parse(&stripped).unwrap();
+        assert!(container.secondary.is_empty());
+        assert!(container.primary.scan == original.primary.scan);
+        for segment in &container.primary.segments {
+            assert!(!segment.is_app(MARKER_APP2, MPF_NAMESPACE));
+            assert!(!segment.is_app(MARKER_APP2, ISO_21496_NAMESPACE));
+            if segment.is_app(MARKER_APP1, XMP_NAMESPACE) {
+                assert!(!String::from_utf8_lossy(&segment.data).contains("hdrgm:"));
+            }
+        }
+    }
+
+    #[test]
+    fn unrelated_xmp_survives_stripping() {
+        let rights = "xmlns:dc=\"http://purl.org/dc/elements/1.1/\" dc:rights=\"CC BY 4.0\"";
+        let bytes = gain_map_jpeg(
+            vec![hdrgm_segment(rights), iso_segment(0.0, 0.0)],
+            vec![
+                hdrgm_segment("hdrgm:GainMapMax=\"2.0\" hdrgm:HDRCapacityMax=\"2.0\""),
+                iso_segment(2.0, 2.0),
+            ],
+        );
+        let stripped = strip_hdr(&bytes, &options(SdrImageFormat::Jpeg, None)).unwrap();
+
+        let primary = MultiPictureJpeg::parse(&stripped).unwrap().primary;
+        let xmp = metadata::xmp_packet(&primary).expect("XMP packet kept");
+        assert!(xmp.contains(rights), "{xmp}");
+        assert!(!xmp.contains("hdrgm"), "{xmp}");
+    }
+
+    #[test]
+    fn png_holds_the_sdr_base_pixels() {
+        let bytes = ultra_hdr_jpeg();
+        let png = strip_hdr(&bytes, &options(SdrImageFormat::Png, None)).unwrap();
+
+        assert_eq!(image::guess_format(&png).unwrap(), ImageFormat::Png);
+        let primary = MultiPictureJpeg::parse(&bytes).unwrap().primary;
+        let base = image::load_from_memory(&primary.to_bytes().unwrap())
+            .unwrap()
+            .to_rgba8();
+        let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert!(decoded == base);
+    }
+
+    #[test]
+    fn tone_mapping_renders_from_the_hdr_rendition() {
+        let bytes = ultra_hdr_jpeg();
+        let base = mean_level(&strip_hdr(&bytes, &options(SdrImageFormat::Png, None)).unwrap());
+        let clipped = strip_hdr(
+            &bytes,
+            &options(SdrImageFormat::Png, Some(ToneMapper::Clip)),
+        )
+        .unwrap();
+
+        // The gain map brightens every pixel, and clipping keeps that
+        assert!(mean_level(&clipped) > base + 10.0);
+        for mapper in [ToneMapper::Reinhard, ToneMapper::AcesFilmic] {
+            let mapped = strip_hdr(&bytes, &options(SdrImageFormat::Jpeg, Some(mapper))).unwrap();
+            let container = MultiPictureJpeg::parse(&mapped).unwrap();
+            assert!(container.secondary.is_empty());
+            assert_eq!(
+                image::load_from_memory(&mapped)
+                    .unwrap()
+                    .to_rgba8()
+                    .dimensions(),
+                (8, 8)
+            );
+        }
+    }
+}
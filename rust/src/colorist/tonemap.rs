@@ -0,0 +1,91 @@
+//! HDR to SDR tone mapping operators on linear sRGB values (1.0 = SDR white)
+
+use crate::api::hdr_image_api::ToneMapper;
+
+/// Rec. 709 luminance weights for linear sRGB
+pub const LUMA_WEIGHTS: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+pub fn luminance(rgb: [f32; 3]) -> f32 {
+    rgb[0] * LUMA_WEIGHTS[0] + rgb[1] * LUMA_WEIGHTS[1] + rgb[2] * LUMA_WEIGHTS[2]
+}
+
+/// Map linear RGB with highlights up to `peak` into [0, 1]
+pub fn tone_map(rgb: [f32; 3], mapper: ToneMapper, peak: f32) -> [f32; 3] {
+    let rgb = rgb.map(|v| v.max(0.0));
+    match mapper {
+        ToneMapper::Clip => rgb.map(|v| v.min(1.0)),
+        ToneMapper::Reinhard => {
+            // Extended Reinhard on luminance keeps hue; `peak` maps to white
+            let l = luminance(rgb);
+            if l <= 0.0 {
+                return [0.0; 3];
+            }
+            let white = peak.max(1.0);
+            let mapped = l * (1.0 + l / (white * white)) / (1.0 + l);
+            rgb.map(|v| (v * mapped / l).min(1.0))
+        }
+        ToneMapper::AcesFilmic => rgb.map(|v| {
+            // Narkowicz's fit of the ACES RRT + ODT
+            let v = v * 0.6;
+            ((v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14)).clamp(0.0, 1.0)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPPERS: [ToneMapper; 3] = [
+        ToneMapper::Clip,
+        ToneMapper::Reinhard,
+        ToneMapper::AcesFilmic,
+    ];
+
+    #[test]
+    fn output_stays_in_sdr_range() {
+        for mapper in MAPPERS {
+            for rgb in [
+                [0.0; 3],
+                [-1.0, 0.5, 2.0],
+                [8.0, 8.0, 8.0],
+                [100.0, 0.0, 0.0],
+            ] {
+                let mapped = tone_map(rgb, mapper, 8.0);
+                assert!(mapped.iter().all(|v| (0.0..=1.0).contains(v)), "{mapper:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn clip_leaves_sdr_values_alone() {
+        assert_eq!(
+            tone_map([0.25, 0.5, 3.0], ToneMapper::Clip, 4.0),
+            [0.25, 0.5, 1.0]
+        );
+    }
+
+    #[test]
+    fn reinhard_maps_the_peak_to_white_and_keeps_hue() {
+        let white = tone_map([4.0; 3], ToneMapper::Reinhard, 4.0);
+        for v in white {
+            assert!((v - 1.0).abs() < 1e-5);
+        }
+
+        let [r, g, b] = tone_map([0.4, 0.2, 0.1], ToneMapper::Reinhard, 4.0);
+        assert!((r / g - 2.0).abs() < 1e-4 && (g / b - 2.0).abs() < 1e-4);
+        assert!(r < 0.4);
+    }
+
+    #[test]
+    fn brighter_input_never_maps_darker() {
+        for mapper in MAPPERS {
+            let mut previous = 0.0;
+            for step in 0..=64 {
+                let v = tone_map([step as f32 / 8.0; 3], mapper, 8.0)[0];
+                assert!(v >= previous, "{mapper:?} at {step}");
+                previous = v;
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Lossless rotation and mirroring of capture buffers
+//!
+//! Pixels are moved as opaque `bytes_per_pixel` chunks, so every capture
+//! format is handled and no value is ever re-encoded.
+
+use anyhow::bail;
+
+use super::pixels;
+use crate::api::processing_api::ImageTransform;
+use crate::api::screen_shot_api::CaptureResult;
+
+/// EXIF Orientation (tag 0x0112) that tells viewers to apply `transform`
+pub fn exif_orientation(transform: ImageTransform) -> u16 {
+    match transform {
+        ImageTransform::FlipHorizontal => 2,
+        ImageTransform::Rotate180 => 3,
+        ImageTransform::FlipVertical => 4,
+        ImageTransform::Rotate90 => 6,
+        ImageTransform::Rotate270 => 8,
+    }
+}
+
+pub fn transform(
+    capture: &CaptureResult,
+    transform: ImageTransform,
+) -> anyhow::Result<CaptureResult> {
+    let bpp = pixels::bytes_per_pixel(&capture.mode);
+    let width = capture.frame_width as usize;
+    let height = capture.frame_height as usize;
+    if capture.raw_data.len() != width * height * bpp {
+        bail!(
+            "Buffer size {} does not match {width}x{height} {}",
+            capture.raw_data.len(),
+            capture.mode
+        );
+    }
+
+    let (out_width, out_height) = match transform {
+        ImageTransform::Rotate90 | ImageTransform::Rotate270 => (height, width),
+        _ => (width, height),
+    };
+    // Source pixel for each destination pixel (rotations are clockwise)
+    let source = |x: usize, y: usize| -> (usize, usize) {
+        match transform {
+            ImageTransform::Rotate90 => (y, height - 1 - x),
+            ImageTransform::Rotate180 => (width - 1 - x, height - 1 - y),
+            ImageTransform::Rotate270 => (width - 1 - y, x),
+            ImageTransform::FlipHorizontal => (width - 1 - x, y),
+            ImageTransform::FlipVertical => (x, height - 1 - y),
+        }
+    };
+
+    let mut raw_data = Vec::with_capacity(capture.raw_data.len());
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let (sx, sy) = source(x, y);
+            let offset = (sy * width + sx) * bpp;
+            raw_data.extend_from_slice(&capture.raw_data[offset..offset + bpp]);
+        }
+    }
+    Ok(CaptureResult {
+        mode: capture.mode.clone(),
+        raw_data,
+        frame_width: out_width as u32,
+        frame_height: out_height as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 3x2 BGRA capture whose pixels are numbered 0..6 in the blue byte
+    fn numbered() -> CaptureResult {
+        CaptureResult {
+            mode: pixels::MODE_SDR_MACOS.to_string(),
+            raw_data: (0..6u8).flat_map(|i| [i, 0, 0, 255]).collect(),
+            frame_width: 3,
+            frame_height: 2,
+        }
+    }
+
+    fn numbers(capture: &CaptureResult) -> Vec<u8> {
+        capture.raw_data.chunks_exact(4).map(|p| p[0]).collect()
+    }
+
+    #[test]
+    fn moves_pixels_as_described() {
+        // 0 1 2
+        // 3 4 5
+        let cases = [
+            (ImageTransform::Rotate90, (2, 3), vec![3, 0, 4, 1, 5, 2]),
+            (ImageTransform::Rotate180, (3, 2), vec![5, 4, 3, 2, 1, 0]),
+            (ImageTransform::Rotate270, (2, 3), vec![2, 5, 1, 4, 0, 3]),
+            (
+                ImageTransform::FlipHorizontal,
+                (3, 2),
+                vec![2, 1, 0, 5, 4, 3],
+            ),
+            (ImageTransform::FlipVertical, (3, 2), vec![3, 4, 5, 0, 1, 2]),
+        ];
+        for (kind, size, expected) in cases {
+            let out = transform(&numbered(), kind).unwrap();
+            assert_eq!((out.frame_width, out.frame_height), size, "{kind:?}");
+            assert_eq!(numbers(&out), expected, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn inverse_transforms_restore_the_capture() {
+        let source = numbered();
+        for (kind, inverse) in [
+            (ImageTransform::Rotate90, ImageTransform::Rotate270),
+            (ImageTransform::Rotate180, ImageTransform::Rotate180),
+            (
+                ImageTransform::FlipHorizontal,
+                ImageTransform::FlipHorizontal,
+            ),
+            (ImageTransform::FlipVertical, ImageTransform::FlipVertical),
+        ] {
+            let back = transform(&transform(&source, kind).unwrap(), inverse).unwrap();
+            assert_eq!((back.frame_width, back.frame_height), (3, 2));
+            assert!(back.raw_data == source.raw_data, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn fp16_pixels_move_whole() {
+        let source = pixels::capture_from_linear(
+            "hdr_macos",
+            2,
+            1,
+            [[4.0, 0.5, 0.25, 1.0], [0.1, 0.2, 0.3, 0.5]].concat(),
+        );
+        let out = transform(&source, ImageTransform::FlipHorizontal).unwrap();
+        assert!(out.raw_data == [&source.raw_data[8..], &source.raw_data[..8]].concat());
+    }
+
+    #[test]
+    fn mismatched_buffer_is_rejected() {
+        let mut source = numbered();
+        source.raw_data.pop();
+        assert!(transform(&source, ImageTransform::Rotate90).is_err());
+    }
+}
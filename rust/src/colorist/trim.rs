@@ -0,0 +1,180 @@
+//! Detection of uniform margins around a capture's content
+
+use super::pixels;
+use crate::api::analysis_api::SampleRegion;
+use crate::api::screen_shot_api::CaptureResult;
+
+struct Frame {
+    width: usize,
+    /// Linear RGBA, sRGB primaries, 1.0 = SDR white
+    linear: Vec<f32>,
+    tolerance: f32,
+}
+
+impl Frame {
+    fn pixel(&self, x: usize, y: usize) -> &[f32] {
+        &self.linear[(y * self.width + x) * 4..][..4]
+    }
+
+    fn matches(&self, x: usize, y: usize, reference: &[f32]) -> bool {
+        self.pixel(x, y)
+            .iter()
+            .zip(reference)
+            .all(|(a, b)| (a - b).abs() <= self.tolerance)
+    }
+
+    fn row_is(&self, y: usize, columns: (usize, usize), reference: &[f32]) -> bool {
+        (columns.0..columns.1).all(|x| self.matches(x, y, reference))
+    }
+
+    fn column_is(&self, x: usize, rows: (usize, usize), reference: &[f32]) -> bool {
+        (rows.0..rows.1).all(|y| self.matches(x, y, reference))
+    }
+}
+
+/// Tight bounds of the content inside uniform margins
+///
+/// Each side is trimmed while its outermost line matches that side's corner
+/// pixel within `tolerance` per channel (linear, alpha included), so a
+/// margin may differ in colour from the one opposite it. Returns the whole
+/// frame if there is nothing to trim or the capture is uniform.
+pub fn trim_region(capture: &CaptureResult, tolerance: f32) -> anyhow::Result<SampleRegion> {
+    let (width, height) = (capture.frame_width as usize, capture.frame_height as usize);
+    let whole = SampleRegion {
+        x: 0,
+        y: 0,
+        width: capture.frame_width,
+        height: capture.frame_height,
+    };
+    if width == 0 || height == 0 {
+        return Ok(whole);
+    }
+    let frame = Frame {
+        width,
+        linear: pixels::decode_linear(capture)?,
+        tolerance: tolerance.max(0.0),
+    };
+
+    let (mut top, mut bottom) = (0, height);
+    let reference = frame.pixel(0, 0).to_vec();
+    while top < bottom && frame.row_is(top, (0, width), &reference) {
+        top += 1;
+    }
+    if top == bottom {
+        return Ok(whole);
+    }
+    let reference = frame.pixel(0, height - 1).to_vec();
+    while bottom > top && frame.row_is(bottom - 1, (0, width), &reference) {
+        bottom -= 1;
+    }
+
+    // Left and right margins only need to hold between the trimmed rows
+    let (mut left, mut right) = (0, width);
+    let reference = frame.pixel(0, top).to_vec();
+    while left < right && frame.column_is(left, (top, bottom), &reference) {
+        left += 1;
+    }
+    let reference = frame.pixel(width - 1, top).to_vec();
+    while right > left && frame.column_is(right - 1, (top, bottom), &reference) {
+        right -= 1;
+    }
+    if left == right {
+        return Ok(whole);
+    }
+
+    Ok(SampleRegion {
+        x: left as u32,
+        y: top as u32,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `width`x`height` frame filled by `margin(x, y)` outside the content
+    /// rectangle and mid grey inside it
+    fn framed(
+        width: u32,
+        height: u32,
+        content: (u32, u32, u32, u32),
+        margin: impl Fn(u32, u32) -> [f32; 4],
+    ) -> CaptureResult {
+        let (cx, cy, cw, ch) = content;
+        let mut linear = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let inside = (cx..cx + cw).contains(&x) && (cy..cy + ch).contains(&y);
+                linear.extend(if inside {
+                    [0.3, 0.4, 0.5, 1.0]
+                } else {
+                    margin(x, y)
+                });
+            }
+        }
+        pixels::capture_from_linear("hdr_macos", width, height, linear)
+    }
+
+    fn bounds(region: SampleRegion) -> (u32, u32, u32, u32) {
+        (region.x, region.y, region.width, region.height)
+    }
+
+    #[test]
+    fn asymmetric_margins_are_trimmed() {
+        let capture = framed(30, 20, (3, 5, 20, 9), |_, _| [1.0; 4]);
+        assert_eq!(bounds(trim_region(&capture, 0.01).unwrap()), (3, 5, 20, 9));
+    }
+
+    #[test]
+    fn each_side_follows_its_own_colour() {
+        // White above and left of the content, black below and right
+        let capture = framed(24, 16, (4, 2, 10, 6), |x, y| {
+            if y < 2 || (y < 8 && x < 4) {
+                [1.0; 4]
+            } else {
+                [0.0, 0.0, 0.0, 1.0]
+            }
+        });
+        assert_eq!(bounds(trim_region(&capture, 0.01).unwrap()), (4, 2, 10, 6));
+    }
+
+    #[test]
+    fn tolerance_absorbs_noise_in_the_margin() {
+        let noisy = |x: u32, y: u32| {
+            let v = 1.0 + ((x * 7 + y * 3) % 5) as f32 * 0.004;
+            [v, v, v, 1.0]
+        };
+        let capture = framed(20, 20, (6, 6, 8, 8), noisy);
+        assert_eq!(bounds(trim_region(&capture, 0.05).unwrap()), (6, 6, 8, 8));
+        assert_eq!(bounds(trim_region(&capture, 0.0).unwrap()), (0, 0, 20, 20));
+    }
+
+    #[test]
+    fn uniform_or_borderless_frames_are_kept_whole() {
+        let uniform = framed(8, 8, (0, 0, 0, 0), |_, _| [0.2, 0.2, 0.2, 1.0]);
+        assert_eq!(bounds(trim_region(&uniform, 0.01).unwrap()), (0, 0, 8, 8));
+        let full = framed(8, 8, (0, 0, 8, 8), |_, _| [1.0; 4]);
+        assert_eq!(bounds(trim_region(&full, 0.01).unwrap()), (0, 0, 8, 8));
+    }
+
+    #[test]
+    fn sdr_capture_is_auto_trimmed_at_its_own_stride() {
+        let hdr = framed(30, 20, (3, 12, 20, 6), |_, _| [1.0; 4]);
+        let linear = pixels::decode_linear(&hdr).unwrap();
+        let sdr = pixels::capture_from_linear(pixels::MODE_SDR_MACOS, 30, 20, linear);
+        let trimmed = sdr.auto_trim(0.01).unwrap();
+        assert_eq!((trimmed.frame_width, trimmed.frame_height), (20, 6));
+        assert_eq!(trimmed.raw_data.len(), 20 * 6 * 4);
+        let content = pixels::decode_signal(&trimmed).unwrap();
+        let expected = pixels::decode_signal(&pixels::capture_from_linear(
+            pixels::MODE_SDR_MACOS,
+            1,
+            1,
+            vec![0.3, 0.4, 0.5, 1.0],
+        ))
+        .unwrap();
+        assert!(content.chunks_exact(4).all(|p| p == expected.as_slice()));
+    }
+}
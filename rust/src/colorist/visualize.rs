@@ -0,0 +1,152 @@
+//! Diagnostic heatmaps for explaining HDR issues in the overlay
+//!
+//! Every renderer returns an RGBA8 image the size of the capture. Pixels
+//! without anything to report are drawn as dimmed greyscale so the
+//! highlighted ones stand out.
+
+use glam::f32::{Mat3, Vec3};
+
+use super::pixels::{self, SDR_WHITE_NITS, SRGB_TO_P3};
+use super::tonemap;
+use crate::api::analysis_api::VisualizationKind;
+use crate::api::screen_shot_api::CaptureResult;
+
+/// Upper bound in nits and colour of each false-colour band
+const FALSE_COLOR_BANDS: [(f32, [u8; 3]); 10] = [
+    (1.0, [16, 16, 64]),
+    (10.0, [32, 64, 192]),
+    (50.0, [0, 160, 208]),
+    (100.0, [0, 176, 96]),
+    (SDR_WHITE_NITS, [160, 160, 160]),
+    (400.0, [240, 224, 0]),
+    (1000.0, [255, 144, 0]),
+    (2000.0, [224, 32, 32]),
+    (4000.0, [224, 0, 224]),
+    (f32::INFINITY, [255, 255, 255]),
+];
+
+/// Components may sit this far outside [0, 1] before counting as clipped,
+/// which absorbs FP16 rounding
+const TOLERANCE: f32 = 1.0 / 512.0;
+
+const CLIPPED_HIGH: [u8; 3] = [255, 32, 32];
+const CLIPPED_LOW: [u8; 3] = [32, 96, 255];
+const OUTSIDE_SRGB: [u8; 3] = [255, 208, 0];
+const OUTSIDE_P3: [u8; 3] = [255, 0, 255];
+
+pub(crate) fn dimmed_grey(linear: [f32; 3]) -> [u8; 3] {
+    let grey = pixels::linear_to_srgb8(tonemap::luminance(linear).min(1.0)) / 2;
+    [grey; 3]
+}
+
+fn false_color(linear: [f32; 3]) -> [u8; 3] {
+    let nits = tonemap::luminance(linear) * SDR_WHITE_NITS;
+    FALSE_COLOR_BANDS
+        .iter()
+        .find(|(upper, _)| nits < *upper)
+        .map_or([255; 3], |(_, color)| *color)
+}
+
+/// Red where the SDR base clips highlights, blue where it crushes
+/// negative (out-of-gamut) values to zero
+fn sdr_clipping(signal: [f32; 3], linear: [f32; 3]) -> [u8; 3] {
+    if signal.iter().any(|v| *v > 1.0 + TOLERANCE) {
+        CLIPPED_HIGH
+    } else if signal.iter().any(|v| *v < -TOLERANCE) {
+        CLIPPED_LOW
+    } else {
+        dimmed_grey(linear)
+    }
+}
+
+/// Yellow outside sRGB but inside Display P3, magenta outside P3 too
+fn gamut(linear: [f32; 3], srgb_to_p3: &Mat3) -> [u8; 3] {
+    // Chromaticity only: brightness above SDR white is not a gamut excursion
+    let peak = linear[0].max(linear[1]).max(linear[2]);
+    if peak <= 0.0 {
+        return dimmed_grey(linear);
+    }
+    let normalized = linear.map(|v| v / peak);
+    if normalized.iter().all(|v| *v >= -TOLERANCE) {
+        return dimmed_grey(linear);
+    }
+    let p3 = srgb_to_p3.mul_vec3(Vec3::from_array(normalized));
+    if p3.min_element() >= -TOLERANCE {
+        OUTSIDE_SRGB
+    } else {
+        OUTSIDE_P3
+    }
+}
+
+pub fn render_visualization(
+    capture: &CaptureResult,
+    kind: VisualizationKind,
+) -> anyhow::Result<Vec<u8>> {
+    let signal = pixels::decode_signal(capture)?;
+    let srgb_to_p3 = Mat3::from_cols_array(&SRGB_TO_P3);
+
+    let mut rgba = Vec::with_capacity(signal.len());
+    for pixel in signal.chunks_exact(4) {
+        let signal = [pixel[0], pixel[1], pixel[2]];
+        let linear = signal.map(pixels::signal_to_linear);
+        let [r, g, b] = match kind {
+            VisualizationKind::FalseColorNits => false_color(linear),
+            VisualizationKind::SdrClipping => sdr_clipping(signal, linear),
+            VisualizationKind::Gamut => gamut(linear, &srgb_to_p3),
+        };
+        rgba.extend_from_slice(&[r, g, b, 255]);
+    }
+    Ok(rgba)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One row of pixels given in linear light
+    fn row(linear: &[[f32; 3]]) -> CaptureResult {
+        let values = linear
+            .iter()
+            .flat_map(|&[r, g, b]| [r, g, b, 1.0])
+            .collect();
+        pixels::capture_from_linear("hdr_macos", linear.len() as u32, 1, values)
+    }
+
+    fn colors(capture: &CaptureResult, kind: VisualizationKind) -> Vec<[u8; 3]> {
+        let rgba = render_visualization(capture, kind).unwrap();
+        assert_eq!(rgba.len(), capture.raw_data.len() / 2);
+        rgba.chunks_exact(4).map(|p| [p[0], p[1], p[2]]).collect()
+    }
+
+    #[test]
+    fn false_colour_bands_follow_nits() {
+        let capture = row(&[[0.0; 3], [0.5; 3], [4.0; 3], [15.0; 3]]);
+        assert_eq!(
+            colors(&capture, VisualizationKind::FalseColorNits),
+            [[16, 16, 64], [160, 160, 160], [255, 144, 0], [224, 0, 224]]
+        );
+    }
+
+    #[test]
+    fn sdr_clipping_marks_both_ends() {
+        let capture = row(&[[0.5; 3], [2.0, 0.5, 0.5], [0.5, -0.1, 0.5]]);
+        let out = colors(&capture, VisualizationKind::SdrClipping);
+        assert_eq!(out[0], dimmed_grey([0.5; 3]));
+        assert_eq!(out[1], CLIPPED_HIGH);
+        assert_eq!(out[2], CLIPPED_LOW);
+    }
+
+    #[test]
+    fn gamut_separates_p3_from_wider_colours() {
+        let p3_red = Mat3::from_cols_array(&pixels::P3_TO_SRGB).col(0).to_array();
+        let bt2020_red = Mat3::from_cols_array(&pixels::BT2020_TO_SRGB)
+            .col(0)
+            .to_array();
+        // A bright sRGB colour is not a gamut excursion
+        let capture = row(&[[4.0, 2.0, 1.0], p3_red, bt2020_red]);
+        let out = colors(&capture, VisualizationKind::Gamut);
+        assert_eq!(out[0], dimmed_grey([4.0, 2.0, 1.0]));
+        assert_eq!(out[1], OUTSIDE_SRGB);
+        assert_eq!(out[2], OUTSIDE_P3);
+    }
+}
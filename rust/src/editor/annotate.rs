@@ -0,0 +1,374 @@
+//! Rasterise annotations onto a capture in linear light
+//!
+//! Colours are scaled by their brightness in nits, so an annotation can sit
+//! at SDR white next to SDR content or glow like the HDR highlights around it.
+
+use super::canvas::{BlendMode, Canvas, Mask, Paint};
+use super::{shape, text};
+use crate::api::edit_api::{Annotation, AnnotationColor, EditPoint};
+use crate::api::screen_shot_api::CaptureResult;
+use crate::colorist::pixels::{self, SDR_WHITE_NITS};
+use crate::colorist::tonemap;
+
+/// Arrow head length as a multiple of the stroke width, and its minimum
+const ARROW_HEAD_SCALE: f32 = 4.0;
+const ARROW_HEAD_MIN: f32 = 10.0;
+/// Badge numbers are set at this fraction of the badge radius
+const BADGE_TEXT_SCALE: f32 = 1.1;
+/// Height of DejaVu Sans digits, in em
+const DIGIT_HEIGHT: f32 = 0.729;
+/// Badges brighter than this (SDR relative luminance) get dark numbers
+const BADGE_DARK_TEXT_LUMINANCE: f32 = 0.4;
+
+impl AnnotationColor {
+    /// Linear RGB with 1.0 = SDR white
+    pub(crate) fn linear(&self) -> [f32; 3] {
+        let scale = self.nits.max(0.0) / SDR_WHITE_NITS;
+        [self.red, self.green, self.blue].map(|v| pixels::srgb_to_linear(v.clamp(0.0, 1.0)) * scale)
+    }
+
+    pub(crate) fn paint(&self) -> Paint {
+        Paint::new(self.linear(), self.opacity)
+    }
+}
+
+fn point(p: &EditPoint) -> shape::Point {
+    (p.x, p.y)
+}
+
+/// Union a round-capped polyline of `width` into `mask`
+fn add_stroke(mask: &mut Mask, points: &[shape::Point], width: f32) {
+    let half = width.max(0.0) / 2.0;
+    if let [p] = points {
+        let (min, max) = shape::bounds(points, half + 1.0);
+        mask.add_sdf(min, max, |x, y| shape::circle((x, y), *p, half));
+    }
+    for pair in points.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        let (min, max) = shape::bounds(&[a, b], half + 1.0);
+        mask.add_sdf(min, max, |x, y| {
+            shape::segment_distance((x, y), a, b) - half
+        });
+    }
+}
+
+fn stroke_mask(canvas: &Canvas, points: &[shape::Point], width: f32) -> Mask {
+    let (min, max) = shape::bounds(points, width.max(0.0) / 2.0 + 1.0);
+    let mut mask = Mask::new(canvas, min, max);
+    add_stroke(&mut mask, points, width);
+    mask
+}
+
+fn arrow_mask(canvas: &Canvas, from: shape::Point, to: shape::Point, width: f32) -> Mask {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let length = (dx * dx + dy * dy).sqrt();
+    if length <= f32::EPSILON {
+        return stroke_mask(canvas, &[from], width);
+    }
+    let (ux, uy) = (dx / length, dy / length);
+    let head = (width * ARROW_HEAD_SCALE).max(ARROW_HEAD_MIN).min(length);
+    let base = (to.0 - ux * head, to.1 - uy * head);
+    let half_base = head * 0.5;
+    let left = (base.0 - uy * half_base, base.1 + ux * half_base);
+    let right = (base.0 + uy * half_base, base.1 - ux * half_base);
+    let triangle = [to, left, right];
+
+    let (min, max) = shape::bounds(&[from, to, left, right], width.max(0.0) / 2.0 + 1.0);
+    let mut mask = Mask::new(canvas, min, max);
+    // Stop the shaft inside the head so its round cap does not poke out
+    let shaft_end = (to.0 - ux * head * 0.5, to.1 - uy * head * 0.5);
+    add_stroke(&mut mask, &[from, shaft_end], width);
+    let (min, max) = shape::bounds(&triangle, 1.0);
+    mask.add_sdf(min, max, |x, y| triangle_distance((x, y), &triangle));
+    mask
+}
+
+/// Signed distance to a triangle: the largest distance past any edge
+fn triangle_distance(p: shape::Point, t: &[shape::Point; 3]) -> f32 {
+    // Orient edges so outward normals point away from the centroid
+    let centroid = (
+        (t[0].0 + t[1].0 + t[2].0) / 3.0,
+        (t[0].1 + t[1].1 + t[2].1) / 3.0,
+    );
+    (0..3)
+        .map(|i| {
+            let (a, b) = (t[i], t[(i + 1) % 3]);
+            let (ex, ey) = (b.0 - a.0, b.1 - a.1);
+            let len = (ex * ex + ey * ey).sqrt().max(f32::EPSILON);
+            let (mut nx, mut ny) = (ey / len, -ex / len);
+            if (centroid.0 - a.0) * nx + (centroid.1 - a.1) * ny > 0.0 {
+                (nx, ny) = (-nx, -ny);
+            }
+            (p.0 - a.0) * nx + (p.1 - a.1) * ny
+        })
+        .fold(f32::NEG_INFINITY, f32::max)
+}
+
+fn draw(canvas: &mut Canvas, annotation: &Annotation) {
+    match annotation {
+        Annotation::Rectangle {
+            rect,
+            color,
+            stroke_width,
+            corner_radius,
+            filled,
+        } => {
+            let r = (rect.x, rect.y, rect.width, rect.height);
+            let half = stroke_width.max(0.0) / 2.0;
+            let margin = if *filled { 1.0 } else { half + 1.0 };
+            let min = (r.0 - margin, r.1 - margin);
+            let max = (r.0 + r.2 + margin, r.1 + r.3 + margin);
+            let mut mask = Mask::new(canvas, min, max);
+            if *filled {
+                mask.add_sdf(min, max, |x, y| {
+                    shape::rounded_rect((x, y), r, *corner_radius)
+                });
+            } else {
+                mask.add_sdf(min, max, |x, y| {
+                    shape::rounded_rect((x, y), r, *corner_radius).abs() - half
+                });
+            }
+            canvas.composite(&mask, &color.paint());
+        }
+        Annotation::Arrow {
+            from,
+            to,
+            color,
+            stroke_width,
+        } => {
+            let mask = arrow_mask(canvas, point(from), point(to), *stroke_width);
+            canvas.composite(&mask, &color.paint());
+        }
+        Annotation::Freehand {
+            points,
+            color,
+            stroke_width,
+        } => {
+            if points.is_empty() {
+                return;
+            }
+            let points: Vec<_> = points.iter().map(point).collect();
+            let mask = stroke_mask(canvas, &points, *stroke_width);
+            canvas.composite(&mask, &color.paint());
+        }
+        Annotation::Highlighter {
+            points,
+            color,
+            width,
+        } => {
+            if points.is_empty() {
+                return;
+            }
+            let points: Vec<_> = points.iter().map(point).collect();
+            let mask = stroke_mask(canvas, &points, *width);
+            let paint = Paint {
+                blend: BlendMode::Multiply,
+                ..color.paint()
+            };
+            canvas.composite(&mask, &paint);
+        }
+        Annotation::StepBadge {
+            center,
+            number,
+            radius,
+            color,
+        } => {
+            let c = point(center);
+            let min = (c.0 - radius - 1.0, c.1 - radius - 1.0);
+            let max = (c.0 + radius + 1.0, c.1 + radius + 1.0);
+            let mut mask = Mask::new(canvas, min, max);
+            mask.add_sdf(min, max, |x, y| shape::circle((x, y), c, *radius));
+            canvas.composite(&mask, &color.paint());
+
+            let label = number.to_string();
+            let size = radius * BADGE_TEXT_SCALE;
+            let (width, _) = text::measure(&label, size);
+            let sdr = [color.red, color.green, color.blue].map(pixels::srgb_to_linear);
+            let ink = if tonemap::luminance(sdr) > BADGE_DARK_TEXT_LUMINANCE {
+                [0.0; 3]
+            } else {
+                [1.0; 3]
+            };
+            // Centre the digits themselves, not the line box with its descender
+            let top = c.1 + size * DIGIT_HEIGHT / 2.0 - text::ascent(size);
+            let mask = text::text_mask(canvas, &label, (c.0 - width / 2.0, top), size);
+            canvas.composite(&mask, &Paint::new(ink, color.opacity));
+        }
+        Annotation::Text {
+            origin,
+            text,
+            font_size,
+            color,
+        } => {
+            let mask = text::text_mask(canvas, text, point(origin), *font_size);
+            canvas.composite(&mask, &color.paint());
+        }
+    }
+}
+
+/// Draw every annotation onto the canvas, in order
+pub fn draw_all(canvas: &mut Canvas, annotations: &[Annotation]) {
+    for annotation in annotations {
+        draw(canvas, annotation);
+    }
+}
+
+pub fn annotate(
+    capture: &CaptureResult,
+    annotations: &[Annotation],
+) -> anyhow::Result<CaptureResult> {
+    for annotation in annotations {
+        match annotation {
+            Annotation::Text { font_size, .. } => text::check_size(*font_size)?,
+            Annotation::StepBadge { radius, .. } => text::check_size(radius * BADGE_TEXT_SCALE)?,
+            _ => {}
+        }
+    }
+    let mut canvas = Canvas::from_capture(capture)?;
+    draw_all(&mut canvas, annotations);
+    Ok(canvas.into_capture(&capture.mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::edit_api::EditRect;
+
+    fn color(rgb: [f32; 3], nits: f32) -> AnnotationColor {
+        AnnotationColor {
+            red: rgb[0],
+            green: rgb[1],
+            blue: rgb[2],
+            nits,
+            opacity: 1.0,
+        }
+    }
+
+    fn canvas(width: u32, height: u32, value: f32) -> Canvas {
+        Canvas {
+            width,
+            height,
+            pixels: [value, value, value, 1.0].repeat((width * height) as usize),
+        }
+    }
+
+    fn rgb(canvas: &Canvas, x: usize, y: usize) -> [f32; 3] {
+        let i = (y * canvas.width as usize + x) * 4;
+        [canvas.pixels[i], canvas.pixels[i + 1], canvas.pixels[i + 2]]
+    }
+
+    fn rectangle(filled: bool) -> Annotation {
+        Annotation::Rectangle {
+            rect: EditRect {
+                x: 4.0,
+                y: 4.0,
+                width: 12.0,
+                height: 8.0,
+            },
+            color: color([1.0, 0.0, 0.0], 2.0 * SDR_WHITE_NITS),
+            stroke_width: 2.0,
+            corner_radius: 0.0,
+            filled,
+        }
+    }
+
+    #[test]
+    fn colour_brightness_is_in_nits() {
+        assert_eq!(
+            color([1.0, 0.0, 0.0], SDR_WHITE_NITS).linear(),
+            [1.0, 0.0, 0.0]
+        );
+        assert_eq!(
+            color([1.0, 1.0, 1.0], 1000.0).linear()[1],
+            1000.0 / SDR_WHITE_NITS
+        );
+        assert_eq!(color([1.0; 3], -5.0).linear(), [0.0; 3]);
+    }
+
+    #[test]
+    fn filled_rectangle_glows_at_its_brightness() {
+        let mut canvas = canvas(20, 16, 0.25);
+        draw_all(&mut canvas, &[rectangle(true)]);
+        assert_eq!(rgb(&canvas, 10, 8), [2.0, 0.0, 0.0]);
+        assert_eq!(rgb(&canvas, 1, 1), [0.25; 3]);
+    }
+
+    #[test]
+    fn outlined_rectangle_leaves_the_inside_alone() {
+        let mut canvas = canvas(20, 16, 0.25);
+        draw_all(&mut canvas, &[rectangle(false)]);
+        assert_eq!(rgb(&canvas, 10, 8), [0.25; 3]);
+        assert_eq!(rgb(&canvas, 10, 4), [2.0, 0.0, 0.0]);
+        assert_eq!(rgb(&canvas, 4, 8), [2.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn highlighter_tints_instead_of_covering() {
+        let mut canvas = canvas(20, 4, 0.5);
+        canvas.pixels[4 * 10..4 * 10 + 3].fill(0.0);
+        let stroke = Annotation::Highlighter {
+            points: vec![EditPoint { x: 0.0, y: 2.0 }, EditPoint { x: 20.0, y: 2.0 }],
+            color: color([1.0, 1.0, 0.0], SDR_WHITE_NITS),
+            width: 4.0,
+        };
+        draw_all(&mut canvas, &[stroke]);
+        assert_eq!(rgb(&canvas, 5, 1), [0.5, 0.5, 0.0]);
+        assert_eq!(rgb(&canvas, 10, 0), [0.0; 3], "black stays black");
+    }
+
+    #[test]
+    fn arrow_head_reaches_the_tip() {
+        let mut canvas = canvas(40, 20, 0.0);
+        let arrow = Annotation::Arrow {
+            from: EditPoint { x: 2.0, y: 10.0 },
+            to: EditPoint { x: 36.0, y: 10.0 },
+            color: color([1.0; 3], SDR_WHITE_NITS),
+            stroke_width: 2.0,
+        };
+        draw_all(&mut canvas, &[arrow]);
+        assert_eq!(rgb(&canvas, 32, 10), [1.0; 3]);
+        // The head is wider than the shaft
+        assert_eq!(rgb(&canvas, 28, 12), [1.0; 3]);
+        assert_eq!(rgb(&canvas, 10, 13), [0.0; 3]);
+        assert_eq!(rgb(&canvas, 38, 10), [0.0; 3]);
+    }
+
+    #[test]
+    fn step_badge_numbers_contrast_with_the_badge() {
+        let badge = |rgb_color| Annotation::StepBadge {
+            center: EditPoint { x: 20.0, y: 20.0 },
+            number: 1,
+            radius: 16.0,
+            color: color(rgb_color, SDR_WHITE_NITS),
+        };
+        for (fill, ink) in [([0.0, 0.0, 0.6], 1.0), ([1.0, 0.9, 0.2], 0.0)] {
+            let mut canvas = canvas(40, 40, 0.5);
+            draw_all(&mut canvas, &[badge(fill)]);
+            // Pixels mostly covered by the number, whose ink is the
+            // opposite of the badge's red channel
+            let inked = (0..40)
+                .flat_map(|y| (0..40).map(move |x| (x, y)))
+                .filter(|&(x, y)| (rgb(&canvas, x, y)[0] - ink).abs() < 0.25)
+                .count();
+            assert!(inked > 10, "{fill:?}: {inked} pixels in {ink}");
+            let edge = rgb(&canvas, 20, 6);
+            for (a, b) in edge.iter().zip(color(fill, SDR_WHITE_NITS).linear()) {
+                assert!((a - b).abs() < 1e-6, "{edge:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn huge_or_non_finite_text_is_rejected() {
+        let capture = pixels::capture_from_linear("hdr_macos", 8, 8, [0.5; 4].repeat(64));
+        let text = |font_size| Annotation::Text {
+            origin: EditPoint { x: 0.0, y: 0.0 },
+            text: "Hi".to_string(),
+            font_size,
+            color: color([1.0; 3], SDR_WHITE_NITS),
+        };
+        annotate(&capture, &[text(12.0)]).unwrap();
+        assert!(annotate(&capture, &[text(1e7)]).is_err());
+        assert!(annotate(&capture, &[text(f32::NAN)]).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+//! Presentation framing: background, padding, rounded corners and shadow
+//!
+//! Composited in linear light, so the gradient blends and the shadow's
+//! falloff look right and the capture's HDR pixels are copied unscaled.
+
+use anyhow::bail;
+
+use super::canvas::{Canvas, Mask};
+use super::{filter, shape};
+use crate::api::edit_api::{BeautifyBackground, BeautifyOptions};
+use crate::api::screen_shot_api::CaptureResult;
+
+fn fill_background(canvas: &mut Canvas, background: &BeautifyBackground) {
+    let (width, height) = (canvas.width as usize, canvas.height as usize);
+    match background {
+        BeautifyBackground::Solid { color } => {
+            let c = color.linear();
+            for pixel in canvas.pixels.chunks_exact_mut(4) {
+                pixel.copy_from_slice(&[c[0], c[1], c[2], color.opacity.clamp(0.0, 1.0)]);
+            }
+        }
+        BeautifyBackground::LinearGradient {
+            start,
+            end,
+            angle_degrees,
+        } => {
+            let (dy, dx) = angle_degrees.to_radians().sin_cos();
+            // Project the corners onto the direction to find where 0 and 1 fall
+            let corners = [
+                (0.0, 0.0),
+                (width as f32, 0.0),
+                (0.0, height as f32),
+                (width as f32, height as f32),
+            ];
+            let projections = corners.map(|(x, y)| x * dx + y * dy);
+            let lo = projections.iter().copied().fold(f32::INFINITY, f32::min);
+            let hi = projections
+                .iter()
+                .copied()
+                .fold(f32::NEG_INFINITY, f32::max);
+            let span = (hi - lo).max(f32::EPSILON);
+            let (a, b) = (start.linear(), end.linear());
+            let (alpha_a, alpha_b) = (start.opacity.clamp(0.0, 1.0), end.opacity.clamp(0.0, 1.0));
+            for y in 0..height {
+                for x in 0..width {
+                    let t = (((x as f32 + 0.5) * dx + (y as f32 + 0.5) * dy - lo) / span)
+                        .clamp(0.0, 1.0);
+                    let i = (y * width + x) * 4;
+                    for c in 0..3 {
+                        canvas.pixels[i + c] = a[c] + (b[c] - a[c]) * t;
+                    }
+                    canvas.pixels[i + 3] = alpha_a + (alpha_b - alpha_a) * t;
+                }
+            }
+        }
+    }
+}
+
+pub fn beautify(
+    capture: &CaptureResult,
+    options: &BeautifyOptions,
+) -> anyhow::Result<CaptureResult> {
+    let source = Canvas::from_capture(capture)?;
+    let padded = |size: u32| options.padding.checked_mul(2)?.checked_add(size);
+    let (Some(canvas_width), Some(canvas_height)) =
+        (padded(capture.frame_width), padded(capture.frame_height))
+    else {
+        bail!(
+            "Padding {} around {}x{} is too large",
+            options.padding,
+            capture.frame_width,
+            capture.frame_height
+        );
+    };
+    let padding = options.padding as usize;
+    let (width, height) = (canvas_width as usize, canvas_height as usize);
+    let Some(len) = width.checked_mul(height).and_then(|n| n.checked_mul(4)) else {
+        bail!("Beautified canvas of {width}x{height} is too large");
+    };
+    let mut canvas = Canvas {
+        width: canvas_width,
+        height: canvas_height,
+        pixels: vec![0.0; len],
+    };
+    fill_background(&mut canvas, &options.background);
+
+    let rect = (
+        padding as f32,
+        padding as f32,
+        capture.frame_width as f32,
+        capture.frame_height as f32,
+    );
+    let full = ((0.0, 0.0), (width as f32, height as f32));
+
+    if let Some(shadow) = &options.shadow {
+        let offset = (
+            rect.0 + shadow.offset_x,
+            rect.1 + shadow.offset_y,
+            rect.2,
+            rect.3,
+        );
+        let mut mask = Mask::new(&canvas, full.0, full.1);
+        mask.add_sdf(full.0, full.1, |x, y| {
+            shape::rounded_rect((x, y), offset, options.corner_radius)
+        });
+        filter::gaussian_blur(
+            &mut mask.coverage,
+            mask.width,
+            mask.height,
+            1,
+            shadow.blur_radius.max(0.0) / 2.0,
+        );
+        canvas.composite(&mask, &shadow.color.paint());
+    }
+
+    // Capture pixels go over the background through the rounded-corner mask
+    let mut mask = Mask::new(&canvas, full.0, full.1);
+    mask.add_sdf(
+        (rect.0, rect.1),
+        (rect.0 + rect.2, rect.1 + rect.3),
+        |x, y| shape::rounded_rect((x, y), rect, options.corner_radius),
+    );
+    let source_width = capture.frame_width as usize;
+    for y in 0..capture.frame_height as usize {
+        for x in 0..source_width {
+            let coverage = mask.coverage[(y + padding) * width + x + padding];
+            let s = &source.pixels[(y * source_width + x) * 4..][..4];
+            let alpha = s[3].clamp(0.0, 1.0) * coverage;
+            if alpha <= 0.0 {
+                continue;
+            }
+            let d = &mut canvas.pixels[((y + padding) * width + x + padding) * 4..][..4];
+            for c in 0..3 {
+                d[c] += (s[c] - d[c]) * alpha;
+            }
+            d[3] = alpha + d[3] * (1.0 - alpha);
+        }
+    }
+    Ok(canvas.into_capture(&capture.mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::edit_api::AnnotationColor;
+    use crate::colorist::pixels::{self, SDR_WHITE_NITS};
+
+    fn solid(rgb: [f32; 3]) -> BeautifyBackground {
+        BeautifyBackground::Solid {
+            color: AnnotationColor {
+                red: rgb[0],
+                green: rgb[1],
+                blue: rgb[2],
+                nits: SDR_WHITE_NITS,
+                opacity: 1.0,
+            },
+        }
+    }
+
+    fn beautified(options: &BeautifyOptions) -> (CaptureResult, Vec<f32>) {
+        let capture =
+            pixels::capture_from_linear("hdr_macos", 20, 10, [3.0, 3.0, 3.0, 1.0].repeat(200));
+        let out = beautify(&capture, options).unwrap();
+        let linear = pixels::decode_linear(&out).unwrap();
+        (out, linear)
+    }
+
+    fn at(out: &CaptureResult, linear: &[f32], x: u32, y: u32) -> [f32; 4] {
+        let i = (y * out.frame_width + x) as usize * 4;
+        std::array::from_fn(|c| linear[i + c])
+    }
+
+    fn close(a: [f32; 4], b: [f32; 4]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 5e-3)
+    }
+
+    #[test]
+    fn capture_sits_inside_the_padding_unscaled() {
+        let options = BeautifyOptions {
+            padding: 8,
+            background: solid([0.0, 0.0, 1.0]),
+            corner_radius: 4.0,
+            shadow: None,
+        };
+        let (out, linear) = beautified(&options);
+        assert_eq!((out.frame_width, out.frame_height), (36, 26));
+        assert!(close(at(&out, &linear, 2, 2), [0.0, 0.0, 1.0, 1.0]));
+        // HDR pixels are copied as they are
+        assert!(close(at(&out, &linear, 18, 13), [3.0, 3.0, 3.0, 1.0]));
+        // The rounded corner shows the background
+        assert!(close(at(&out, &linear, 8, 8), [0.0, 0.0, 1.0, 1.0]));
+        assert!(close(at(&out, &linear, 12, 8), [3.0, 3.0, 3.0, 1.0]));
+    }
+
+    #[test]
+    fn gradient_runs_along_its_angle() {
+        let options = BeautifyOptions {
+            padding: 4,
+            background: BeautifyBackground::LinearGradient {
+                start: AnnotationColor {
+                    red: 0.0,
+                    green: 0.0,
+                    blue: 0.0,
+                    nits: SDR_WHITE_NITS,
+                    opacity: 1.0,
+                },
+                end: AnnotationColor {
+                    red: 1.0,
+                    green: 1.0,
+                    blue: 1.0,
+                    nits: SDR_WHITE_NITS,
+                    opacity: 1.0,
+                },
+                angle_degrees: 90.0,
+            },
+            corner_radius: 0.0,
+            shadow: None,
+        };
+        let (out, linear) = beautified(&options);
+        let (top, bottom) = (at(&out, &linear, 1, 0)[0], at(&out, &linear, 1, 17)[0]);
+        assert!(top < 0.05 && bottom > 0.95, "{top} {bottom}");
+        // Same value across a row at 90°
+        assert!(close(at(&out, &linear, 1, 9), at(&out, &linear, 26, 9)));
+    }
+
+    #[test]
+    fn shadow_falls_at_its_offset() {
+        let options = BeautifyOptions {
+            padding: 16,
+            background: solid([1.0; 3]),
+            corner_radius: 0.0,
+            shadow: Some(crate::api::edit_api::DropShadow {
+                offset_x: 0.0,
+                offset_y: 8.0,
+                blur_radius: 4.0,
+                color: AnnotationColor {
+                    red: 0.0,
+                    green: 0.0,
+                    blue: 0.0,
+                    nits: SDR_WHITE_NITS,
+                    opacity: 0.5,
+                },
+            }),
+        };
+        let (out, linear) = beautified(&options);
+        let below = at(&out, &linear, 26, 30)[0];
+        let above = at(&out, &linear, 26, 12)[0];
+        assert!(below < 0.6, "{below}");
+        assert!(above > 0.99, "{above}");
+    }
+
+    #[test]
+    fn oversized_canvas_is_rejected() {
+        let capture = pixels::capture_from_linear("hdr_macos", 2, 2, [0.5; 16].to_vec());
+        // Overflows u32, then fits u32 but not a pixel buffer
+        for padding in [u32::MAX, u32::MAX / 2 - 2] {
+            let options = BeautifyOptions {
+                padding,
+                background: solid([1.0; 3]),
+                corner_radius: 0.0,
+                shadow: None,
+            };
+            assert!(beautify(&capture, &options).is_err(), "{padding}");
+        }
+    }
+}
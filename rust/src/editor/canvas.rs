@@ -0,0 +1,210 @@
+//! Linear-light pixel buffer with coverage-mask compositing
+
+use crate::api::screen_shot_api::CaptureResult;
+use crate::colorist::pixels;
+
+/// How a mask's colour combines with the pixels underneath
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Source-over: paint covers the canvas
+    Over,
+    /// Multiply: paint tints the canvas like a highlighter marker
+    Multiply,
+}
+
+/// Colour and opacity to composite a mask with
+#[derive(Clone, Copy, Debug)]
+pub struct Paint {
+    /// Linear RGB, 1.0 = SDR white
+    pub color: [f32; 3],
+    pub opacity: f32,
+    pub blend: BlendMode,
+}
+
+impl Paint {
+    pub fn new(color: [f32; 3], opacity: f32) -> Self {
+        Self {
+            color,
+            opacity: opacity.clamp(0.0, 1.0),
+            blend: BlendMode::Over,
+        }
+    }
+}
+
+/// Per-pixel coverage (0.0 to 1.0) over a rectangle of the canvas
+///
+/// Shapes are unioned into one mask before compositing, so overlapping
+/// parts of a stroke are not painted twice.
+pub struct Mask {
+    pub x: i32,
+    pub y: i32,
+    pub width: usize,
+    pub height: usize,
+    pub coverage: Vec<f32>,
+}
+
+impl Mask {
+    /// Empty mask over `[min, max)` in canvas pixels, clipped to the canvas
+    pub fn new(canvas: &Canvas, min: (f32, f32), max: (f32, f32)) -> Self {
+        let x0 = min.0.floor().clamp(0.0, canvas.width as f32) as i32;
+        let y0 = min.1.floor().clamp(0.0, canvas.height as f32) as i32;
+        let x1 = max.0.ceil().clamp(0.0, canvas.width as f32) as i32;
+        let y1 = max.1.ceil().clamp(0.0, canvas.height as f32) as i32;
+        let width = (x1 - x0).max(0) as usize;
+        let height = (y1 - y0).max(0) as usize;
+        Self {
+            x: x0,
+            y: y0,
+            width,
+            height,
+            coverage: vec![0.0; width * height],
+        }
+    }
+
+    /// Union a shape given by its signed distance (negative inside), with a
+    /// one-pixel anti-aliased edge; only pixels in `[min, max)` are evaluated
+    pub fn add_sdf(&mut self, min: (f32, f32), max: (f32, f32), sdf: impl Fn(f32, f32) -> f32) {
+        let clip = |v: f32, origin: i32, len: usize| (v - origin as f32).clamp(0.0, len as f32);
+        let x0 = clip(min.0.floor(), self.x, self.width) as usize;
+        let x1 = clip(max.0.ceil(), self.x, self.width) as usize;
+        let y0 = clip(min.1.floor(), self.y, self.height) as usize;
+        let y1 = clip(max.1.ceil(), self.y, self.height) as usize;
+        for my in y0..y1 {
+            let py = (self.y + my as i32) as f32 + 0.5;
+            for mx in x0..x1 {
+                let px = (self.x + mx as i32) as f32 + 0.5;
+                let coverage = (0.5 - sdf(px, py)).clamp(0.0, 1.0);
+                let cell = &mut self.coverage[my * self.width + mx];
+                *cell = cell.max(coverage);
+            }
+        }
+    }
+
+    /// Union raw coverage at canvas pixel (x, y), ignoring pixels outside
+    pub fn add_coverage(&mut self, x: i32, y: i32, coverage: f32) {
+        let (mx, my) = (x - self.x, y - self.y);
+        if mx < 0 || my < 0 || mx as usize >= self.width || my as usize >= self.height {
+            return;
+        }
+        let cell = &mut self.coverage[my as usize * self.width + mx as usize];
+        *cell = cell.max(coverage.clamp(0.0, 1.0));
+    }
+}
+
+/// Linear RGBA image being edited
+#[derive(Clone)]
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    /// Linear RGBA, sRGB primaries, 1.0 = SDR white
+    pub pixels: Vec<f32>,
+}
+
+impl Canvas {
+    pub fn from_capture(capture: &CaptureResult) -> anyhow::Result<Self> {
+        Ok(Self {
+            width: capture.frame_width,
+            height: capture.frame_height,
+            pixels: pixels::decode_linear(capture)?,
+        })
+    }
+
+    /// Encode back into a capture in `mode`
+    pub fn into_capture(self, mode: &str) -> CaptureResult {
+        pixels::capture_from_linear(mode, self.width, self.height, self.pixels)
+    }
+
+    /// Composite `paint` through `mask`
+    pub fn composite(&mut self, mask: &Mask, paint: &Paint) {
+        for my in 0..mask.height {
+            let y = mask.y as usize + my;
+            for mx in 0..mask.width {
+                let alpha = mask.coverage[my * mask.width + mx] * paint.opacity;
+                if alpha <= 0.0 {
+                    continue;
+                }
+                let x = mask.x as usize + mx;
+                let index = (y * self.width as usize + x) * 4;
+                let pixel = &mut self.pixels[index..index + 4];
+                for (value, color) in pixel[..3].iter_mut().zip(paint.color) {
+                    let target = match paint.blend {
+                        BlendMode::Over => color,
+                        BlendMode::Multiply => *value * color,
+                    };
+                    *value += (target - *value) * alpha;
+                }
+                if paint.blend == BlendMode::Over {
+                    pixel[3] = alpha + pixel[3] * (1.0 - alpha);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey_canvas(width: u32, height: u32) -> Canvas {
+        Canvas {
+            width,
+            height,
+            pixels: [0.5, 0.5, 0.5, 1.0].repeat((width * height) as usize),
+        }
+    }
+
+    fn pixel(canvas: &Canvas, x: usize, y: usize) -> &[f32] {
+        let i = (y * canvas.width as usize + x) * 4;
+        &canvas.pixels[i..i + 4]
+    }
+
+    #[test]
+    fn mask_is_clipped_to_the_canvas() {
+        let canvas = grey_canvas(8, 6);
+        let mask = Mask::new(&canvas, (-3.5, 2.2), (20.0, 4.5));
+        assert_eq!((mask.x, mask.y, mask.width, mask.height), (0, 2, 8, 3));
+        let empty = Mask::new(&canvas, (10.0, 10.0), (12.0, 12.0));
+        assert!(empty.coverage.is_empty());
+    }
+
+    #[test]
+    fn overlapping_shapes_are_painted_once() {
+        let mut canvas = grey_canvas(8, 8);
+        let mut mask = Mask::new(&canvas, (0.0, 0.0), (8.0, 8.0));
+        // Two overlapping squares: pixels inside both still get one coat
+        let square =
+            |x0: f32| move |x: f32, y: f32| (x - x0 - 2.0).abs().max((y - 4.0).abs()) - 2.0;
+        mask.add_sdf((0.0, 0.0), (8.0, 8.0), square(1.0));
+        mask.add_sdf((0.0, 0.0), (8.0, 8.0), square(3.0));
+        canvas.composite(&mask, &Paint::new([2.0, 0.0, 0.0], 0.5));
+        assert_eq!(pixel(&canvas, 4, 4), [1.25, 0.25, 0.25, 1.0]);
+        assert_eq!(pixel(&canvas, 4, 0), [0.5, 0.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn edges_are_anti_aliased() {
+        let mut canvas = grey_canvas(4, 1);
+        let mut mask = Mask::new(&canvas, (0.0, 0.0), (4.0, 1.0));
+        // Edge through the middle of the second pixel
+        mask.add_sdf((0.0, 0.0), (4.0, 1.0), |x, _| x - 1.5);
+        assert_eq!(mask.coverage, [1.0, 0.5, 0.0, 0.0]);
+        canvas.composite(&mask, &Paint::new([1.0; 3], 1.0));
+        assert_eq!(pixel(&canvas, 1, 0), [0.75, 0.75, 0.75, 1.0]);
+    }
+
+    #[test]
+    fn multiply_tints_without_changing_alpha() {
+        let mut canvas = grey_canvas(2, 1);
+        canvas.pixels[3] = 0.0;
+        let mut mask = Mask::new(&canvas, (0.0, 0.0), (2.0, 1.0));
+        mask.add_coverage(0, 0, 1.0);
+        mask.add_coverage(5, 0, 1.0);
+        let paint = Paint {
+            blend: BlendMode::Multiply,
+            ..Paint::new([1.0, 1.0, 0.25], 1.0)
+        };
+        canvas.composite(&mask, &paint);
+        assert_eq!(pixel(&canvas, 0, 0), [0.5, 0.5, 0.125, 0.0]);
+        assert_eq!(pixel(&canvas, 1, 0), [0.5, 0.5, 0.5, 1.0]);
+    }
+}
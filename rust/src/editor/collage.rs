@@ -0,0 +1,353 @@
+//! Several captures laid out in one image
+//!
+//! Sources are decoded to linear light and rescaled to a shared SDR white
+//! before placement, so a capture from a dim SDR screen and one from a bright
+//! HDR screen sit side by side at comparable brightness.
+
+use super::canvas::Canvas;
+use super::text;
+use crate::api::edit_api::{CollageItem, CollageLayout, CollageOptions};
+use crate::api::screen_shot_api::CaptureResult;
+use crate::colorist::pixels::{self, MODE_SDR_MACOS};
+
+/// Gap between an image and its label, as a multiple of the font size
+const LABEL_GAP_SCALE: f32 = 0.5;
+
+struct Cell {
+    canvas: Canvas,
+    label: Option<(String, u32, u32)>,
+}
+
+fn load(item: &CollageItem, font_size: f32) -> anyhow::Result<Cell> {
+    let capture = match &item.region {
+        Some(rect) => {
+            let x0 = rect.x.floor().max(0.0) as u32;
+            let y0 = rect.y.floor().max(0.0) as u32;
+            let x1 = (rect.x + rect.width).ceil().max(0.0) as u32;
+            let y1 = (rect.y + rect.height).ceil().max(0.0) as u32;
+            let (width, height) = (x1.saturating_sub(x0), y1.saturating_sub(y0));
+            pixels::check_region(&item.capture, x0, y0, width, height)?;
+            item.capture.crop(x0, y0, width, height)?
+        }
+        None => item.capture.clone(),
+    };
+    let mut canvas = Canvas::from_capture(&capture)?;
+    if item.sdr_white > 0.0 && item.sdr_white != 1.0 {
+        let scale = 1.0 / item.sdr_white;
+        for pixel in canvas.pixels.chunks_exact_mut(4) {
+            for v in &mut pixel[..3] {
+                *v *= scale;
+            }
+        }
+    }
+    let label = item
+        .label
+        .as_ref()
+        .filter(|label| !label.is_empty() && font_size > 0.0)
+        .map(|label| {
+            let (width, height) = text::measure(label, font_size);
+            (label.clone(), width.ceil() as u32, height.ceil() as u32)
+        });
+    Ok(Cell { canvas, label })
+}
+
+/// HDR if any source is, so no source loses its highlights
+fn output_mode(items: &[CollageItem]) -> &str {
+    items
+        .iter()
+        .map(|item| item.capture.mode.as_str())
+        .find(|mode| *mode != MODE_SDR_MACOS)
+        .unwrap_or(MODE_SDR_MACOS)
+}
+
+/// Total of `sizes` with `spacing` between and around them, `None` if it
+/// (or any size) does not fit in a u32
+fn extent(spacing: u32, sizes: impl ExactSizeIterator<Item = Option<u32>>) -> Option<u32> {
+    let gaps = u32::try_from(sizes.len()).ok()?.checked_add(1)?;
+    sizes.fold(spacing.checked_mul(gaps), |total, size| {
+        total?.checked_add(size?)
+    })
+}
+
+pub fn compose(items: &[CollageItem], options: &CollageOptions) -> anyhow::Result<CaptureResult> {
+    if items.is_empty() {
+        anyhow::bail!("Collage needs at least one image");
+    }
+    let font_size = options.label_font_size;
+    text::check_size(font_size)?;
+    let cells = items
+        .iter()
+        .map(|item| load(item, font_size))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let columns = match options.layout {
+        CollageLayout::Row => cells.len(),
+        CollageLayout::Column => 1,
+        CollageLayout::Grid { columns } => (columns.max(1) as usize).min(cells.len()),
+    };
+    let rows = cells.len().div_ceil(columns);
+    let label_gap = (font_size.max(0.0) * LABEL_GAP_SCALE).ceil() as u32;
+
+    // Each column is as wide as its widest image or label; each row as tall
+    // as its tallest image, plus room for labels if any has one
+    let mut column_widths = vec![0u32; columns];
+    let mut image_heights = vec![0u32; rows];
+    let mut label_heights = vec![0u32; rows];
+    for (index, cell) in cells.iter().enumerate() {
+        let (row, column) = (index / columns, index % columns);
+        let label_width = cell.label.as_ref().map_or(0, |(_, width, _)| *width);
+        column_widths[column] = column_widths[column]
+            .max(cell.canvas.width)
+            .max(label_width);
+        image_heights[row] = image_heights[row].max(cell.canvas.height);
+        if let Some((_, _, height)) = &cell.label {
+            let Some(label_height) = label_gap.checked_add(*height) else {
+                anyhow::bail!("Collage label too tall at font size {font_size}");
+            };
+            label_heights[row] = label_heights[row].max(label_height);
+        }
+    }
+
+    let spacing = options.spacing;
+    let row_heights = image_heights
+        .iter()
+        .zip(&label_heights)
+        .map(|(image, label)| image.checked_add(*label));
+    let (Some(width), Some(height)) = (
+        extent(spacing, column_widths.iter().map(|w| Some(*w))),
+        extent(spacing, row_heights),
+    ) else {
+        anyhow::bail!("Collage of {columns}x{rows} images with spacing {spacing} is too large");
+    };
+    let (width_px, height_px) = (width as usize, height as usize);
+    let Some(len) = width_px
+        .checked_mul(height_px)
+        .and_then(|n| n.checked_mul(4))
+    else {
+        anyhow::bail!("Collage canvas of {width}x{height} is too large");
+    };
+    let background = options.background.linear();
+    let mut canvas = Canvas {
+        width,
+        height,
+        pixels: [
+            background[0],
+            background[1],
+            background[2],
+            options.background.opacity.clamp(0.0, 1.0),
+        ]
+        .repeat(len / 4),
+    };
+
+    let column_x: Vec<u32> = column_widths
+        .iter()
+        .scan(spacing, |x, w| {
+            let left = *x;
+            *x += w + spacing;
+            Some(left)
+        })
+        .collect();
+    let row_y: Vec<u32> = image_heights
+        .iter()
+        .zip(&label_heights)
+        .scan(spacing, |y, (image, label)| {
+            let top = *y;
+            *y += image + label + spacing;
+            Some(top)
+        })
+        .collect();
+
+    let paint = options.label_color.paint();
+    for (index, cell) in cells.iter().enumerate() {
+        let (row, column) = (index / columns, index % columns);
+        let source = &cell.canvas;
+        let left = column_x[column] + (column_widths[column] - source.width) / 2;
+        let top = row_y[row] + (image_heights[row] - source.height) / 2;
+        let source_width = source.width as usize;
+        for y in 0..source.height as usize {
+            for x in 0..source_width {
+                let s = &source.pixels[(y * source_width + x) * 4..][..4];
+                let alpha = s[3].clamp(0.0, 1.0);
+                if alpha <= 0.0 {
+                    continue;
+                }
+                let i = ((top as usize + y) * width_px + left as usize + x) * 4;
+                let d = &mut canvas.pixels[i..i + 4];
+                for c in 0..3 {
+                    d[c] += (s[c] - d[c]) * alpha;
+                }
+                d[3] = alpha + d[3] * (1.0 - alpha);
+            }
+        }
+
+        if let Some((label, label_width, _)) = &cell.label {
+            let origin = (
+                column_x[column] as f32 + (column_widths[column] - label_width) as f32 / 2.0,
+                (row_y[row] + image_heights[row] + label_gap) as f32,
+            );
+            let mask = text::text_mask(&canvas, label, origin, font_size);
+            canvas.composite(&mask, &paint);
+        }
+    }
+    Ok(canvas.into_capture(output_mode(items)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::edit_api::{AnnotationColor, EditRect};
+
+    fn flat(mode: &str, width: u32, height: u32, value: f32) -> CaptureResult {
+        let linear = [value, value, value, 1.0].repeat((width * height) as usize);
+        pixels::capture_from_linear(mode, width, height, linear)
+    }
+
+    fn item(capture: CaptureResult) -> CollageItem {
+        CollageItem {
+            capture,
+            region: None,
+            label: None,
+            sdr_white: 1.0,
+        }
+    }
+
+    fn options(layout: CollageLayout) -> CollageOptions {
+        let black = AnnotationColor {
+            red: 0.0,
+            green: 0.0,
+            blue: 0.0,
+            nits: pixels::SDR_WHITE_NITS,
+            opacity: 1.0,
+        };
+        CollageOptions {
+            layout,
+            spacing: 2,
+            background: black,
+            label_font_size: 12.0,
+            label_color: AnnotationColor {
+                red: 1.0,
+                green: 1.0,
+                blue: 1.0,
+                ..black
+            },
+        }
+    }
+
+    fn value_at(capture: &CaptureResult, x: u32, y: u32) -> f32 {
+        pixels::signal_to_linear(pixels::signal_at(capture, x, y)[0])
+    }
+
+    #[test]
+    fn row_centres_items_between_spacing() {
+        let items = [
+            item(flat("hdr_macos", 10, 4, 0.5)),
+            item(flat("hdr_macos", 6, 8, 0.25)),
+        ];
+        let out = compose(&items, &options(CollageLayout::Row)).unwrap();
+        assert_eq!((out.frame_width, out.frame_height), (22, 12));
+        assert_eq!(value_at(&out, 1, 1), 0.0);
+        // The shorter image is centred vertically in its row
+        assert_eq!(value_at(&out, 2, 3), 0.0);
+        assert!((value_at(&out, 2, 4) - 0.5).abs() < 1e-3);
+        assert!((value_at(&out, 14, 2) - 0.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn grid_wraps_into_rows() {
+        let items: Vec<_> = (0..3).map(|_| item(flat("hdr_macos", 4, 4, 0.5))).collect();
+        let out = compose(&items, &options(CollageLayout::Grid { columns: 2 })).unwrap();
+        assert_eq!((out.frame_width, out.frame_height), (14, 14));
+        let column = compose(&items, &options(CollageLayout::Column)).unwrap();
+        assert_eq!((column.frame_width, column.frame_height), (8, 20));
+    }
+
+    #[test]
+    fn sources_share_one_sdr_white() {
+        let bright = CollageItem {
+            sdr_white: 2.0,
+            ..item(flat("hdr_macos", 2, 2, 2.0))
+        };
+        let items = [item(flat(MODE_SDR_MACOS, 2, 2, 1.0)), bright];
+        let out = compose(&items, &options(CollageLayout::Row)).unwrap();
+        assert_eq!(out.mode, "hdr_macos", "HDR wins over SDR sources");
+        assert!((value_at(&out, 2, 2) - 1.0).abs() < 1e-3);
+        assert!((value_at(&out, 6, 2) - 1.0).abs() < 1e-3);
+
+        let sdr_only = [item(flat(MODE_SDR_MACOS, 2, 2, 1.0))];
+        let out = compose(&sdr_only, &options(CollageLayout::Row)).unwrap();
+        assert_eq!(out.mode, MODE_SDR_MACOS);
+    }
+
+    #[test]
+    fn regions_and_labels_change_the_cell() {
+        // Fractional edges are rounded outwards: 6x4 pixels
+        let cropped = CollageItem {
+            region: Some(EditRect {
+                x: 2.5,
+                y: 3.0,
+                width: 5.0,
+                height: 4.0,
+            }),
+            ..item(flat("hdr_macos", 20, 20, 0.5))
+        };
+        let out = compose(&[cropped], &options(CollageLayout::Row)).unwrap();
+        assert_eq!((out.frame_width, out.frame_height), (10, 8));
+
+        let labelled = CollageItem {
+            label: Some("Before".to_string()),
+            ..item(flat("hdr_macos", 4, 4, 0.5))
+        };
+        let out = compose(&[labelled], &options(CollageLayout::Row)).unwrap();
+        let (label_width, label_height) = text::measure("Before", 12.0);
+        assert_eq!(out.frame_width, label_width.ceil() as u32 + 4);
+        assert_eq!(out.frame_height, 4 + 6 + label_height.ceil() as u32 + 4);
+        let lit = (0..out.frame_height)
+            .flat_map(|y| (0..out.frame_width).map(move |x| (x, y)))
+            .filter(|&(x, y)| y >= 12 && value_at(&out, x, y) > 0.9)
+            .count();
+        assert!(lit > 10, "label pixels: {lit}");
+    }
+
+    #[test]
+    fn bad_input_is_rejected() {
+        assert!(compose(&[], &options(CollageLayout::Row)).is_err());
+        let outside = CollageItem {
+            region: Some(EditRect {
+                x: 2.0,
+                y: 0.0,
+                width: 4.0,
+                height: 1.0,
+            }),
+            ..item(flat("hdr_macos", 4, 4, 0.5))
+        };
+        assert!(compose(&[outside], &options(CollageLayout::Row)).is_err());
+        let huge_labels = CollageOptions {
+            label_font_size: 1e7,
+            ..options(CollageLayout::Row)
+        };
+        let labelled = CollageItem {
+            label: Some("Before".to_string()),
+            ..item(flat("hdr_macos", 4, 4, 0.5))
+        };
+        assert!(compose(&[labelled], &huge_labels).is_err());
+    }
+
+    #[test]
+    fn oversized_collage_is_rejected() {
+        let items = [
+            item(flat("hdr_macos", 2, 2, 0.5)),
+            item(flat("hdr_macos", 2, 2, 0.5)),
+        ];
+        let spaced = CollageOptions {
+            spacing: u32::MAX / 2,
+            ..options(CollageLayout::Row)
+        };
+        assert!(compose(&items, &spaced).is_err());
+        // Each side fits in a u32 but the pixel buffer does not fit in memory
+        let spaced = CollageOptions {
+            spacing: u32::MAX / 3,
+            ..options(CollageLayout::Row)
+        };
+        assert!(compose(&items[..1], &spaced).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+//! Shaped crops and flattening of transparency
+
+use super::canvas::{Canvas, Mask};
+use super::shape;
+use crate::api::edit_api::CropShape;
+use crate::api::screen_shot_api::CaptureResult;
+use crate::colorist::pixels;
+
+/// Crop to `(x, y, width, height)` and multiply alpha by the shape's coverage
+pub fn crop_shape(
+    capture: &CaptureResult,
+    rect: (u32, u32, u32, u32),
+    crop: &CropShape,
+) -> anyhow::Result<CaptureResult> {
+    let (x, y, width, height) = rect;
+    pixels::check_region(capture, x, y, width, height)?;
+    let mut canvas = Canvas::from_capture(&capture.crop(x, y, width, height)?)?;
+
+    // Shapes are evaluated in crop coordinates
+    let bounds = (0.0, 0.0, width as f32, height as f32);
+    let (min, max) = ((0.0, 0.0), (width as f32, height as f32));
+    let mut mask = Mask::new(&canvas, min, max);
+    match crop {
+        CropShape::RoundedRect { corner_radius } => mask.add_sdf(min, max, |px, py| {
+            shape::rounded_rect((px, py), bounds, *corner_radius)
+        }),
+        CropShape::Ellipse => mask.add_sdf(min, max, |px, py| shape::ellipse((px, py), bounds)),
+        CropShape::Polygon { points } => {
+            let vertices: Vec<_> = points
+                .iter()
+                .map(|p| (p.x - x as f32, p.y - y as f32))
+                .collect();
+            mask.add_sdf(min, max, |px, py| shape::polygon((px, py), &vertices));
+        }
+    }
+
+    for (pixel, coverage) in canvas.pixels.chunks_exact_mut(4).zip(&mask.coverage) {
+        pixel[3] *= coverage;
+    }
+    Ok(canvas.into_capture(&capture.mode))
+}
+
+/// Composite the capture over an opaque colour (linear RGB)
+pub fn flatten(capture: &CaptureResult, background: [f32; 3]) -> anyhow::Result<CaptureResult> {
+    let mut canvas = Canvas::from_capture(capture)?;
+    for pixel in canvas.pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3].clamp(0.0, 1.0);
+        for (value, back) in pixel[..3].iter_mut().zip(background) {
+            *value = *value * alpha + back * (1.0 - alpha);
+        }
+        pixel[3] = 1.0;
+    }
+    Ok(canvas.into_capture(&capture.mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::edit_api::EditPoint;
+    use crate::api::hdr_image_api::{SdrImageFormat, StripHdrOptions};
+
+    fn grey(width: u32, height: u32) -> CaptureResult {
+        pixels::capture_from_linear(
+            "hdr_macos",
+            width,
+            height,
+            [0.5, 0.5, 0.5, 1.0].repeat((width * height) as usize),
+        )
+    }
+
+    fn alpha(capture: &CaptureResult, x: u32, y: u32) -> f32 {
+        pixels::signal_at(capture, x, y)[3]
+    }
+
+    #[test]
+    fn rounded_corners_become_transparent() {
+        let shape = CropShape::RoundedRect { corner_radius: 6.0 };
+        let out = crop_shape(&grey(32, 24), (4, 4, 20, 16), &shape).unwrap();
+        assert_eq!((out.frame_width, out.frame_height), (20, 16));
+        assert_eq!(alpha(&out, 0, 0), 0.0);
+        assert_eq!(alpha(&out, 19, 15), 0.0);
+        assert_eq!(alpha(&out, 10, 0), 1.0);
+        assert_eq!(alpha(&out, 10, 8), 1.0);
+        // RGB is kept under the transparent corners
+        assert_eq!(
+            pixels::signal_at(&out, 0, 0)[0],
+            pixels::signal_at(&out, 10, 8)[0]
+        );
+    }
+
+    #[test]
+    fn ellipse_fills_the_rectangle() {
+        let out = crop_shape(&grey(40, 20), (0, 0, 40, 20), &CropShape::Ellipse).unwrap();
+        assert_eq!(alpha(&out, 20, 10), 1.0);
+        assert_eq!(alpha(&out, 0, 0), 0.0);
+        assert_eq!(alpha(&out, 1, 10), 1.0);
+        assert_eq!(alpha(&out, 20, 0), 1.0);
+    }
+
+    #[test]
+    fn polygon_points_are_in_capture_pixels() {
+        // Triangle in capture coordinates, cropped at an offset
+        let points = [(10.0, 10.0), (30.0, 10.0), (10.0, 30.0)]
+            .map(|(x, y)| EditPoint { x, y })
+            .to_vec();
+        let out = crop_shape(
+            &grey(40, 40),
+            (10, 10, 20, 20),
+            &CropShape::Polygon { points },
+        )
+        .unwrap();
+        assert_eq!(alpha(&out, 2, 2), 1.0);
+        assert_eq!(alpha(&out, 17, 17), 0.0);
+    }
+
+    #[test]
+    fn png_export_keeps_the_shape_alpha() {
+        let shape = CropShape::RoundedRect { corner_radius: 6.0 };
+        let out = crop_shape(&grey(32, 24), (4, 4, 20, 16), &shape).unwrap();
+        let options = StripHdrOptions {
+            format: SdrImageFormat::Png,
+            tone_mapper: None,
+            jpeg_quality: 90,
+        };
+        let png = out.to_sdr_image(options, None).unwrap();
+
+        let rgba = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(rgba.dimensions(), (20, 16));
+        assert_eq!(rgba.get_pixel(0, 0)[3], 0);
+        assert_eq!(rgba.get_pixel(19, 15)[3], 0);
+        assert_eq!(rgba.get_pixel(10, 8)[3], 255);
+        let edge = rgba.get_pixel(1, 1)[3];
+        assert!(edge < 255, "anti-aliased edge: {edge}");
+    }
+
+    #[test]
+    fn exr_export_keeps_the_shape_alpha_and_hdr() {
+        let capture =
+            pixels::capture_from_linear("hdr_macos", 40, 20, [4.0, 2.0, 1.0, 1.0].repeat(40 * 20));
+        let out = crop_shape(&capture, (0, 0, 40, 20), &CropShape::Ellipse).unwrap();
+        let exr = out.to_exr().unwrap();
+
+        let rgba = image::load_from_memory(&exr).unwrap().to_rgba32f();
+        assert_eq!(rgba.dimensions(), (40, 20));
+        assert_eq!(rgba.get_pixel(0, 0)[3], 0.0);
+        let centre = rgba.get_pixel(20, 10);
+        assert_eq!(centre[3], 1.0);
+        assert!((centre[0] - 4.0).abs() < 0.01, "{centre:?}");
+    }
+
+    #[test]
+    fn crop_must_lie_inside_the_frame() {
+        let shape = CropShape::Ellipse;
+        assert!(crop_shape(&grey(8, 8), (4, 4, 8, 2), &shape).is_err());
+        assert!(crop_shape(&grey(8, 8), (0, 0, 0, 2), &shape).is_err());
+    }
+
+    #[test]
+    fn flatten_blends_with_the_background() {
+        let mut linear = [0.5, 0.5, 0.5, 1.0].repeat(3);
+        linear[7] = 0.5;
+        linear[11] = 0.0;
+        let capture = pixels::capture_from_linear("hdr_macos", 3, 1, linear);
+        let flat = flatten(&capture, [2.0, 0.0, 0.0]).unwrap();
+        assert!(!pixels::has_transparency(&flat));
+        let out = pixels::decode_linear(&flat).unwrap();
+        let close = |a: &[f32], b: [f32; 4]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 2e-3);
+        assert!(close(&out[0..4], [0.5, 0.5, 0.5, 1.0]), "{out:?}");
+        assert!(close(&out[4..8], [1.25, 0.25, 0.25, 1.0]), "{out:?}");
+        assert!(close(&out[8..12], [2.0, 0.0, 0.0, 1.0]), "{out:?}");
+    }
+}
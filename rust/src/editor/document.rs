@@ -0,0 +1,321 @@
+//! Replaying and persisting edit documents
+//!
+//! Project file layout (all integers little-endian):
+//!
+//! | bytes | content                                            |
+//! |-------|----------------------------------------------------|
+//! | 8     | magic `SHOTHDRP`                                   |
+//! | 4     | format version                                     |
+//! | 4     | header length `n`                                  |
+//! | n     | JSON header: capture mode, size and operations     |
+//! | rest  | original capture's raw pixel data, untouched       |
+
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::api::edit_api::{EditDocument, EditOperation};
+use crate::api::screen_shot_api::CaptureResult;
+
+const PROJECT_MAGIC: &[u8; 8] = b"SHOTHDRP";
+const PROJECT_VERSION: u32 = 1;
+/// Undo steps kept; older states are dropped
+const MAX_HISTORY: usize = 256;
+
+#[derive(Serialize, Deserialize)]
+struct ProjectHeader {
+    mode: String,
+    width: u32,
+    height: u32,
+    operations: Vec<EditOperation>,
+}
+
+/// Make `operations` the current state, discarding anything undone
+pub fn commit(document: &mut EditDocument, operations: Vec<EditOperation>) {
+    document.history.truncate(document.cursor + 1);
+    document.history.push(operations);
+    if document.history.len() > MAX_HISTORY {
+        document.history.remove(0);
+    }
+    document.cursor = document.history.len() - 1;
+}
+
+fn apply(capture: CaptureResult, operation: &EditOperation) -> anyhow::Result<CaptureResult> {
+    match operation {
+        EditOperation::Crop {
+            x,
+            y,
+            width,
+            height,
+        } => {
+            crate::colorist::pixels::check_region(&capture, *x, *y, *width, *height)?;
+            capture.crop(*x, *y, *width, *height)
+        }
+        EditOperation::Transform { transform } => capture.transform(*transform),
+        EditOperation::Resize {
+            width,
+            height,
+            filter,
+        } => capture.resize(*width, *height, *filter),
+        EditOperation::Adjust { adjustments } => capture.adjust(*adjustments),
+        EditOperation::Annotate { annotations } => super::annotate::annotate(&capture, annotations),
+        EditOperation::Redact { redactions } => super::redact::redact(&capture, redactions),
+        EditOperation::CropShape {
+            x,
+            y,
+            width,
+            height,
+            shape,
+        } => super::crop::crop_shape(&capture, (*x, *y, *width, *height), shape),
+        EditOperation::Beautify { options } => super::beautify::beautify(&capture, options),
+    }
+}
+
+pub fn render(
+    original: &CaptureResult,
+    operations: &[EditOperation],
+) -> anyhow::Result<CaptureResult> {
+    operations
+        .iter()
+        .enumerate()
+        .try_fold(original.clone(), |capture, (index, operation)| {
+            apply(capture, operation).map_err(|e| anyhow!("Edit {index} failed: {e}"))
+        })
+}
+
+pub fn to_project_bytes(document: &EditDocument) -> anyhow::Result<Vec<u8>> {
+    // Bake everything up to the last redaction into the stored image, so the
+    // redacted pixels never reach the file
+    let operations = &document.history[document.cursor];
+    let baked = operations
+        .iter()
+        .rposition(|op| matches!(op, EditOperation::Redact { .. }))
+        .map_or(0, |index| index + 1);
+    let base = if baked > 0 {
+        render(&document.original, &operations[..baked])?
+    } else {
+        document.original.clone()
+    };
+
+    let header = serde_json::to_vec(&ProjectHeader {
+        mode: base.mode.clone(),
+        width: base.frame_width,
+        height: base.frame_height,
+        operations: operations[baked..].to_vec(),
+    })?;
+
+    let raw = &base.raw_data;
+    let mut out = Vec::with_capacity(16 + header.len() + raw.len());
+    out.extend_from_slice(PROJECT_MAGIC);
+    out.extend_from_slice(&PROJECT_VERSION.to_le_bytes());
+    out.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    out.extend_from_slice(&header);
+    out.extend_from_slice(raw);
+    Ok(out)
+}
+
+pub fn from_project_bytes(bytes: &[u8]) -> anyhow::Result<EditDocument> {
+    if bytes.len() < 16 || &bytes[..8] != PROJECT_MAGIC {
+        bail!("Not a project file");
+    }
+    let version = u32::from_le_bytes(bytes[8..12].try_into()?);
+    if version != PROJECT_VERSION {
+        bail!("Unsupported project version {version}");
+    }
+    let header_len = u32::from_le_bytes(bytes[12..16].try_into()?) as usize;
+    let header_end = 16usize
+        .checked_add(header_len)
+        .filter(|end| *end <= bytes.len())
+        .ok_or_else(|| anyhow!("Truncated project header"))?;
+    let header: ProjectHeader = serde_json::from_slice(&bytes[16..header_end])?;
+
+    let raw_data = bytes[header_end..].to_vec();
+    let expected = header.width as usize
+        * header.height as usize
+        * crate::colorist::pixels::bytes_per_pixel(&header.mode);
+    if raw_data.len() != expected {
+        bail!(
+            "Project pixel data is {} bytes, expected {expected} for {}x{} {}",
+            raw_data.len(),
+            header.width,
+            header.height,
+            header.mode
+        );
+    }
+
+    let mut document = EditDocument::new(CaptureResult {
+        mode: header.mode,
+        raw_data,
+        frame_width: header.width,
+        frame_height: header.height,
+    });
+    document.history = vec![header.operations];
+    Ok(document)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::edit_api::{EditRect, Redaction, RedactionStyle};
+    use crate::api::processing_api::{Adjustments, ImageTransform};
+    use crate::colorist::pixels;
+
+    fn gradient(width: u32, height: u32) -> CaptureResult {
+        let linear = (0..width * height)
+            .flat_map(|i| {
+                let v = i as f32 / (width * height) as f32;
+                [v, 0.5, 1.0 - v, 1.0]
+            })
+            .collect();
+        pixels::capture_from_linear("hdr_macos", width, height, linear)
+    }
+
+    fn exposure(stops: f32) -> EditOperation {
+        EditOperation::Adjust {
+            adjustments: Adjustments {
+                exposure: stops,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn undo_and_redo_move_the_cursor() {
+        let mut document = EditDocument::new(gradient(4, 4));
+        assert!(!document.can_undo() && !document.can_redo());
+        for stops in [1.0, 2.0, 3.0] {
+            document.push_operation(exposure(stops));
+        }
+        assert_eq!(document.operations().len(), 3);
+
+        assert!(document.undo() && document.undo());
+        assert_eq!(document.operations(), [exposure(1.0)]);
+        assert!(document.redo());
+        assert_eq!(document.operations().len(), 2);
+
+        // A new edit discards the undone one
+        document.push_operation(exposure(-1.0));
+        assert!(!document.can_redo() && !document.redo());
+        assert_eq!(
+            document.operations(),
+            [exposure(1.0), exposure(2.0), exposure(-1.0)]
+        );
+
+        while document.undo() {}
+        assert!(document.operations().is_empty());
+        assert_eq!(document.history.len(), 4);
+    }
+
+    #[test]
+    fn history_keeps_the_latest_states() {
+        let mut document = EditDocument::new(gradient(2, 2));
+        for i in 0..MAX_HISTORY + 10 {
+            document.push_operation(exposure(i as f32));
+        }
+        assert_eq!(document.history.len(), MAX_HISTORY);
+        assert_eq!(document.cursor, MAX_HISTORY - 1);
+        let mut undone = 0;
+        while document.undo() {
+            undone += 1;
+        }
+        assert_eq!(undone, MAX_HISTORY - 1);
+        // The oldest state left already holds the first eleven edits
+        assert_eq!(document.operations().len(), 11);
+    }
+
+    #[test]
+    fn replace_and_remove_check_the_index() {
+        let mut document = EditDocument::new(gradient(2, 2));
+        document.push_operation(exposure(1.0));
+        document.replace_operation(0, exposure(2.0)).unwrap();
+        assert_eq!(document.operations(), [exposure(2.0)]);
+        assert!(document.replace_operation(1, exposure(3.0)).is_err());
+        assert!(document.remove_operation(1).is_err());
+        document.remove_operation(0).unwrap();
+        assert!(document.operations().is_empty());
+        assert!(document.undo());
+        assert_eq!(document.operations(), [exposure(2.0)]);
+    }
+
+    #[test]
+    fn project_round_trip_keeps_original_and_operations() {
+        let mut document = EditDocument::new(gradient(6, 4));
+        document.push_operation(exposure(1.0));
+        document.push_operation(EditOperation::Transform {
+            transform: ImageTransform::Rotate90,
+        });
+        let reopened = from_project_bytes(&to_project_bytes(&document).unwrap()).unwrap();
+
+        assert_eq!(reopened.original.raw_data, document.original.raw_data);
+        assert_eq!(reopened.original.mode, document.original.mode);
+        assert_eq!(reopened.operations(), document.operations());
+        assert!(!reopened.can_undo(), "undo history is not saved");
+        let rendered = reopened.render().unwrap();
+        assert_eq!(rendered.raw_data, document.render().unwrap().raw_data);
+        assert_eq!((rendered.frame_width, rendered.frame_height), (4, 6));
+    }
+
+    #[test]
+    fn redactions_are_baked_into_the_saved_pixels() {
+        let mut document = EditDocument::new(gradient(8, 8));
+        document.push_operation(EditOperation::Redact {
+            redactions: vec![Redaction {
+                rect: EditRect {
+                    x: 0.0,
+                    y: 0.0,
+                    width: 8.0,
+                    height: 4.0,
+                },
+                style: RedactionStyle::Pixelate { block_size: 4 },
+            }],
+        });
+        document.push_operation(exposure(1.0));
+        let reopened = from_project_bytes(&to_project_bytes(&document).unwrap()).unwrap();
+
+        assert_ne!(reopened.original.raw_data, document.original.raw_data);
+        assert_eq!(reopened.operations(), [exposure(1.0)]);
+        assert_eq!(
+            reopened.render().unwrap().raw_data,
+            document.render().unwrap().raw_data
+        );
+    }
+
+    #[test]
+    fn damaged_projects_are_rejected() {
+        let bytes = to_project_bytes(&EditDocument::new(gradient(4, 4))).unwrap();
+        assert!(from_project_bytes(b"not a project").is_err());
+        assert!(from_project_bytes(&bytes[..bytes.len() - 8]).is_err());
+        assert!(from_project_bytes(&bytes[..20]).is_err());
+        let mut future = bytes.clone();
+        future[8] = 2;
+        assert!(from_project_bytes(&future).is_err());
+    }
+
+    #[test]
+    fn failing_edit_reports_its_index() {
+        let mut document = EditDocument::new(gradient(4, 4));
+        document.push_operation(exposure(1.0));
+        document.push_operation(EditOperation::Crop {
+            x: 2,
+            y: 2,
+            width: 4,
+            height: 1,
+        });
+        let Err(error) = document.render() else {
+            panic!("crop outside the frame must fail");
+        };
+        let error = error.to_string();
+        assert!(error.starts_with("Edit 1 failed"), "{error}");
+    }
+
+    #[test]
+    fn overflowing_crop_is_rejected() {
+        let mut document = EditDocument::new(gradient(4, 4));
+        document.push_operation(EditOperation::Crop {
+            x: u32::MAX,
+            y: 0,
+            width: 2,
+            height: 2,
+        });
+        assert!(document.render().is_err());
+    }
+}
@@ -0,0 +1,47 @@
+//! Image filters shared by the editing operations
+
+/// Separable Gaussian blur of `width`x`height` pixels with `channels`
+/// interleaved values each, clamping at the buffer's edges
+///
+/// A non-finite or non-positive `sigma` leaves the values unchanged.
+pub fn gaussian_blur(values: &mut [f32], width: usize, height: usize, channels: usize, sigma: f32) {
+    if !sigma.is_finite() || sigma <= 0.0 || width == 0 || height == 0 {
+        return;
+    }
+    // Taps past the longest line only repeat the edge pixel
+    let radius = ((sigma * 3.0).ceil() as usize).min(width.max(height)) as isize;
+    let weights: Vec<f32> = (-radius..=radius)
+        .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    let weights: Vec<f32> = weights.iter().map(|w| w / total).collect();
+
+    for horizontal in [true, false] {
+        let (lines, length) = if horizontal {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        let source = values.to_vec();
+        for line in 0..lines {
+            let index = |k: usize| {
+                if horizontal {
+                    (line * width + k) * channels
+                } else {
+                    (k * width + line) * channels
+                }
+            };
+            for k in 0..length {
+                let out = index(k);
+                values[out..out + channels].fill(0.0);
+                for (offset, w) in (-radius..=radius).zip(&weights) {
+                    let tap = (k as isize + offset).clamp(0, length as isize - 1) as usize;
+                    let i = index(tap);
+                    for c in 0..channels {
+                        values[out + c] += source[i + c] * w;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,16 @@
+//! Drawing and compositing on captures in linear light
+//!
+//! Everything here works on a [`canvas::Canvas`]: linear RGBA with sRGB
+//! primaries and 1.0 = SDR white, so edits keep the HDR range of the capture.
+
+pub mod annotate;
+pub mod beautify;
+pub mod canvas;
+pub mod collage;
+pub mod crop;
+pub mod document;
+pub mod filter;
+pub mod redact;
+pub mod shape;
+pub mod stitch;
+pub mod text;
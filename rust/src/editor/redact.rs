@@ -0,0 +1,277 @@
+//! Irreversible redaction of capture regions
+//!
+//! Every pixel of a region is overwritten from values computed inside the
+//! region only, so nothing from the original leaks out and nothing from the
+//! surroundings hints at what was there.
+
+use super::canvas::Canvas;
+use super::filter;
+use crate::api::edit_api::{Redaction, RedactionStyle};
+use crate::api::screen_shot_api::CaptureResult;
+
+/// Blur first averages blocks of this fraction of the radius
+const BLUR_BLOCK_FRACTION: f32 = 0.5;
+/// Smallest block averaged by either style; 1-2 px blocks keep text legible
+const MIN_BLOCK_SIZE: usize = 4;
+
+/// Region pixels, linear RGBA, copied out of the canvas
+struct Region {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    pixels: Vec<f32>,
+}
+
+impl Region {
+    fn extract(canvas: &Canvas, redaction: &Redaction) -> Option<Self> {
+        let rect = &redaction.rect;
+        let x0 = rect.x.floor().clamp(0.0, canvas.width as f32) as usize;
+        let y0 = rect.y.floor().clamp(0.0, canvas.height as f32) as usize;
+        let x1 = (rect.x + rect.width).ceil().clamp(0.0, canvas.width as f32) as usize;
+        let y1 = (rect.y + rect.height)
+            .ceil()
+            .clamp(0.0, canvas.height as f32) as usize;
+        if x1 <= x0 || y1 <= y0 {
+            return None;
+        }
+        let mut pixels = Vec::with_capacity((x1 - x0) * (y1 - y0) * 4);
+        for y in y0..y1 {
+            let start = (y * canvas.width as usize + x0) * 4;
+            pixels.extend_from_slice(&canvas.pixels[start..start + (x1 - x0) * 4]);
+        }
+        Some(Self {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+            pixels,
+        })
+    }
+
+    fn write_back(&self, canvas: &mut Canvas) {
+        let row = self.width * 4;
+        for y in 0..self.height {
+            let start = ((self.y + y) * canvas.width as usize + self.x) * 4;
+            canvas.pixels[start..start + row].copy_from_slice(&self.pixels[y * row..(y + 1) * row]);
+        }
+    }
+
+    /// Replace every `block`² block by its mean, with blocks of at least
+    /// [`MIN_BLOCK_SIZE`]
+    fn pixelate(&mut self, block: usize) {
+        let block = block.max(MIN_BLOCK_SIZE);
+        for by in (0..self.height).step_by(block) {
+            for bx in (0..self.width).step_by(block) {
+                let (y1, x1) = ((by + block).min(self.height), (bx + block).min(self.width));
+                let mut sum = [0.0f32; 4];
+                for y in by..y1 {
+                    for x in bx..x1 {
+                        let i = (y * self.width + x) * 4;
+                        for (s, v) in sum.iter_mut().zip(&self.pixels[i..i + 4]) {
+                            *s += v;
+                        }
+                    }
+                }
+                let mean = sum.map(|v| v / ((y1 - by) * (x1 - bx)) as f32);
+                for y in by..y1 {
+                    for x in bx..x1 {
+                        let i = (y * self.width + x) * 4;
+                        self.pixels[i..i + 4].copy_from_slice(&mean);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Gaussian blur that only samples the region's own pixels
+    fn blur(&mut self, sigma: f32) {
+        filter::gaussian_blur(&mut self.pixels, self.width, self.height, 4, sigma);
+    }
+
+    fn fill(&mut self, color: [f32; 3]) {
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[color[0], color[1], color[2], 1.0]);
+        }
+    }
+}
+
+pub fn apply(canvas: &mut Canvas, redaction: &Redaction) {
+    let Some(mut region) = Region::extract(canvas, redaction) else {
+        return;
+    };
+    match redaction.style {
+        RedactionStyle::GaussianBlur { radius } => {
+            let radius = radius.max(1.0);
+            // Averaging blocks throws the fine detail away for good; the blur
+            // then only hides the block edges
+            region.pixelate((radius * BLUR_BLOCK_FRACTION).ceil() as usize);
+            region.blur(radius / 2.0);
+        }
+        RedactionStyle::Pixelate { block_size } => region.pixelate(block_size as usize),
+        RedactionStyle::SolidFill { color } => region.fill(color.linear()),
+    }
+    region.write_back(canvas);
+}
+
+pub fn redact(capture: &CaptureResult, redactions: &[Redaction]) -> anyhow::Result<CaptureResult> {
+    let mut canvas = Canvas::from_capture(capture)?;
+    for redaction in redactions {
+        apply(&mut canvas, redaction);
+    }
+    Ok(canvas.into_capture(&capture.mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::edit_api::{AnnotationColor, EditRect};
+
+    const SIZE: u32 = 16;
+
+    /// 1-pixel black/white checkerboard, the worst case for weak averaging
+    fn checkerboard() -> Canvas {
+        let pixels = (0..SIZE * SIZE)
+            .flat_map(|i| {
+                let v = ((i % SIZE + i / SIZE) % 2) as f32;
+                [v, v, v, 1.0]
+            })
+            .collect();
+        Canvas {
+            width: SIZE,
+            height: SIZE,
+            pixels,
+        }
+    }
+
+    fn redaction(x: f32, y: f32, width: f32, height: f32, style: RedactionStyle) -> Redaction {
+        Redaction {
+            rect: EditRect {
+                x,
+                y,
+                width,
+                height,
+            },
+            style,
+        }
+    }
+
+    fn pixel(canvas: &Canvas, x: u32, y: u32) -> [f32; 4] {
+        let i = ((y * canvas.width + x) * 4) as usize;
+        canvas.pixels[i..i + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn tiny_pixelate_blocks_are_raised_to_the_minimum() {
+        for block_size in [0, 1, 2] {
+            let mut canvas = checkerboard();
+            apply(
+                &mut canvas,
+                &redaction(0.0, 0.0, 8.0, 8.0, RedactionStyle::Pixelate { block_size }),
+            );
+            for y in 0..8 {
+                for x in 0..8 {
+                    assert_eq!(pixel(&canvas, x, y), [0.5, 0.5, 0.5, 1.0], "{block_size}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn small_blur_radius_still_destroys_detail() {
+        let mut canvas = checkerboard();
+        apply(
+            &mut canvas,
+            &redaction(
+                4.0,
+                4.0,
+                8.0,
+                8.0,
+                RedactionStyle::GaussianBlur { radius: 0.5 },
+            ),
+        );
+        for y in 4..12 {
+            for x in 4..12 {
+                let expected = [0.5, 0.5, 0.5, 1.0];
+                for (v, e) in pixel(&canvas, x, y).iter().zip(expected) {
+                    assert!((v - e).abs() < 1e-4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pixels_outside_the_region_are_untouched_and_unused() {
+        let mut canvas = checkerboard();
+        let original = checkerboard();
+        // A bright pixel right next to the region must not bleed into it
+        let i = ((3 * SIZE + 4) * 4) as usize;
+        canvas.pixels[i..i + 3].fill(100.0);
+        let before = canvas.pixels.clone();
+        apply(
+            &mut canvas,
+            &redaction(
+                4.0,
+                4.0,
+                8.0,
+                8.0,
+                RedactionStyle::GaussianBlur { radius: 6.0 },
+            ),
+        );
+
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let i = ((y * SIZE + x) * 4) as usize;
+                let inside = (4..12).contains(&x) && (4..12).contains(&y);
+                if inside {
+                    assert!((canvas.pixels[i] - 0.5).abs() < 1e-4);
+                } else {
+                    assert_eq!(canvas.pixels[i..i + 4], before[i..i + 4]);
+                }
+            }
+        }
+        assert_eq!(pixel(&canvas, 0, 0), pixel(&original, 0, 0));
+    }
+
+    #[test]
+    fn solid_fill_is_opaque_and_clipped_to_the_canvas() {
+        let mut canvas = checkerboard();
+        let color = AnnotationColor {
+            red: 1.0,
+            green: 0.0,
+            blue: 0.0,
+            nits: 203.0,
+            opacity: 0.2,
+        };
+        apply(
+            &mut canvas,
+            &redaction(12.5, -3.0, 10.0, 5.0, RedactionStyle::SolidFill { color }),
+        );
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let expected = if x >= 12 && y < 2 {
+                    [1.0, 0.0, 0.0, 1.0]
+                } else {
+                    pixel(&checkerboard(), x, y)
+                };
+                assert_eq!(pixel(&canvas, x, y), expected, "({x},{y})");
+            }
+        }
+    }
+
+    #[test]
+    fn region_outside_the_capture_is_ignored() {
+        let mut canvas = checkerboard();
+        apply(
+            &mut canvas,
+            &redaction(
+                20.0,
+                20.0,
+                4.0,
+                4.0,
+                RedactionStyle::Pixelate { block_size: 4 },
+            ),
+        );
+        assert_eq!(canvas.pixels, checkerboard().pixels);
+    }
+}
@@ -0,0 +1,127 @@
+//! Signed distance functions for annotation and mask shapes
+//!
+//! All distances are in pixels, negative inside the shape.
+
+pub type Point = (f32, f32);
+
+fn length(x: f32, y: f32) -> f32 {
+    (x * x + y * y).sqrt()
+}
+
+/// Distance from `p` to the segment `a`-`b`
+pub fn segment_distance(p: Point, a: Point, b: Point) -> f32 {
+    let (abx, aby) = (b.0 - a.0, b.1 - a.1);
+    let (apx, apy) = (p.0 - a.0, p.1 - a.1);
+    let len2 = abx * abx + aby * aby;
+    let t = if len2 > 0.0 {
+        ((apx * abx + apy * aby) / len2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    length(apx - abx * t, apy - aby * t)
+}
+
+/// Rectangle at (x, y, width, height) with rounded corners
+pub fn rounded_rect(p: Point, rect: (f32, f32, f32, f32), radius: f32) -> f32 {
+    let (x, y, w, h) = rect;
+    let half = (w / 2.0, h / 2.0);
+    let radius = radius.clamp(0.0, half.0.min(half.1));
+    let qx = (p.0 - (x + half.0)).abs() - (half.0 - radius);
+    let qy = (p.1 - (y + half.1)).abs() - (half.1 - radius);
+    length(qx.max(0.0), qy.max(0.0)) + qx.max(qy).min(0.0) - radius
+}
+
+pub fn circle(p: Point, center: Point, radius: f32) -> f32 {
+    length(p.0 - center.0, p.1 - center.1) - radius
+}
+
+/// Axis-aligned ellipse inscribed in (x, y, width, height)
+///
+/// Uses the gradient-normalised implicit function, which is accurate near
+/// the edge where anti-aliasing needs it.
+pub fn ellipse(p: Point, rect: (f32, f32, f32, f32)) -> f32 {
+    let (x, y, w, h) = rect;
+    let (rx, ry) = ((w / 2.0).max(f32::EPSILON), (h / 2.0).max(f32::EPSILON));
+    let (dx, dy) = (p.0 - (x + rx), p.1 - (y + ry));
+    let f = (dx / rx).powi(2) + (dy / ry).powi(2) - 1.0;
+    let gradient = 2.0 * length(dx / (rx * rx), dy / (ry * ry));
+    if gradient > 0.0 {
+        f / gradient
+    } else {
+        -rx.min(ry)
+    }
+}
+
+/// Any simple polygon, convex or not (even-odd rule)
+pub fn polygon(p: Point, vertices: &[Point]) -> f32 {
+    if vertices.len() < 3 {
+        return f32::INFINITY;
+    }
+    let mut distance = f32::INFINITY;
+    let mut inside = false;
+    let mut previous = vertices[vertices.len() - 1];
+    for &vertex in vertices {
+        distance = distance.min(segment_distance(p, previous, vertex));
+        let crosses = (vertex.1 > p.1) != (previous.1 > p.1)
+            && p.0
+                < (previous.0 - vertex.0) * (p.1 - vertex.1) / (previous.1 - vertex.1) + vertex.0;
+        if crosses {
+            inside = !inside;
+        }
+        previous = vertex;
+    }
+    if inside {
+        -distance
+    } else {
+        distance
+    }
+}
+
+/// Bounding box of `points` grown by `margin` on every side
+pub fn bounds(points: &[Point], margin: f32) -> (Point, Point) {
+    let mut min = (f32::INFINITY, f32::INFINITY);
+    let mut max = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+    for p in points {
+        min = (min.0.min(p.0), min.1.min(p.1));
+        max = (max.0.max(p.0), max.1.max(p.1));
+    }
+    (
+        (min.0 - margin, min.1 - margin),
+        (max.0 + margin, max.1 + margin),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ellipse_edge_is_at_zero() {
+        let rect = (0.0, 0.0, 40.0, 20.0);
+        assert!(ellipse((20.0, 10.0), rect) < 0.0);
+        assert!(ellipse((40.0, 10.0), rect).abs() < 1e-4);
+        assert!(ellipse((20.0, 0.0), rect).abs() < 1e-4);
+        // First-order estimate, slightly short of the true 5 px this far out
+        let outside = ellipse((45.0, 10.0), rect);
+        assert!((4.0..=5.0).contains(&outside), "{outside}");
+    }
+
+    #[test]
+    fn polygon_handles_concave_shapes() {
+        // U shape open at the top
+        let u = [
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 30.0),
+            (20.0, 30.0),
+            (20.0, 0.0),
+            (30.0, 0.0),
+            (30.0, 40.0),
+            (0.0, 40.0),
+        ];
+        assert!(polygon((5.0, 20.0), &u) < 0.0);
+        assert_eq!(polygon((15.0, 10.0), &u), 5.0);
+        assert!(polygon((15.0, 35.0), &u) < 0.0);
+        assert_eq!(polygon((0.0, 0.0), &u[..2]), f32::INFINITY);
+    }
+}
@@ -0,0 +1,272 @@
+//! Stitching a scrolling capture into one tall image
+//!
+//! Frames are compared through per-row luma profiles: each row is reduced to
+//! the mean luma of a few column bins, which is cheap to compare at every
+//! candidate offset and insensitive to FP16 noise. Rows that stay put in
+//! every frame (a sticky header or footer) are found first and kept out of
+//! the offset search, then copied once. Pixels are copied from the frames'
+//! raw buffers, so the result is bit-exact.
+
+use anyhow::bail;
+
+use crate::api::screen_shot_api::CaptureResult;
+use crate::colorist::{pixels, tonemap};
+
+/// Column bins per row profile
+const PROFILE_BINS: usize = 64;
+/// Mean bin difference (signal units) below which two rows are the same
+const SAME_ROW_TOLERANCE: f32 = 1.0 / 512.0;
+/// Mean bin difference above which the best offset is rejected
+const MAX_MATCH_ERROR: f32 = 0.02;
+/// Consecutive frames must share at least this many scrolling rows
+const MIN_OVERLAP_ROWS: usize = 16;
+
+struct Profile {
+    bins: usize,
+    /// `height` rows of `bins` mean luma values
+    rows: Vec<f32>,
+}
+
+impl Profile {
+    fn new(capture: &CaptureResult) -> anyhow::Result<Self> {
+        let signal = pixels::decode_signal(capture)?;
+        let width = capture.frame_width as usize;
+        let bins = PROFILE_BINS.min(width);
+        let mut rows = vec![0.0f32; capture.frame_height as usize * bins];
+        for (row, pixels) in rows
+            .chunks_exact_mut(bins)
+            .zip(signal.chunks_exact(width * 4))
+        {
+            for (x, pixel) in pixels.chunks_exact(4).enumerate() {
+                row[x * bins / width] += tonemap::luminance([pixel[0], pixel[1], pixel[2]]);
+            }
+            for (bin, value) in row.iter_mut().enumerate() {
+                let (start, end) = (bin * width / bins, (bin + 1) * width / bins);
+                *value /= (end - start).max(1) as f32;
+            }
+        }
+        Ok(Self { bins, rows })
+    }
+
+    fn row(&self, y: usize) -> &[f32] {
+        &self.rows[y * self.bins..(y + 1) * self.bins]
+    }
+
+    fn row_difference(&self, y: usize, other: &Profile, other_y: usize) -> f32 {
+        let sum: f32 = self
+            .row(y)
+            .iter()
+            .zip(other.row(other_y))
+            .map(|(a, b)| (a - b).abs())
+            .sum();
+        sum / self.bins as f32
+    }
+}
+
+/// Rows at the top and bottom that are the same in both frames, or `None`
+/// if the frames are identical
+fn fixed_rows(a: &Profile, b: &Profile, height: usize) -> Option<(usize, usize)> {
+    let same = |y: usize| a.row_difference(y, b, y) < SAME_ROW_TOLERANCE;
+    let header = (0..height).take_while(|&y| same(y)).count();
+    if header == height {
+        return None;
+    }
+    let footer = (header..height).rev().take_while(|&y| same(y)).count();
+    Some((header, footer))
+}
+
+/// How far the content between `top` and `bottom` moved up from `a` to `b`,
+/// with the mean row difference at that offset
+fn scroll_offset(a: &Profile, b: &Profile, top: usize, bottom: usize) -> Option<(usize, f32)> {
+    let band = bottom - top;
+    let min_overlap = MIN_OVERLAP_ROWS.min(band / 2).max(1);
+    let mut best: Option<(usize, f32)> = None;
+    for offset in 1..=band.saturating_sub(min_overlap) {
+        let overlap = band - offset;
+        // Stop summing once this offset can no longer beat the best one
+        let limit = best.map_or(f32::INFINITY, |(_, error)| error * overlap as f32);
+        let mut total = 0.0;
+        for y in 0..overlap {
+            total += a.row_difference(top + offset + y, b, top + y);
+            if total >= limit {
+                break;
+            }
+        }
+        if total < limit {
+            best = Some((offset, total / overlap as f32));
+        }
+    }
+    best
+}
+
+pub fn stitch(frames: &[CaptureResult]) -> anyhow::Result<CaptureResult> {
+    let Some(first) = frames.first() else {
+        bail!("Nothing to stitch");
+    };
+    let (width, height) = (first.frame_width, first.frame_height as usize);
+    if let Some(frame) = frames.iter().find(|f| {
+        f.frame_width != width || f.frame_height as usize != height || f.mode != first.mode
+    }) {
+        bail!(
+            "Frames must match in size and mode: {}x{} {} vs {}x{} {}",
+            width,
+            height,
+            first.mode,
+            frame.frame_width,
+            frame.frame_height,
+            frame.mode
+        );
+    }
+    if width == 0 || height == 0 {
+        bail!("Cannot stitch empty {width}x{height} frames");
+    }
+
+    let profiles = frames
+        .iter()
+        .map(Profile::new)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // Frames that didn't scroll add nothing; drop them up front
+    let mut kept = vec![0];
+    let mut header = height;
+    let mut footer = height;
+    for index in 1..frames.len() {
+        let previous = *kept.last().unwrap_or(&0);
+        if let Some((top, bottom)) = fixed_rows(&profiles[previous], &profiles[index], height) {
+            header = header.min(top);
+            footer = footer.min(bottom);
+            kept.push(index);
+        }
+    }
+    if kept.len() == 1 {
+        return Ok(first.clone());
+    }
+    let bottom = height - footer;
+
+    let bpp = pixels::bytes_per_pixel(&first.mode);
+    let row_bytes = width as usize * bpp;
+    let rows = |frame: &CaptureResult, from: usize, to: usize| {
+        frame.raw_data[from * row_bytes..to * row_bytes].to_vec()
+    };
+
+    let mut raw_data = rows(first, 0, bottom);
+    for pair in kept.windows(2) {
+        let (a, b) = (&profiles[pair[0]], &profiles[pair[1]]);
+        let Some((offset, error)) = scroll_offset(a, b, header, bottom) else {
+            bail!("Frame {} has no scrolling area to match", pair[1]);
+        };
+        if error > MAX_MATCH_ERROR {
+            bail!(
+                "Frames {} and {} don't overlap (best match error {error:.3})",
+                pair[0],
+                pair[1]
+            );
+        }
+        // The last `offset` rows of the band are new
+        raw_data.extend(rows(&frames[pair[1]], bottom - offset, bottom));
+    }
+    let last = &frames[*kept.last().unwrap_or(&0)];
+    raw_data.extend(rows(last, bottom, height));
+
+    Ok(CaptureResult {
+        mode: first.mode.clone(),
+        frame_height: (raw_data.len() / row_bytes) as u32,
+        frame_width: width,
+        raw_data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 64;
+    const HEADER: u32 = 10;
+    const FOOTER: u32 = 8;
+    /// Scrolling rows visible in one frame
+    const BAND: u32 = 82;
+
+    /// Deterministic per-pixel noise, so every row has a distinct profile
+    fn noise(row: u32, x: u32) -> u8 {
+        let mut h = row.wrapping_mul(0x9E37_79B9) ^ x.wrapping_mul(0x85EB_CA6B);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x2C1B_3C6D);
+        h ^= h >> 12;
+        (h >> 24) as u8
+    }
+
+    /// One opaque BGRA row; `id` picks its content
+    fn row(id: u32) -> Vec<u8> {
+        (0..WIDTH)
+            .flat_map(|x| {
+                [
+                    noise(id, x * 3),
+                    noise(id, x * 3 + 1),
+                    noise(id, x * 3 + 2),
+                    255,
+                ]
+            })
+            .collect()
+    }
+
+    fn header_row(y: u32) -> Vec<u8> {
+        row(10_000 + y)
+    }
+
+    fn footer_row(y: u32) -> Vec<u8> {
+        row(20_000 + y)
+    }
+
+    /// Fixed header and footer around the page scrolled down by `scroll` rows
+    fn frame(scroll: u32) -> CaptureResult {
+        let raw_data = (0..HEADER)
+            .map(header_row)
+            .chain((scroll..scroll + BAND).map(row))
+            .chain((0..FOOTER).map(footer_row))
+            .flatten()
+            .collect();
+        CaptureResult {
+            mode: pixels::MODE_SDR_MACOS.to_string(),
+            raw_data,
+            frame_width: WIDTH,
+            frame_height: HEADER + BAND + FOOTER,
+        }
+    }
+
+    #[test]
+    fn stitches_scrolled_frames_with_sticky_header_and_footer() {
+        let stitched = stitch(&[frame(0), frame(40), frame(80)]).unwrap();
+
+        let expected: Vec<u8> = (0..HEADER)
+            .map(header_row)
+            .chain((0..80 + BAND).map(row))
+            .chain((0..FOOTER).map(footer_row))
+            .flatten()
+            .collect();
+        assert_eq!(stitched.frame_width, WIDTH);
+        assert_eq!(stitched.frame_height, 180);
+        assert!(stitched.raw_data == expected, "stitched pixels differ");
+    }
+
+    #[test]
+    fn rejects_scrolling_back_up() {
+        assert!(stitch(&[frame(80), frame(40)]).is_err());
+    }
+
+    #[test]
+    fn frames_that_did_not_scroll_add_nothing() {
+        let stitched = stitch(&[frame(0), frame(0)]).unwrap();
+        assert_eq!(stitched.frame_height, HEADER + BAND + FOOTER);
+        assert!(stitched.raw_data == frame(0).raw_data);
+    }
+
+    #[test]
+    fn rejects_empty_frames() {
+        let empty = CaptureResult {
+            frame_width: 0,
+            raw_data: Vec::new(),
+            ..frame(0)
+        };
+        assert!(stitch(&[empty.clone(), empty]).is_err());
+    }
+}
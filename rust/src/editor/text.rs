@@ -0,0 +1,176 @@
+//! Anti-aliased text with the bundled DejaVu Sans font
+
+use std::sync::OnceLock;
+
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use anyhow::bail;
+
+use super::canvas::{Canvas, Mask};
+
+/// DejaVu Sans, Bitstream Vera license (see `assets/fonts/LICENSE-DejaVu.txt`)
+const FONT_DATA: &[u8] = include_bytes!("../../assets/fonts/DejaVuSans.ttf");
+
+/// Largest font size accepted, in pixels: a glyph is rasterised over its
+/// whole box, even the part that falls off the canvas
+pub const MAX_FONT_SIZE: f32 = 2048.0;
+
+/// Parsed once; every annotation and collage label reuses it
+static FONT: OnceLock<FontRef<'static>> = OnceLock::new();
+
+fn font() -> &'static FontRef<'static> {
+    FONT.get_or_init(|| {
+        FontRef::try_from_slice(FONT_DATA).expect("bundled font is a valid TrueType file")
+    })
+}
+
+/// Fail unless `size` is finite and at most [`MAX_FONT_SIZE`]
+pub fn check_size(size: f32) -> anyhow::Result<()> {
+    if !size.is_finite() || size > MAX_FONT_SIZE {
+        bail!("Font size {size} must be finite and at most {MAX_FONT_SIZE}");
+    }
+    Ok(())
+}
+
+/// Width and height in pixels of `text` set at `size` pixels
+pub fn measure(text: &str, size: f32) -> (f32, f32) {
+    let font = font();
+    let scaled = font.as_scaled(PxScale::from(size));
+    let mut width = 0.0f32;
+    let mut lines = 0;
+    for line in text.split('\n') {
+        let mut caret = 0.0;
+        let mut previous = None;
+        for c in line.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += scaled.kern(previous, id);
+            }
+            caret += scaled.h_advance(id);
+            previous = Some(id);
+        }
+        width = width.max(caret);
+        lines += 1;
+    }
+    let line_height = scaled.height() + scaled.line_gap();
+    (width, scaled.height() + (lines - 1) as f32 * line_height)
+}
+
+/// Distance from the top of a line to its baseline, in pixels
+pub fn ascent(size: f32) -> f32 {
+    font().as_scaled(PxScale::from(size)).ascent()
+}
+
+/// Union the glyph coverage of `text` into a mask covering the canvas,
+/// with `origin` at the top-left of the first line
+pub fn text_mask(canvas: &Canvas, text: &str, origin: (f32, f32), size: f32) -> Mask {
+    let font = font();
+    let scaled = font.as_scaled(PxScale::from(size));
+    let line_height = scaled.height() + scaled.line_gap();
+    let (width, height) = measure(text, size);
+    // Glyph outlines may overhang their advance box slightly
+    let margin = size * 0.25;
+    let mut mask = Mask::new(
+        canvas,
+        (origin.0 - margin, origin.1 - margin),
+        (origin.0 + width + margin, origin.1 + height + margin),
+    );
+
+    for (row, line) in text.split('\n').enumerate() {
+        let baseline = origin.1 + scaled.ascent() + row as f32 * line_height;
+        let mut caret = origin.0;
+        let mut previous = None;
+        for c in line.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += scaled.kern(previous, id);
+            }
+            let glyph = id.with_scale_and_position(size, point(caret, baseline));
+            caret += scaled.h_advance(id);
+            previous = Some(id);
+            let Some(outline) = font.outline_glyph(glyph) else {
+                continue; // whitespace
+            };
+            let bounds = outline.px_bounds();
+            let outside = bounds.max.x <= mask.x as f32
+                || bounds.max.y <= mask.y as f32
+                || bounds.min.x >= (mask.x + mask.width as i32) as f32
+                || bounds.min.y >= (mask.y + mask.height as i32) as f32;
+            if outside {
+                continue;
+            }
+            outline.draw(|gx, gy, coverage| {
+                mask.add_coverage(
+                    bounds.min.x as i32 + gx as i32,
+                    bounds.min.y as i32 + gy as i32,
+                    coverage,
+                );
+            });
+        }
+    }
+    mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blank_canvas(width: u32, height: u32) -> Canvas {
+        Canvas {
+            width,
+            height,
+            pixels: vec![0.0; (width * height * 4) as usize],
+        }
+    }
+
+    #[test]
+    fn measure_grows_with_text_and_lines() {
+        let (one, line) = measure("W", 20.0);
+        let (three, _) = measure("WWW", 20.0);
+        let (_, two_lines) = measure("W\nW", 20.0);
+        assert!(one > 0.0 && line > 0.0);
+        assert!((three - 3.0 * one).abs() < 1e-3);
+        assert!(two_lines > 1.5 * line);
+        assert_eq!(measure("", 20.0).0, 0.0);
+        assert!(ascent(20.0) > 0.0 && ascent(20.0) < line);
+    }
+
+    #[test]
+    fn glyphs_stay_inside_their_measured_box() {
+        let canvas = blank_canvas(100, 40);
+        let origin = (10.0, 5.0);
+        let (width, height) = measure("Hello", 24.0);
+        let mask = text_mask(&canvas, "Hello", origin, 24.0);
+
+        let mut inked = 0;
+        for my in 0..mask.height {
+            for mx in 0..mask.width {
+                if mask.coverage[my * mask.width + mx] > 0.0 {
+                    inked += 1;
+                    let x = (mask.x + mx as i32) as f32;
+                    let y = (mask.y + my as i32) as f32;
+                    assert!(x >= origin.0 - 1.0 && x <= origin.0 + width + 1.0);
+                    assert!(y >= origin.1 - 1.0 && y <= origin.1 + height + 1.0);
+                }
+            }
+        }
+        assert!(inked > 50, "{inked} pixels inked");
+    }
+
+    #[test]
+    fn whitespace_and_offscreen_text_draw_nothing() {
+        let canvas = blank_canvas(40, 20);
+        let spaces = text_mask(&canvas, "   ", (0.0, 0.0), 16.0);
+        assert!(spaces.coverage.iter().all(|c| *c == 0.0));
+        let offscreen = text_mask(&canvas, "Hi", (100.0, 100.0), 16.0);
+        assert!(offscreen.coverage.is_empty());
+    }
+
+    #[test]
+    fn oversized_or_non_finite_sizes_are_rejected() {
+        check_size(12.0).unwrap();
+        check_size(MAX_FONT_SIZE).unwrap();
+        assert!(check_size(1e7).is_err());
+        assert!(check_size(f32::NAN).is_err());
+        assert!(check_size(f32::INFINITY).is_err());
+    }
+}
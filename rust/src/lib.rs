@@ -1,4 +1,5 @@
 pub mod api;
 pub mod colorist;
+pub mod editor;
 mod frb_generated;
 pub mod screenshot;